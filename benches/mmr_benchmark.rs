@@ -4,7 +4,12 @@ extern crate criterion;
 use criterion::{BenchmarkId, Criterion};
 
 use bytes::Bytes;
-use ckb_merkle_mountain_range::{util::MemStore, Error, MMRStoreReadOps, Merge, Result, MMR};
+use ckb_merkle_mountain_range::{
+    helper::get_peaks,
+    leaf_index_to_mmr_size,
+    util::{ArrayStore, MemStore},
+    Error, MMRStoreReadOps, Merge, MerkleProof, Result, MMR,
+};
 use rand::{seq::SliceRandom, thread_rng};
 use std::convert::TryFrom;
 
@@ -52,6 +57,36 @@ fn prepare_mmr(count: u32) -> (u64, MemStore<NumberHash>, Vec<u64>) {
     (mmr_size, store, positions)
 }
 
+struct MergeArrayHash;
+
+impl Merge for MergeArrayHash {
+    type Item = [u8; 32];
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> Result<Self::Item> {
+        let mut hasher = new_blake2b();
+        let mut hash = [0u8; 32];
+        hasher.update(lhs);
+        hasher.update(rhs);
+        hasher.finalize(&mut hash);
+        Ok(hash)
+    }
+}
+
+// same as `prepare_mmr` with fixed size items, nothing is allocated per node
+fn prepare_array_mmr(count: u32) -> (u64, ArrayStore<32>) {
+    let store = ArrayStore::default();
+    let mut mmr = MMR::<_, MergeArrayHash, _>::new(0, &store);
+    for i in 0u32..count {
+        let mut hasher = new_blake2b();
+        let mut hash = [0u8; 32];
+        hasher.update(&i.to_le_bytes());
+        hasher.finalize(&mut hash);
+        mmr.push(hash).unwrap();
+    }
+    let mmr_size = mmr.mmr_size();
+    mmr.commit().expect("write to store");
+    (mmr_size, store)
+}
+
 fn bench(c: &mut Criterion) {
     {
         let mut group = c.benchmark_group("MMR insertion");
@@ -63,6 +98,16 @@ fn bench(c: &mut Criterion) {
         }
     }
 
+    {
+        let mut group = c.benchmark_group("MMR insertion of arrays");
+        let inputs = [10_000, 100_000, 100_0000];
+        for input in inputs.iter() {
+            group.bench_with_input(BenchmarkId::new("times", input), &input, |b, &&size| {
+                b.iter(|| prepare_array_mmr(size));
+            });
+        }
+    }
+
     c.bench_function("MMR gen proof", |b| {
         let (mmr_size, store, positions) = prepare_mmr(100_0000);
         let mmr = MMR::<_, MergeNumberHash, _>::new(mmr_size, &store);
@@ -90,6 +135,22 @@ fn bench(c: &mut Criterion) {
                 .unwrap();
         });
     });
+
+    c.bench_function("MMR bagging peaks of 2^40 leaves", |b| {
+        // 2^40 - 1 leaves form 40 peaks, the last peak is a single leaf
+        let mmr_size = leaf_index_to_mmr_size((1 << 40) - 2);
+        let peaks_count = get_peaks(mmr_size).len() as u32;
+        let last_peak = NumberHash::try_from(peaks_count).unwrap();
+        let lhs_peaks = (0..peaks_count - 1)
+            .map(|i| NumberHash::try_from(i).unwrap())
+            .collect();
+        let proof = MerkleProof::<_, MergeNumberHash>::new(mmr_size, lhs_peaks);
+        b.iter(|| {
+            proof
+                .calculate_root(vec![(mmr_size - 1, last_peak.clone())])
+                .unwrap()
+        });
+    });
 }
 
 criterion_group!(
@@ -0,0 +1,143 @@
+//! A MMR over asynchronous stores, enabled by the `async` feature.
+//!
+//! `AsyncMMR` stages pushed elements in a `MMRBatch` like `MMR`, and awaits the store for the
+//! elements which aren't staged.
+
+use crate::helper::proof_skeleton;
+use crate::helper::{get_peak_map, get_peaks};
+use crate::mmr::{bagging_peaks_hashes, check_capacity};
+use crate::mmr_store::{MMRBatch, MMRStoreReadOpsAsync, MMRStoreWriteOpsAsync};
+use crate::vec;
+use crate::vec::Vec;
+use crate::{Error, ItemRole, Merge, MerkleProof, Result};
+use core::marker::PhantomData;
+
+pub struct AsyncMMR<T, M, S> {
+    mmr_size: u64,
+    batch: MMRBatch<T, S>,
+    max_leaves: Option<u64>,
+    merge: PhantomData<M>,
+}
+
+impl<T, M, S> AsyncMMR<T, M, S> {
+    pub fn new(mmr_size: u64, store: S) -> Self {
+        AsyncMMR {
+            mmr_size,
+            batch: MMRBatch::new(store),
+            max_leaves: None,
+            merge: PhantomData,
+        }
+    }
+
+    /// Same as `MMR::with_capacity`
+    pub fn with_capacity(mmr_size: u64, store: S, max_leaves: u64) -> Self {
+        AsyncMMR {
+            max_leaves: Some(max_leaves),
+            ..AsyncMMR::new(mmr_size, store)
+        }
+    }
+
+    pub fn mmr_size(&self) -> u64 {
+        self.mmr_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mmr_size == 0
+    }
+
+    pub fn batch(&self) -> &MMRBatch<T, S> {
+        &self.batch
+    }
+
+    pub fn store(&self) -> &S {
+        self.batch.store()
+    }
+}
+
+impl<T: Clone + PartialEq, M: Merge<Item = T>, S: MMRStoreReadOpsAsync<T>> AsyncMMR<T, M, S> {
+    async fn get_elem(&self, pos: u64) -> Result<T> {
+        match self.batch.get_staged_elem(pos) {
+            Some(elem) => Ok(elem),
+            None => self
+                .batch
+                .store()
+                .get_elem(pos)
+                .await?
+                .ok_or(Error::InconsistentStore),
+        }
+    }
+
+    async fn get_peak_elems(&self, peaks: impl Iterator<Item = u64>) -> Result<Vec<T>> {
+        let mut elems = Vec::new();
+        for peak_pos in peaks {
+            elems.push(self.get_elem(peak_pos).await?);
+        }
+        Ok(elems)
+    }
+
+    /// Same as `MMR::push`, the left siblings of the carry chain are awaited one by one.
+    pub async fn push(&mut self, elem: T) -> Result<u64> {
+        check_capacity(self.max_leaves, self.mmr_size, 1)?;
+        let elem_pos = self.mmr_size;
+        let peak_map = get_peak_map(self.mmr_size);
+        let mut elems = vec![elem];
+        let mut pos = self.mmr_size;
+        let mut peak = 1;
+        while (peak_map & peak) != 0 {
+            peak <<= 1;
+            pos += 1;
+            // the left sibling is a peak before this push
+            let left_elem = self.get_elem(pos - peak).await?;
+            let right_elem = elems.last().expect("checked");
+            let parent_elem =
+                M::merge_with_height(&left_elem, right_elem, peak.trailing_zeros() as u8)?;
+            elems.push(parent_elem);
+        }
+        self.mmr_size += elems.len() as u64;
+        self.batch.append(elem_pos, elems);
+        Ok(elem_pos)
+    }
+
+    /// Same as `MMR::get_root`
+    pub async fn get_root(&self) -> Result<T> {
+        if self.mmr_size == 0 {
+            return Err(Error::GetRootOnEmpty);
+        }
+        let peaks = self
+            .get_peak_elems(get_peaks(self.mmr_size).into_iter())
+            .await?;
+        bagging_peaks_hashes::<_, M>(&peaks, &mut { usize::MAX })
+    }
+
+    /// Same as `MMR::gen_proof`
+    pub async fn gen_proof(&self, pos_list: Vec<u64>) -> Result<MerkleProof<T, M>> {
+        let skeleton = proof_skeleton(pos_list, self.mmr_size)?;
+        let mut proof = Vec::with_capacity(skeleton.len());
+        for &(pos, role) in &skeleton {
+            let item = if role == ItemRole::BaggedRhs {
+                let rhs_peaks = get_peaks(self.mmr_size)
+                    .into_iter()
+                    .filter(|peak_pos| *peak_pos >= pos);
+                let rhs_peaks = self.get_peak_elems(rhs_peaks).await?;
+                bagging_peaks_hashes::<_, M>(&rhs_peaks, &mut { usize::MAX })?
+            } else {
+                self.get_elem(pos).await?
+            };
+            proof.push(item);
+        }
+        let positions = skeleton.iter().map(|(pos, _)| *pos).collect();
+        Ok(MerkleProof::with_positions(self.mmr_size, proof, positions))
+    }
+}
+
+impl<T, M, S: MMRStoreWriteOpsAsync<T>> AsyncMMR<T, M, S> {
+    /// Same as `MMR::commit`, returns the count of persisted positions.
+    pub async fn commit(&mut self) -> Result<u64> {
+        let mut count = 0;
+        for (pos, elems) in self.batch.take_staged() {
+            count += elems.len() as u64;
+            self.batch.store_mut().append(pos, elems).await?;
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,365 @@
+//! A portable binary format of `MerkleProof`, and `LengthPrefixed` to pack variable-length items,
+//! also exported from `util`.
+//!
+//! Version 1 layout, integers are little endian:
+//!
+//! | field      | size                |
+//! |------------|---------------------|
+//! | magic      | 2, `PROOF_MAGIC`    |
+//! | version    | 1, `1`              |
+//! | mmr_size   | 8                   |
+//! | item_count | 4                   |
+//! | items      | `item_count` items of a 4 bytes length followed by the item bytes |
+//!
+//! Version 2 inserts the 4 bytes `Merge::ID` after the version, so a proof can't be decoded
+//! with a different `Merge`. The rest is the same as version 1.
+//!
+//! `pack_merkle_proof` writes a bare layout without header for verifiers which know what to
+//! expect: the 8 bytes mmr_size followed by each item packed by `LengthPrefixed` with
+//! `LengthWidth::U32`, until the end of the data.
+//!
+//! `MerkleProof::to_hex` and `MerkleProof::from_hex` convert the items to lowercase hex
+//! strings for logs and test vectors, they aren't a wire format.
+
+use crate::{string::String, vec::Vec, Error, Merge, MerkleProof, Result};
+use core::convert::TryFrom;
+use core::marker::PhantomData;
+
+/// Magic bytes of an encoded `MerkleProof`
+pub const PROOF_MAGIC: [u8; 2] = *b"MP";
+
+const VERSION_1: u8 = 1;
+const VERSION_2: u8 = 2;
+
+impl<T: Clone + PartialEq + AsRef<[u8]>, M: Merge<Item = T>> MerkleProof<T, M> {
+    /// Encode the proof in the version 1 format, see the module document.
+    pub fn encode_v1(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(15 + self.encoded_items_size());
+        data.extend_from_slice(&PROOF_MAGIC);
+        data.push(VERSION_1);
+        self.encode_body(&mut data)?;
+        Ok(data)
+    }
+
+    /// Encode the proof in the version 2 format, which records `M::ID`.
+    pub fn encode_v2(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(19 + self.encoded_items_size());
+        data.extend_from_slice(&PROOF_MAGIC);
+        data.push(VERSION_2);
+        data.extend_from_slice(&M::ID.to_le_bytes());
+        self.encode_body(&mut data)?;
+        Ok(data)
+    }
+
+    /// Returns the bytes of each proof item.
+    pub fn proof_bytes(&self) -> Vec<Vec<u8>> {
+        self.proof_items()
+            .iter()
+            .map(|item| item.as_ref().to_vec())
+            .collect()
+    }
+
+    /// Returns each proof item as a lowercase hex string, see `from_hex`.
+    pub fn to_hex(&self) -> Vec<String> {
+        self.proof_items()
+            .iter()
+            .map(|item| hex_encode(item.as_ref()))
+            .collect()
+    }
+
+    fn encoded_items_size(&self) -> usize {
+        self.proof_items()
+            .iter()
+            .map(|item| 4 + item.as_ref().len())
+            .sum()
+    }
+
+    // mmr_size, item_count and items
+    fn encode_body(&self, data: &mut Vec<u8>) -> Result<()> {
+        let items = self.proof_items();
+        let item_count = u32::try_from(items.len()).map_err(|_| Error::InvalidEncoding)?;
+        data.extend_from_slice(&self.mmr_size().to_le_bytes());
+        data.extend_from_slice(&item_count.to_le_bytes());
+        for item in items {
+            let item = item.as_ref();
+            let len = u32::try_from(item.len()).map_err(|_| Error::InvalidEncoding)?;
+            data.extend_from_slice(&len.to_le_bytes());
+            data.extend_from_slice(item);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone + PartialEq + From<Vec<u8>>, M: Merge<Item = T>> MerkleProof<T, M> {
+    /// Rebuild a proof of `mmr_size` from the hex strings of its items, upper or lower case,
+    /// returns `Error::InvalidEncoding` if a string isn't hex.
+    pub fn from_hex<S: AsRef<str>>(mmr_size: u64, items: &[S]) -> Result<Self> {
+        let items = items
+            .iter()
+            .map(|item| hex_decode(item.as_ref()).map(T::from))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(MerkleProof::new(mmr_size, items))
+    }
+
+    /// Decode a proof in the version 1 format, see the module document.
+    pub fn decode_v1(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader(data);
+        if reader.take(2)? != PROOF_MAGIC || reader.take(1)? != [VERSION_1] {
+            return Err(Error::InvalidEncoding);
+        }
+        Self::decode_body(reader)
+    }
+
+    /// Decode a proof in the version 2 format,
+    /// returns `Error::MergeMismatch` if it is encoded with another `Merge::ID`.
+    pub fn decode_v2(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader(data);
+        if reader.take(2)? != PROOF_MAGIC || reader.take(1)? != [VERSION_2] {
+            return Err(Error::InvalidEncoding);
+        }
+        if reader.read_u32()? != M::ID {
+            return Err(Error::MergeMismatch);
+        }
+        Self::decode_body(reader)
+    }
+
+    /// Decode a proof in any supported version
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        match data.get(2) {
+            Some(&VERSION_1) => Self::decode_v1(data),
+            Some(&VERSION_2) => Self::decode_v2(data),
+            Some(_) => Err(Error::InvalidEncoding),
+            None => Err(Error::UnpackEof),
+        }
+    }
+
+    fn decode_body(mut reader: Reader) -> Result<Self> {
+        let mmr_size = reader.read_u64()?;
+        let item_count = reader.read_u32()? as usize;
+        // every item takes at least 4 bytes, reject a count the buffer can't hold
+        if item_count > reader.0.len() / 4 {
+            return Err(Error::UnpackEof);
+        }
+        let mut items = Vec::with_capacity(item_count);
+        for _ in 0..item_count {
+            let len = reader.read_u32()? as usize;
+            items.push(T::from(reader.take(len)?.to_vec()));
+        }
+        if !reader.0.is_empty() {
+            return Err(Error::InvalidEncoding);
+        }
+        Ok(MerkleProof::new(mmr_size, items))
+    }
+}
+
+/// Pack the proof in the bare layout, see the module document.
+pub fn pack_merkle_proof<T, M>(proof: &MerkleProof<T, M>) -> Result<Vec<u8>>
+where
+    T: Clone + PartialEq + AsRef<[u8]>,
+    M: Merge<Item = T>,
+{
+    let packer = LengthPrefixed::new(LengthWidth::U32);
+    let items = proof.proof_items();
+    let items_size: usize = items.iter().map(|item| 4 + item.as_ref().len()).sum();
+    let mut data = Vec::with_capacity(8 + items_size);
+    data.extend_from_slice(&proof.mmr_size().to_le_bytes());
+    for item in items {
+        packer.pack(item, &mut data)?;
+    }
+    Ok(data)
+}
+
+/// Returns the length of `pack_merkle_proof(proof)` without packing the proof, fails the same
+/// if an item length doesn't fit the length field.
+pub fn packed_merkle_proof_size<T, M>(proof: &MerkleProof<T, M>) -> Result<usize>
+where
+    T: Clone + PartialEq + AsRef<[u8]>,
+    M: Merge<Item = T>,
+{
+    let packer = LengthPrefixed::new(LengthWidth::U32);
+    proof
+        .proof_items()
+        .iter()
+        .try_fold(8, |size, item| Ok(size + packer.packed_size(item)?))
+}
+
+/// Unpack a proof packed by `pack_merkle_proof`.
+pub fn unpack_merkle_proof<T, M>(data: &[u8]) -> Result<MerkleProof<T, M>>
+where
+    T: Clone + PartialEq + From<Vec<u8>>,
+    M: Merge<Item = T>,
+{
+    let packer = LengthPrefixed::new(LengthWidth::U32);
+    let mut reader = Reader(data);
+    let mmr_size = reader.read_u64()?;
+    let mut data = reader.0;
+    let mut items = Vec::new();
+    while !data.is_empty() {
+        items.push(packer.unpack(&mut data)?);
+    }
+    Ok(MerkleProof::new(mmr_size, items))
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data {
+        hex.push(DIGITS[(byte >> 4) as usize] as char);
+        hex.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+    hex
+}
+
+fn hex_decode(hex: &str) -> Result<Vec<u8>> {
+    fn digit(c: u8) -> Result<u8> {
+        match c {
+            b'0'..=b'9' => Ok(c - b'0'),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            b'A'..=b'F' => Ok(c - b'A' + 10),
+            _ => Err(Error::InvalidEncoding),
+        }
+    }
+    let pairs = hex.as_bytes().chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return Err(Error::InvalidEncoding);
+    }
+    pairs
+        .map(|pair| Ok(digit(pair[0])? << 4 | digit(pair[1])?))
+        .collect()
+}
+
+/// Packs items into bytes which can be stored or sent back to back, and unpacks them.
+pub trait Packable {
+    type Item;
+
+    /// Append the packed item to `buf`.
+    fn pack(&self, item: &Self::Item, buf: &mut Vec<u8>) -> Result<()>;
+
+    /// Returns the count of bytes `pack` appends for the item.
+    fn packed_size(&self, item: &Self::Item) -> Result<usize>;
+
+    /// Unpack an item from the front of `data` and advance `data` past it, returns
+    /// `Error::UnpackEof` if `data` is truncated.
+    fn unpack(&self, data: &mut &[u8]) -> Result<Self::Item>;
+}
+
+/// Width of the length field written by `LengthPrefixed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthWidth {
+    U16,
+    U32,
+}
+
+/// Packs variable-length items as a little endian length followed by the item bytes.
+///
+/// Useful for `Merge` implementations whose output length varies, so items can be stored or
+/// sent back to back.
+#[derive(Debug, Clone, Copy)]
+pub struct LengthPrefixed<T> {
+    width: LengthWidth,
+    item: PhantomData<T>,
+}
+
+impl<T> LengthPrefixed<T> {
+    pub fn new(width: LengthWidth) -> Self {
+        LengthPrefixed {
+            width,
+            item: PhantomData,
+        }
+    }
+
+    pub fn width(&self) -> LengthWidth {
+        self.width
+    }
+}
+
+impl<T: AsRef<[u8]>> LengthPrefixed<T> {
+    /// Append the packed item to `buf`, fails if the item length doesn't fit the length field.
+    pub fn pack(&self, item: &T, buf: &mut Vec<u8>) -> Result<()> {
+        let item = item.as_ref();
+        match self.width {
+            LengthWidth::U16 => {
+                let len = u16::try_from(item.len()).map_err(|_| Error::InvalidEncoding)?;
+                buf.extend_from_slice(&len.to_le_bytes());
+            }
+            LengthWidth::U32 => {
+                let len = u32::try_from(item.len()).map_err(|_| Error::InvalidEncoding)?;
+                buf.extend_from_slice(&len.to_le_bytes());
+            }
+        }
+        buf.extend_from_slice(item);
+        Ok(())
+    }
+
+    /// Returns the count of bytes `pack` appends for the item.
+    pub fn packed_size(&self, item: &T) -> Result<usize> {
+        let len = item.as_ref().len();
+        let width = match self.width {
+            LengthWidth::U16 => u16::try_from(len).map(|_| 2),
+            LengthWidth::U32 => u32::try_from(len).map(|_| 4),
+        };
+        Ok(width.map_err(|_| Error::InvalidEncoding)? + len)
+    }
+}
+
+impl<T: From<Vec<u8>>> LengthPrefixed<T> {
+    /// Unpack an item from the front of `data` and advance `data` past it.
+    pub fn unpack(&self, data: &mut &[u8]) -> Result<T> {
+        let mut reader = Reader(data);
+        let len = match self.width {
+            LengthWidth::U16 => reader.read_u16()? as usize,
+            LengthWidth::U32 => reader.read_u32()? as usize,
+        };
+        let item = T::from(reader.take(len)?.to_vec());
+        *data = reader.0;
+        Ok(item)
+    }
+}
+
+/// The inherent methods only need either bound, e.g. to pack items which can't be unpacked.
+impl<T: AsRef<[u8]> + From<Vec<u8>>> Packable for LengthPrefixed<T> {
+    type Item = T;
+
+    fn pack(&self, item: &T, buf: &mut Vec<u8>) -> Result<()> {
+        LengthPrefixed::pack(self, item, buf)
+    }
+
+    fn packed_size(&self, item: &T) -> Result<usize> {
+        LengthPrefixed::packed_size(self, item)
+    }
+
+    fn unpack(&self, data: &mut &[u8]) -> Result<T> {
+        LengthPrefixed::unpack(self, data)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::UnpackEof);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        buf.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+}
@@ -5,15 +5,52 @@ pub enum Error {
     GetRootOnEmpty,
     InconsistentStore,
     StoreError(crate::string::String),
-    /// proof items is not enough to build a tree
+    /// proof items is not enough to build a tree, for failures not covered by the variants below
     CorruptedProof,
-    /// tried to verify proof of a non-leaf
+    /// The proof has fewer items than the positions require
+    ProofItemsExhausted,
+    /// The proof has more items than the positions require
+    ProofItemsLeftover,
+    /// A peak is calculated while nodes under it are still pending
+    UnexpectedPeakRoot,
+    /// A position is beyond the peaks of the mmr_size
+    PositionOutOfRange,
+    /// tried to prove a non-leaf where it isn't supported, or along with its descendants
     NodeProofsNotSupported,
     /// The leaves is an empty list, or beyond the mmr range
     GenProofForInvalidLeaves,
 
     /// The two nodes couldn't merge into one.
     MergeError(crate::string::String),
+    /// The proof requires more merge operations than the given budget
+    ProofBudgetExceeded,
+    /// The new leaf is not the next leaf of the MMR
+    InvalidNewLeaf,
+    /// The data ends before the encoded content
+    UnpackEof,
+    /// The data is not a valid encoding
+    InvalidEncoding,
+    /// The appended segment doesn't start at the end of the store, contains the segment position
+    NonContiguousAppend(u64),
+    /// The MMR already has the maximum count of leaves
+    CapacityExceeded,
+    /// The proof is encoded with a different `Merge`
+    MergeMismatch,
+    /// The mmr size is not the size of any MMR, or not usable for the operation
+    InvalidMMRSize,
+    /// The position is not a leaf of the MMR, contains the position
+    NotLeaf(u64),
+    /// The leaf index is out of the range the operation accepts, e.g. the leaf exists when
+    /// proving it doesn't
+    LeafIndexOutOfRange,
+    /// The leaves are not in ascending position order, contains the first position out of order
+    UnsortedLeaves(u64),
+    /// Two leaves are given at one position with different values, contains the position
+    ConflictingLeafValues(u64),
+    /// The k-index given with a leaf isn't the one of its position, contains the position
+    KIndexMismatch(u64),
+    /// The nodes don't fit the fixed stack of a verifier without allocation
+    CorruptedStack,
 }
 
 impl core::fmt::Display for Error {
@@ -24,9 +61,27 @@ impl core::fmt::Display for Error {
             InconsistentStore => write!(f, "Inconsistent store")?,
             StoreError(msg) => write!(f, "Store error {}", msg)?,
             CorruptedProof => write!(f, "Corrupted proof")?,
+            ProofItemsExhausted => write!(f, "Proof items exhausted")?,
+            ProofItemsLeftover => write!(f, "Proof items left over")?,
+            UnexpectedPeakRoot => write!(f, "Unexpected peak root")?,
+            PositionOutOfRange => write!(f, "Position out of range")?,
             NodeProofsNotSupported => write!(f, "Tried to verify membership of a non-leaf")?,
             GenProofForInvalidLeaves => write!(f, "Generate proof ofr invalid leaves")?,
             MergeError(msg) => write!(f, "Merge error {}", msg)?,
+            ProofBudgetExceeded => write!(f, "Proof budget exceeded")?,
+            InvalidNewLeaf => write!(f, "Invalid new leaf")?,
+            UnpackEof => write!(f, "Unpack eof")?,
+            InvalidEncoding => write!(f, "Invalid encoding")?,
+            NonContiguousAppend(pos) => write!(f, "Non-contiguous append at {}", pos)?,
+            CapacityExceeded => write!(f, "Capacity exceeded")?,
+            MergeMismatch => write!(f, "Merge mismatch")?,
+            InvalidMMRSize => write!(f, "Invalid MMR size")?,
+            NotLeaf(pos) => write!(f, "Position {} is not a leaf", pos)?,
+            LeafIndexOutOfRange => write!(f, "Leaf index out of range")?,
+            UnsortedLeaves(pos) => write!(f, "Leaf at {} is out of order", pos)?,
+            ConflictingLeafValues(pos) => write!(f, "Conflicting leaf values at {}", pos)?,
+            KIndexMismatch(pos) => write!(f, "K-index mismatch at {}", pos)?,
+            CorruptedStack => write!(f, "Corrupted stack")?,
         }
         Ok(())
     }
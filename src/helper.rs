@@ -1,5 +1,6 @@
-use crate::vec;
+use crate::collections::VecDeque;
 use crate::vec::Vec;
+use crate::{Error, Result};
 
 pub fn leaf_index_to_pos(index: u64) -> u64 {
     // mmr_size - H - 1, H is the height(intervals) of last peak
@@ -16,6 +17,46 @@ pub fn leaf_index_to_mmr_size(index: u64) -> u64 {
     2 * leaves_count - peak_count
 }
 
+/// Returns whether `mmr_size` is the size of a mmr with some count of leaves, including 0.
+/// for example, 4 is the size of a mmr with 3 leaves, but no mmr has size 5 or 6,
+/// which `get_peak_map` and `get_peaks` treat as the last valid size.
+pub fn is_valid_mmr_size(mmr_size: u64) -> bool {
+    let leaf_count = mmr_size_to_leaf_count(mmr_size);
+    leaf_count == 0 || leaf_index_to_mmr_size(leaf_count - 1) == mmr_size
+}
+
+/// Returns the positions which exist in a mmr of `new_mmr_size` but not in one of
+/// `old_mmr_size` in ascending order, the new leaves and the parents merged by pushing them.
+/// Nodes are stored in post-order, so they are always `old_mmr_size..new_mmr_size`.
+/// for example, growing from 7 to 8 adds 7, and from 8 to 15 adds 8 to 14.
+/// Returns an empty list if either size is invalid or `new_mmr_size` isn't greater.
+pub fn added_positions(old_mmr_size: u64, new_mmr_size: u64) -> Vec<u64> {
+    if !is_valid_mmr_size(old_mmr_size)
+        || !is_valid_mmr_size(new_mmr_size)
+        || old_mmr_size >= new_mmr_size
+    {
+        return Vec::new();
+    }
+    (old_mmr_size..new_mmr_size).collect()
+}
+
+/// Returns the index of the leaf at `pos`, the inverse of `leaf_index_to_pos`.
+/// Returns `None` if `pos` is an internal node.
+pub fn pos_to_leaf_index(pos: u64) -> Option<u64> {
+    if pos_height_in_tree(pos) > 0 {
+        return None;
+    }
+    // the nodes before a leaf form a mmr of size `pos`
+    Some(mmr_size_to_leaf_count(pos))
+}
+
+/// Returns the count of leaves in a mmr of `mmr_size`, the inverse of `leaf_index_to_mmr_size`.
+/// for example, a mmr with size 19 has 11 leaves, see `get_peak_map`.
+pub fn mmr_size_to_leaf_count(mmr_size: u64) -> u64 {
+    // a peak of height h has 2^h leaves, the same bit of the peak map
+    get_peak_map(mmr_size)
+}
+
 pub fn pos_height_in_tree(mut pos: u64) -> u8 {
     if pos == 0 {
         return 0;
@@ -39,6 +80,43 @@ pub fn sibling_offset(height: u8) -> u64 {
     (2 << height) - 1
 }
 
+// whether the node at `pos` and `height` is the right child of its parent
+fn is_right_sibling(pos: u64, height: u8) -> bool {
+    pos_height_in_tree(pos + 1) > height
+}
+
+/// Returns the position of the sibling of the node at `pos`, which is at `height`.
+/// for example, the sibling of 3 is 4 and the sibling of 13 is 6, see `get_peaks`.
+/// The result is meaningless for peaks, which have no sibling.
+pub fn sibling_pos(pos: u64, height: u8) -> u64 {
+    if is_right_sibling(pos, height) {
+        pos - sibling_offset(height)
+    } else {
+        pos + sibling_offset(height)
+    }
+}
+
+/// Returns the position of the parent of the node at `pos`, which is at `height`.
+/// for example, the parent of 3 is 5 and the parent of 13 is 14, see `get_peaks`.
+pub fn parent_pos(pos: u64, height: u8) -> u64 {
+    if is_right_sibling(pos, height) {
+        pos + 1
+    } else {
+        pos + parent_offset(height)
+    }
+}
+
+/// Returns the positions of the `(left, right)` children of the node at `parent`, or `None`
+/// if it's a leaf. for example, the children of 6 are 2 and 5, see `get_peaks`.
+pub fn children_pos(parent: u64) -> Option<(u64, u64)> {
+    let height = pos_height_in_tree(parent);
+    if height == 0 {
+        return None;
+    }
+    // the right child is right before its parent, the left one is a whole subtree before it
+    Some((parent - (1 << height), parent - 1))
+}
+
 /// Returns the height of the peaks in the mmr, presented by a bitmap.
 /// for example, for a mmr with 11 leaves, the mmr_size is 19, it will return 0b1011.
 /// 0b1011 indicates that the left peaks are at height 0, 1 and 3.
@@ -91,22 +169,301 @@ pub fn get_peak_map(mmr_size: u64) -> u64 {
 ///  / \   /  \
 /// 0   1 3   4
 pub fn get_peaks(mmr_size: u64) -> Vec<u64> {
-    if mmr_size == 0 {
-        return vec![];
+    let mut peaks = Vec::with_capacity(64 - mmr_size.leading_zeros() as usize);
+    peaks.extend(iter_peaks(mmr_size));
+    peaks
+}
+
+/// Same as `get_peaks` without allocating, yields the peaks from left to right.
+pub(crate) fn iter_peaks(mmr_size: u64) -> impl Iterator<Item = u64> {
+    peaks(mmr_size).map(|(pos, _height)| pos)
+}
+
+/// Returns an iterator of the `(position, height)` of the peaks in the mmr from left to right.
+/// for example, for a mmr with size 19, it yields (14, 3), (17, 1) and (18, 0), see `get_peaks`.
+pub fn peaks(mmr_size: u64) -> Peaks {
+    Peaks {
+        pos: mmr_size,
+        // a empty mmr has no peaks, shifting by 64 bits overflows
+        peak_size: u64::MAX.checked_shr(mmr_size.leading_zeros()).unwrap_or(0),
+        peaks_sum: 0,
     }
+}
 
-    let leading_zeros = mmr_size.leading_zeros();
-    let mut pos = mmr_size;
-    let mut peak_size = u64::MAX >> leading_zeros;
-    let mut peaks = Vec::with_capacity(64 - leading_zeros as usize);
-    let mut peaks_sum = 0;
-    while peak_size > 0 {
-        if pos >= peak_size {
-            pos -= peak_size;
-            peaks.push(peaks_sum + peak_size - 1);
-            peaks_sum += peak_size;
+/// Iterator of the peaks in a mmr, see `peaks`.
+#[derive(Debug, Clone)]
+pub struct Peaks {
+    pos: u64,
+    peak_size: u64,
+    peaks_sum: u64,
+}
+
+impl Iterator for Peaks {
+    type Item = (u64, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.peak_size > 0 {
+            let size = self.peak_size;
+            self.peak_size >>= 1;
+            if self.pos >= size {
+                self.pos -= size;
+                let peak = self.peaks_sum + size - 1;
+                self.peaks_sum += size;
+                // a tree with height h has 2^(h+1) - 1 nodes
+                return Some((peak, size.count_ones() as u8 - 1));
+            }
         }
-        peak_size >>= 1;
+        None
     }
-    peaks
+}
+
+/// Returns the positions of the ancestors of `pos`, from its parent up to the peak which
+/// contains it. Returns an empty list if `pos` is a peak or beyond the mmr.
+/// for example, the ancestors of 0 in a mmr with size 19 are [2, 6, 14].
+///           14
+///        /       \
+///      6          13
+///    /   \       /   \
+///   2     5     9     12     17
+///  / \   /  \  / \   /  \   /  \
+/// 0   1 3   4 7   8 10  11 15  16 18
+pub fn ancestors(mut pos: u64, mmr_size: u64) -> Vec<u64> {
+    let peaks = get_peaks(mmr_size);
+    let mut ancestors = Vec::new();
+    if pos >= mmr_size {
+        return ancestors;
+    }
+    let mut height = pos_height_in_tree(pos);
+    while !peaks.contains(&pos) {
+        pos = parent_pos(pos, height);
+        height += 1;
+        if pos >= mmr_size {
+            break;
+        }
+        ancestors.push(pos);
+    }
+    ancestors
+}
+
+/// Returns the position of the peak whose subtree contains `pos`, or `None` if `pos` is beyond
+/// the mmr. for example, in a mmr with size 19, the peak of 3 is 14 and the peak of 17 is 17.
+///           14
+///        /       \
+///      6          13
+///    /   \       /   \
+///   2     5     9     12     17
+///  / \   /  \  / \   /  \   /  \
+/// 0   1 3   4 7   8 10  11 15  16 18
+pub fn containing_peak(pos: u64, mmr_size: u64) -> Option<u64> {
+    if pos >= mmr_size {
+        return None;
+    }
+    get_peaks(mmr_size).into_iter().find(|peak_pos| {
+        // a tree with height h has 2^(h+1) - 1 nodes and ends at the peak
+        let tree_size = (1u64 << (pos_height_in_tree(*peak_pos) + 1)) - 1;
+        peak_pos + 1 - tree_size <= pos && pos <= *peak_pos
+    })
+}
+
+/// Returns whether proofs of the two position lists consist of the same items, by comparing
+/// the position and role of each item rather than generating the proofs.
+/// Returns false if either list can't be proven in the mmr.
+pub fn proofs_equivalent(mmr_size: u64, a: &[u64], b: &[u64]) -> bool {
+    match (
+        proof_skeleton(a.to_vec(), mmr_size),
+        proof_skeleton(b.to_vec(), mmr_size),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Returns the count of items `MMR::gen_proof` emits for the positions, without touching the
+/// store, for example to reject proofs which would be too large before generating them.
+/// Returns 0 if the positions can't be proven in the mmr.
+pub fn expected_proof_len(mmr_size: u64, pos_list: &[u64]) -> usize {
+    proof_skeleton(pos_list.to_vec(), mmr_size).map_or(0, |skeleton| skeleton.len())
+}
+
+/// Returns the `(position, k-index)` of each position, the k-index is the index of the node
+/// from left to right among the nodes at the same height under its peak, which is how layered
+/// verifiers locate siblings, see `util::multi_proof`. Positions beyond the mmr are skipped.
+/// for example, in a mmr with size 19, the k-index of 7 is 4 and the k-index of 16 is 1.
+///           14
+///        /       \
+///      6          13
+///    /   \       /   \
+///   2     5     9     12     17
+///  / \   /  \  / \   /  \   /  \
+/// 0   1 3   4 7   8 10  11 15  16 18
+pub fn mmr_position_to_k_index(positions: Vec<u64>, mmr_size: u64) -> Vec<(u64, usize)> {
+    positions
+        .into_iter()
+        .filter_map(|pos| {
+            let (_, k_index, _) = position_to_peak_and_k_index(pos, mmr_size)?;
+            Some((pos, k_index))
+        })
+        .collect()
+}
+
+/// Returns the peak position, the k-index and the peak height of `pos`, or `None` if `pos` is
+/// beyond the mmr, see `mmr_position_to_k_index`.
+/// for example, in a mmr with size 19, 7 is under the peak 14 at height 3 and its k-index is 4.
+pub fn position_to_peak_and_k_index(pos: u64, mmr_size: u64) -> Option<(u64, usize, u8)> {
+    let (peak_pos, peak_height) = peaks(mmr_size).find(|(peak_pos, _)| *peak_pos >= pos)?;
+    // descend from the peak, each level doubles the index
+    let mut node = peak_pos;
+    let mut k_index = 0;
+    while node != pos {
+        let (left, right) = children_pos(node)?;
+        k_index *= 2;
+        if pos <= left {
+            node = left;
+        } else {
+            node = right;
+            k_index += 1;
+        }
+    }
+    Some((peak_pos, k_index, peak_height))
+}
+
+/// How a proof item is used when calculating the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemRole {
+    /// left sibling of a node on the path, merged as `merge(item, node)`
+    LeftSibling,
+    /// right sibling of a node on the path, merged as `merge(node, item)`
+    RightSibling,
+    /// root of a peak which contains no proven leaves
+    Peak,
+    /// all right hand side peaks bagged into one item
+    BaggedRhs,
+}
+
+/// Calculates the position and role of each proof item `gen_proof` emits for the positions,
+/// without touching the store. Both the generator and the proof inspection are built on it.
+pub(crate) fn proof_skeleton(
+    mut pos_list: Vec<u64>,
+    mmr_size: u64,
+) -> Result<Vec<(u64, ItemRole)>> {
+    if pos_list.is_empty() {
+        return Err(Error::GenProofForInvalidLeaves);
+    }
+    if mmr_size == 1 && pos_list == [0] {
+        return Ok(Vec::new());
+    }
+    // ensure positions are sorted and unique
+    pos_list.sort_unstable();
+    pos_list.dedup();
+    let peaks = get_peaks(mmr_size);
+    // reject positions beyond the mmr up front, before any store read, positions of an
+    // invalid mmr_size after the last valid peak are not under any peak either
+    match (pos_list.last(), peaks.last()) {
+        (Some(pos), Some(last_peak)) if pos <= last_peak => {}
+        _ => return Err(Error::GenProofForInvalidLeaves),
+    }
+    let mut items = Vec::new();
+    let mut bagging_track = 0;
+    for peak_pos in peaks {
+        let pos_list: Vec<_> = take_while_vec(&mut pos_list, |&pos| pos <= peak_pos);
+        if pos_list.is_empty() {
+            bagging_track += 1;
+            items.push((peak_pos, ItemRole::Peak));
+        } else {
+            bagging_track = 0;
+            peak_skeleton(&mut items, pos_list, peak_pos)?;
+        }
+    }
+    debug_assert!(pos_list.is_empty(), "checked");
+    if bagging_track > 1 {
+        let rhs_peaks = items.split_off(items.len() - bagging_track);
+        items.push((rhs_peaks[0].0, ItemRole::BaggedRhs));
+    }
+    Ok(items)
+}
+
+/// calculate proof items for a peak
+/// the pos_list must be sorted, otherwise the behaviour is undefined
+///
+/// 1. find a lower tree in peak that can generate a complete merkle proof for position
+/// 2. find that tree by compare positions
+/// 3. generate proof for each positions
+pub(crate) fn peak_skeleton(
+    items: &mut Vec<(u64, ItemRole)>,
+    pos_list: Vec<u64>,
+    peak_pos: u64,
+) -> Result<()> {
+    // do nothing if position itself is the peak
+    if pos_list.len() == 1 && pos_list == [peak_pos] {
+        return Ok(());
+    }
+
+    // (position, height), ordered by height then position like `calculate_peak_root`
+    let mut queue: VecDeque<_> = pos_list
+        .into_iter()
+        .map(|pos| (pos, pos_height_in_tree(pos)))
+        .collect();
+    queue
+        .make_contiguous()
+        .sort_by_key(|(pos, height)| (*height, *pos));
+
+    // Generate sub-tree merkle proof for positions
+    while let Some((pos, height)) = queue.pop_front() {
+        debug_assert!(pos <= peak_pos);
+        if pos == peak_pos {
+            if queue.is_empty() {
+                break;
+            } else {
+                return Err(Error::NodeProofsNotSupported);
+            }
+        }
+
+        // calculate sibling
+        let (sib_pos, parent_pos, role) = {
+            let next_height = pos_height_in_tree(pos + 1);
+            let sibling_offset = sibling_offset(height);
+            if next_height > height {
+                // implies pos is right sibling
+                (pos - sibling_offset, pos + 1, ItemRole::LeftSibling)
+            } else {
+                // pos is left sibling
+                (
+                    pos + sibling_offset,
+                    pos + parent_offset(height),
+                    ItemRole::RightSibling,
+                )
+            }
+        };
+
+        if Some(&sib_pos) == queue.front().map(|(pos, _)| pos) {
+            // drop sibling
+            queue.pop_front();
+        } else {
+            items.push((sib_pos, role));
+        }
+        if parent_pos < peak_pos {
+            // save pos to tree buf
+            let parent_height = height + 1;
+            let index = queue
+                .partition_point(|(pos, height)| (*height, *pos) < (parent_height, parent_pos));
+            if queue.get(index).map(|(pos, _)| *pos) == Some(parent_pos) {
+                // a node is requested along with its descendants
+                return Err(Error::NodeProofsNotSupported);
+            }
+            queue.insert(index, (parent_pos, parent_height));
+        } else if queue.front().is_some() {
+            // the peak is requested along with its descendants
+            return Err(Error::NodeProofsNotSupported);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn take_while_vec<T, P: Fn(&T) -> bool>(v: &mut Vec<T>, p: P) -> Vec<T> {
+    for i in 0..v.len() {
+        if !p(&v[i]) {
+            return v.drain(..i).collect();
+        }
+    }
+    core::mem::take(v)
 }
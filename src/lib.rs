@@ -1,29 +1,48 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(feature = "async")]
+pub mod async_mmr;
+pub mod encoding;
 mod error;
 pub mod helper;
 mod merge;
 mod mmr;
 mod mmr_store;
+pub mod no_alloc;
+pub mod prelude;
 #[cfg(test)]
 mod tests;
+#[cfg(feature = "typed-positions")]
+pub mod typed;
 pub mod util;
 
 pub use error::{Error, Result};
-pub use helper::{leaf_index_to_mmr_size, leaf_index_to_pos};
-pub use merge::Merge;
-pub use mmr::{MerkleProof, MMR};
+pub use helper::{
+    is_valid_mmr_size, leaf_index_to_mmr_size, leaf_index_to_pos, mmr_size_to_leaf_count,
+    pos_to_leaf_index, ItemRole,
+};
+pub use merge::{check_peaks_distinct, Merge};
+pub use mmr::{
+    ConsistencyProof, MerkleProof, MmrStats, NonMembershipProof, ProofFormat, ProofItemInfo,
+    PushInfo, VerifyReport, MMR,
+};
 pub use mmr_store::{MMRStoreReadOps, MMRStoreWriteOps};
+#[cfg(feature = "async")]
+pub use mmr_store::{MMRStoreReadOpsAsync, MMRStoreWriteOpsAsync};
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "std")] {
         use std::borrow;
+        #[cfg(feature = "testing")]
+        use std::boxed;
         use std::collections;
         use std::vec;
         use std::string;
     } else {
         extern crate alloc;
         use alloc::borrow;
+        #[cfg(feature = "testing")]
+        use alloc::boxed;
         use alloc::collections;
         use alloc::vec;
         use alloc::string;
@@ -3,9 +3,48 @@ use crate::Result;
 pub trait Merge {
     type Item;
 
+    /// Identifies the hash function in encoded proofs, see `MerkleProof::encode_v2`.
+    /// Implementations should pick a distinct value, `0` is left for unidentified ones.
+    const ID: u32 = 0;
+
+    /// Whether peaks are bagged differently from `merge`. Debug builds of `MMR` panic if it's
+    /// set but `merge_peaks` isn't overridden, which would silently produce roots other
+    /// deployments don't accept, see `check_peaks_distinct`. The check runs once the second
+    /// leaf is pushed, by any push method or `MMR::build`, and in `MMR::from_peaks`. Proofs
+    /// are verified without the check.
+    const PEAKS_DISTINCT: bool = false;
+
     fn merge(left: &Self::Item, right: &Self::Item) -> Result<Self::Item>;
 
+    /// Merge two nodes into their parent at `height`, leaves are at height 0, so it's at least 1.
+    /// Every node of the trees is merged by it, implementations can override it to tag the
+    /// height and tell leaves from internal nodes. Peaks are still bagged by `merge_peaks`.
+    fn merge_with_height(left: &Self::Item, right: &Self::Item, _height: u8) -> Result<Self::Item> {
+        Self::merge(left, right)
+    }
+
     fn merge_peaks(peak1: &Self::Item, peak2: &Self::Item) -> Result<Self::Item> {
         Self::merge(peak1, peak2)
     }
+
+    /// Bag the next peak to the left into the `accumulated` peaks on its right. Peaks are
+    /// always folded from right to left, the default hashes `merge_peaks(accumulated, next)`,
+    /// implementations can override it to interoperate with other orders, e.g. hashing
+    /// `merge_peaks(next, accumulated)`. `ProofFormat::Grin` doesn't use it.
+    fn bag(accumulated: &Self::Item, next: &Self::Item) -> Result<Self::Item> {
+        Self::merge_peaks(accumulated, next)
+    }
+}
+
+/// Returns whether `M::PEAKS_DISTINCT` holds for the sample elements `a` and `b`: if it's set,
+/// `M::bag` of them in either order must differ from `M::merge`. It's always `true` if the
+/// const isn't set, overriding `bag` only to change the order of peaks is allowed.
+pub fn check_peaks_distinct<M: Merge>(a: &M::Item, b: &M::Item) -> Result<bool>
+where
+    M::Item: PartialEq,
+{
+    if !M::PEAKS_DISTINCT {
+        return Ok(true);
+    }
+    Ok(M::bag(a, b)? != M::merge(a, b)? || M::bag(b, a)? != M::merge(b, a)?)
 }
@@ -5,22 +5,29 @@
 //! https://github.com/mimblewimble/grin/blob/0ff6763ee64e5a14e70ddd4642b99789a1648a32/core/src/core/pmmr.rs#L606
 
 use crate::borrow::Cow;
-use crate::collections::VecDeque;
+use crate::collections::{BTreeMap, VecDeque};
 use crate::helper::{
-    get_peak_map, get_peaks, leaf_index_to_mmr_size, leaf_index_to_pos, parent_offset,
-    pos_height_in_tree, sibling_offset,
+    children_pos, get_peak_map, get_peaks, is_valid_mmr_size, iter_peaks, leaf_index_to_mmr_size,
+    leaf_index_to_pos, mmr_size_to_leaf_count, parent_offset, parent_pos, peak_skeleton, peaks,
+    pos_height_in_tree, proof_skeleton, sibling_offset, sibling_pos, take_while_vec, ItemRole,
+    Peaks,
 };
 use crate::mmr_store::{MMRBatch, MMRStoreReadOps, MMRStoreWriteOps};
 use crate::vec;
 use crate::vec::Vec;
 use crate::{Error, Merge, Result};
-use core::fmt::Debug;
+use core::cell::RefCell;
+use core::fmt::{self, Debug};
 use core::marker::PhantomData;
 
 #[allow(clippy::upper_case_acronyms)]
 pub struct MMR<T, M, S> {
     mmr_size: u64,
     batch: MMRBatch<T, S>,
+    max_leaves: Option<u64>,
+    // the last root calculated by `get_root` and the mmr_size it belongs to, cleared by every
+    // change of the nodes, it makes the MMR `!Sync`
+    root_cache: RefCell<Option<(u64, T)>>,
     merge: PhantomData<M>,
 }
 
@@ -29,10 +36,30 @@ impl<T, M, S> MMR<T, M, S> {
         MMR {
             mmr_size,
             batch: MMRBatch::new(store),
+            max_leaves: None,
+            root_cache: RefCell::new(None),
             merge: PhantomData,
         }
     }
 
+    /// Same as `new`, but returns `Error::InvalidMMRSize` if `mmr_size` isn't the size of any
+    /// MMR, see `is_valid_mmr_size`.
+    pub fn new_checked(mmr_size: u64, store: S) -> Result<Self> {
+        if !is_valid_mmr_size(mmr_size) {
+            return Err(Error::InvalidMMRSize);
+        }
+        Ok(MMR::new(mmr_size, store))
+    }
+
+    /// Same as `new`, but pushing returns `Error::CapacityExceeded` once the MMR has
+    /// `max_leaves` leaves.
+    pub fn with_capacity(mmr_size: u64, store: S, max_leaves: u64) -> Self {
+        MMR {
+            max_leaves: Some(max_leaves),
+            ..MMR::new(mmr_size, store)
+        }
+    }
+
     pub fn mmr_size(&self) -> u64 {
         self.mmr_size
     }
@@ -41,6 +68,19 @@ impl<T, M, S> MMR<T, M, S> {
         self.mmr_size == 0
     }
 
+    fn clear_root_cache(&mut self) {
+        self.root_cache.get_mut().take();
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        mmr_size_to_leaf_count(self.mmr_size)
+    }
+
+    /// Returns the `(position, height)` of the peaks from left to right.
+    pub fn peaks(&self) -> Peaks {
+        peaks(self.mmr_size)
+    }
+
     pub fn batch(&self) -> &MMRBatch<T, S> {
         &self.batch
     }
@@ -48,6 +88,59 @@ impl<T, M, S> MMR<T, M, S> {
     pub fn store(&self) -> &S {
         self.batch.store()
     }
+
+    /// Returns the height of the node at `pos`, or `None` if `pos` is beyond the MMR.
+    pub fn pos_height(&self, pos: u64) -> Option<u8> {
+        if pos < self.mmr_size {
+            Some(pos_height_in_tree(pos))
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether `pos` is a leaf of the MMR, positions beyond the MMR are not leaves.
+    pub fn pos_is_leaf(&self, pos: u64) -> bool {
+        self.pos_height(pos) == Some(0)
+    }
+
+    /// Structural metrics of the MMR, calculated from the mmr_size without reading the store.
+    pub fn stats(&self) -> MmrStats {
+        let peaks = get_peaks(self.mmr_size);
+        let leaf_count = mmr_size_to_leaf_count(self.mmr_size);
+        MmrStats {
+            mmr_size: self.mmr_size,
+            leaf_count,
+            peak_count: peaks.len() as u64,
+            // the left most peak is the highest
+            height: peaks.first().map_or(0, |pos| pos_height_in_tree(*pos)),
+            node_count: self.mmr_size - leaf_count,
+        }
+    }
+}
+
+/// Result of `MMR::push_info`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushInfo {
+    pub leaf_pos: u64,
+    pub new_mmr_size: u64,
+    /// count of parents merged by the push, which is also the height of the new peak
+    pub merges_performed: u32,
+    /// position of the right most peak after the push
+    pub new_peak_pos: u64,
+}
+
+/// Structural metrics of an MMR, see `MMR::stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MmrStats {
+    /// count of all nodes
+    pub mmr_size: u64,
+    pub leaf_count: u64,
+    pub peak_count: u64,
+    /// height of the highest peak, leaves are at height 0
+    pub height: u8,
+    /// count of internal nodes
+    pub node_count: u64,
 }
 
 impl<T: Clone + PartialEq, M: Merge<Item = T>, S: MMRStoreReadOps<T>> MMR<T, M, S> {
@@ -61,11 +154,117 @@ impl<T: Clone + PartialEq, M: Merge<Item = T>, S: MMRStoreReadOps<T>> MMR<T, M,
         Ok(Cow::Owned(elem))
     }
 
-    // push a element and return position
-    pub fn push(&mut self, elem: T) -> Result<u64> {
-        let mut elems = vec![elem];
-        let elem_pos = self.mmr_size;
+    /// Returns a lazy iterator of the `(position, element)` of every node in ascending position
+    /// order, staged nodes included, see `import_nodes`. It yields `Error::InconsistentStore`
+    /// for a missing node, for example one deleted by `compact_below`.
+    pub fn nodes(&self) -> impl Iterator<Item = Result<(u64, T)>> + '_ {
+        (0..self.mmr_size).map(move |pos| {
+            let elem = self.batch.get_elem(pos)?.ok_or(Error::InconsistentStore)?;
+            Ok((pos, elem))
+        })
+    }
+
+    /// Returns a lazy iterator of the `(position, leaf)` of every leaf in index order, staged
+    /// leaves included. It yields `Error::InconsistentStore` for a missing leaf.
+    pub fn leaves(&self) -> impl Iterator<Item = Result<(u64, T)>> + '_ {
+        (0..self.leaf_count()).map(move |index| {
+            let pos = leaf_index_to_pos(index);
+            let leaf = self.batch.get_elem(pos)?.ok_or(Error::InconsistentStore)?;
+            Ok((pos, leaf))
+        })
+    }
+
+    /// Open the MMR of all committed elements of the store, the mmr_size is taken from
+    /// `MMRStoreReadOps::len`. Returns `Error::InvalidMMRSize` if it isn't a valid mmr size,
+    /// e.g. a push is partially committed.
+    pub fn resume(store: S) -> Result<Self> {
+        let mmr_size = store.len()?;
+        if !is_valid_mmr_size(mmr_size) {
+            return Err(Error::InvalidMMRSize);
+        }
+        Ok(MMR::new(mmr_size, store))
+    }
+
+    /// Resume a MMR from the `(position, element)` of its peaks, as returned by
+    /// `get_peak_values`, without the other nodes. The peaks are staged in the batch, so the
+    /// root and pushes work at once, but proofs of the earlier leaves can't be generated.
+    /// Returns `Error::InvalidMMRSize` if the positions are not the peaks of `mmr_size`.
+    ///
+    /// The peaks are not contiguous, committing them fails on stores which require
+    /// contiguous appends, such as `MemStore` with the `debug-checks` feature.
+    pub fn from_peaks(mmr_size: u64, peaks: Vec<(u64, T)>, store: S) -> Result<Self> {
+        if !is_valid_mmr_size(mmr_size)
+            || !peaks.iter().map(|(pos, _)| *pos).eq(iter_peaks(mmr_size))
+        {
+            return Err(Error::InvalidMMRSize);
+        }
+        if let [(_, a), (_, b), ..] = &peaks[..] {
+            debug_check_peaks_distinct::<M>(a, b);
+        }
+        let mut mmr = MMR::new(mmr_size, store);
+        for (pos, peak) in peaks {
+            mmr.batch.append(pos, vec![peak]);
+        }
+        Ok(mmr)
+    }
+
+    /// Restore a MMR from every `(position, element)` in ascending position order, as returned
+    /// by `nodes`, without recomputing the hashes. The nodes are staged in the batch as one
+    /// segment, the caller decides when to call `commit`. Returns the first error of the nodes,
+    /// or `Error::InvalidMMRSize` if the positions are not exactly `0..mmr_size`.
+    pub fn import_nodes<I: IntoIterator<Item = Result<(u64, T)>>>(
+        mmr_size: u64,
+        nodes: I,
+        store: S,
+    ) -> Result<Self> {
+        if !is_valid_mmr_size(mmr_size) {
+            return Err(Error::InvalidMMRSize);
+        }
+        let mut elems = Vec::new();
+        for (expected_pos, node) in (0..).zip(nodes) {
+            let (pos, elem) = node?;
+            if pos != expected_pos || pos >= mmr_size {
+                return Err(Error::InvalidMMRSize);
+            }
+            elems.push(elem);
+        }
+        if elems.len() as u64 != mmr_size {
+            return Err(Error::InvalidMMRSize);
+        }
+        let mut mmr = MMR::new(mmr_size, store);
+        if !elems.is_empty() {
+            mmr.batch.append(0, elems);
+        }
+        Ok(mmr)
+    }
+
+    /// Create an empty MMR and push all leaves, returns the MMR and the positions of leaves.
+    /// Nothing is committed, the caller decides when to call `commit`.
+    pub fn build<I: IntoIterator<Item = T>>(store: S, leaves: I) -> Result<(Self, Vec<u64>)> {
+        let mut mmr = MMR::new(0, store);
+        let positions = leaves
+            .into_iter()
+            .map(|leaf| mmr.push(leaf))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((mmr, positions))
+    }
+
+    // check `PEAKS_DISTINCT` with the first two leaves once `nodes` are pushed after them,
+    // only debug builds read them
+    fn debug_check_first_leaves(&self, nodes: &[T]) -> Result<()> {
+        if cfg!(debug_assertions) && self.mmr_size < 3 && self.mmr_size + nodes.len() as u64 >= 3 {
+            let a = self.find_elem(0, nodes)?;
+            let b = self.find_elem(1, nodes)?;
+            debug_check_peaks_distinct::<M>(&a, &b);
+        }
+        Ok(())
+    }
+
+    // calculate the leaf and all parents created by pushing a element
+    fn push_elems(&self, elem: T) -> Result<Vec<T>> {
+        check_capacity(self.max_leaves, self.mmr_size, 1)?;
         let peak_map = get_peak_map(self.mmr_size);
+        let mut elems = vec![elem];
         let mut pos = self.mmr_size;
         let mut peak = 1;
         while (peak_map & peak) != 0 {
@@ -74,169 +273,841 @@ impl<T: Clone + PartialEq, M: Merge<Item = T>, S: MMRStoreReadOps<T>> MMR<T, M,
             let left_pos = pos - peak;
             let left_elem = self.find_elem(left_pos, &elems)?;
             let right_elem = elems.last().expect("checked");
-            let parent_elem = M::merge(&left_elem, right_elem)?;
+            let parent_elem =
+                M::merge_with_height(&left_elem, right_elem, peak.trailing_zeros() as u8)?;
             elems.push(parent_elem);
         }
+        self.debug_check_first_leaves(&elems)?;
+        Ok(elems)
+    }
+
+    // push a element and return position, the MMR is unchanged if it fails
+    pub fn push(&mut self, elem: T) -> Result<u64> {
+        let elem_pos = self.mmr_size;
+        let elems = self.push_elems(elem)?;
+        // update mmr_size
+        self.mmr_size += elems.len() as u64;
         // store hashes
         self.batch.append(elem_pos, elems);
-        // update mmr_size
-        self.mmr_size = pos + 1;
+        self.clear_root_cache();
         Ok(elem_pos)
     }
 
+    /// Push elements, returns the leaf positions in order, the same as calling `push` for each.
+    /// The peak map is derived once, and all nodes are staged as one segment of the batch.
+    pub fn push_batch(&mut self, elems: Vec<T>) -> Result<Vec<u64>> {
+        let (positions, nodes) = self.batch_elems(elems)?;
+        if nodes.is_empty() {
+            return Ok(positions);
+        }
+        let mmr_size = self.mmr_size + nodes.len() as u64;
+        self.batch.append(self.mmr_size, nodes);
+        self.mmr_size = mmr_size;
+        self.clear_root_cache();
+        Ok(positions)
+    }
+
+    /// Returns the root as if `extra_leaves` were pushed, without changing the MMR, e.g. to
+    /// preview the root of a candidate block. The new nodes are only kept during the call.
+    pub fn speculative_root(&self, extra_leaves: &[T]) -> Result<T> {
+        let (_, nodes) = self.batch_elems(extra_leaves.to_vec())?;
+        if nodes.is_empty() {
+            return self.get_root();
+        }
+        let mmr_size = self.mmr_size + nodes.len() as u64;
+        let peaks = get_peaks(mmr_size)
+            .into_iter()
+            .map(|peak_pos| self.find_elem(peak_pos, &nodes).map(Cow::into_owned))
+            .collect::<Result<Vec<_>>>()?;
+        bagging_peaks_hashes::<T, M>(&peaks, &mut { usize::MAX })
+    }
+
+    // calculate the leaf positions and all nodes created by pushing the elements
+    fn batch_elems(&self, elems: Vec<T>) -> Result<(Vec<u64>, Vec<T>)> {
+        check_capacity(self.max_leaves, self.mmr_size, elems.len() as u64)?;
+        let leaf_count = mmr_size_to_leaf_count(self.mmr_size);
+        let mut positions = Vec::with_capacity(elems.len());
+        let mut nodes: Vec<T> = Vec::with_capacity(elems.len() * 2);
+        let mut pos = self.mmr_size;
+        // the peak map grows by one with each leaf
+        for (peak_map, elem) in (leaf_count..).zip(elems) {
+            positions.push(pos);
+            nodes.push(elem);
+            let mut peak = 1;
+            while (peak_map & peak) != 0 {
+                peak <<= 1;
+                pos += 1;
+                let left_pos = pos - peak;
+                let left_elem = self.find_elem(left_pos, &nodes)?.into_owned();
+                let right_elem = nodes.last().expect("checked");
+                let parent_elem =
+                    M::merge_with_height(&left_elem, right_elem, peak.trailing_zeros() as u8)?;
+                nodes.push(parent_elem);
+            }
+            pos += 1;
+        }
+        self.debug_check_first_leaves(&nodes)?;
+        Ok((positions, nodes))
+    }
+
+    /// Same as `push_batch`, but the perfect subtrees of the pushed leaves are built in parallel,
+    /// enabled by `rayon`. Only merging the subtrees with the left peaks is sequential.
+    #[cfg(feature = "rayon")]
+    pub fn push_batch_parallel(&mut self, elems: Vec<T>) -> Result<Vec<u64>>
+    where
+        T: Send,
+    {
+        use rayon::prelude::*;
+
+        check_capacity(self.max_leaves, self.mmr_size, elems.len() as u64)?;
+        let leaf_count = mmr_size_to_leaf_count(self.mmr_size);
+        if elems.is_empty() {
+            return Ok(Vec::new());
+        }
+        // split the leaves into the largest subtrees aligned to their first leaf index
+        let end_index = leaf_count + elems.len() as u64;
+        let mut subtrees = Vec::new();
+        let mut rest = elems;
+        let mut index = leaf_count;
+        while index < end_index {
+            let max_height = 63 - (end_index - index).leading_zeros();
+            let height = index.trailing_zeros().min(max_height);
+            let tail = rest.split_off(1 << height);
+            subtrees.push((index, height, rest));
+            rest = tail;
+            index += 1 << height;
+        }
+        let built: Vec<Result<Vec<T>>> = subtrees
+            .par_iter_mut()
+            .map(|(_, _, leaves)| build_subtree::<T, M>(core::mem::take(leaves)))
+            .collect();
+
+        let mut positions = Vec::with_capacity((end_index - leaf_count) as usize);
+        let mut nodes: Vec<T> = Vec::with_capacity((end_index - leaf_count) as usize * 2);
+        let mut pos = self.mmr_size;
+        for ((index, height, _), subtree) in subtrees.into_iter().zip(built) {
+            positions.extend((index..index + (1 << height)).map(leaf_index_to_pos));
+            let subtree = subtree?;
+            pos += subtree.len() as u64;
+            nodes.extend(subtree);
+            // the lower bits of index are 0, merge as pushing the last leaf of the subtree
+            let mut peak = 1 << height;
+            while (index & peak) != 0 {
+                peak <<= 1;
+                let left_elem = self.find_elem(pos - peak, &nodes)?.into_owned();
+                let right_elem = nodes.last().expect("checked");
+                let parent_elem =
+                    M::merge_with_height(&left_elem, right_elem, peak.trailing_zeros() as u8)?;
+                nodes.push(parent_elem);
+                pos += 1;
+            }
+        }
+        self.debug_check_first_leaves(&nodes)?;
+        self.batch.append(self.mmr_size, nodes);
+        self.mmr_size = pos;
+        self.clear_root_cache();
+        Ok(positions)
+    }
+
+    /// Push a element, returns the leaf position and every `(pos, node)` created by this push,
+    /// the leaf and the merged parents, in ascending position order.
+    pub fn push_observed(&mut self, elem: T) -> Result<(u64, Vec<(u64, T)>)> {
+        let elem_pos = self.mmr_size;
+        let elems = self.push_elems(elem)?;
+        let nodes = (elem_pos..).zip(elems.iter().cloned()).collect::<Vec<_>>();
+        self.mmr_size += elems.len() as u64;
+        self.batch.append(elem_pos, elems);
+        self.clear_root_cache();
+        Ok((elem_pos, nodes))
+    }
+
+    /// Push a element, returns the leaf position and the shape of the MMR after the push.
+    /// Nodes are always appended in position order, the leaf first and then its new parents.
+    pub fn push_info(&mut self, elem: T) -> Result<PushInfo> {
+        let leaf_pos = self.mmr_size;
+        let elems = self.push_elems(elem)?;
+        let merges_performed = elems.len() as u32 - 1;
+        self.mmr_size += elems.len() as u64;
+        self.batch.append(leaf_pos, elems);
+        self.clear_root_cache();
+        Ok(PushInfo {
+            leaf_pos,
+            new_mmr_size: self.mmr_size,
+            merges_performed,
+            // the last appended node is always the right most peak
+            new_peak_pos: self.mmr_size - 1,
+        })
+    }
+
     /// get_root
+    ///
+    /// The root is cached until the MMR is changed, calling it again without pushing or
+    /// updating doesn't read the store. The cache is only cleared by the changes made through
+    /// this MMR, it goes stale if the nodes of the store are changed otherwise, e.g. by another
+    /// MMR over the same `&MemStore`.
+    ///
+    /// The cache is a `RefCell`, so the MMR is `Send` but not `Sync`, threads share the store
+    /// instead of the MMR, each opening its own MMR over a `&SyncMemStore`.
     pub fn get_root(&self) -> Result<T> {
+        if let Some((mmr_size, root)) = self.root_cache.borrow().as_ref() {
+            if *mmr_size == self.mmr_size {
+                return Ok(root.clone());
+            }
+        }
+        let root = self.compute_root()?;
+        *self.root_cache.borrow_mut() = Some((self.mmr_size, root.clone()));
+        Ok(root)
+    }
+
+    fn compute_root(&self) -> Result<T> {
         if self.mmr_size == 0 {
             return Err(Error::GetRootOnEmpty);
         } else if self.mmr_size == 1 {
             return self.batch.get_elem(0)?.ok_or(Error::InconsistentStore);
         }
-        let peaks: Vec<T> = get_peaks(self.mmr_size)
+        let peaks = self.get_existing_elems(&get_peaks(self.mmr_size))?;
+        self.bag_rhs_peaks(&peaks)?.ok_or(Error::InconsistentStore)
+    }
+
+    /// Same as `get_root`, but returns `None` for an empty MMR instead of
+    /// `Error::GetRootOnEmpty`.
+    pub fn try_get_root(&self) -> Result<Option<T>> {
+        if self.mmr_size == 0 {
+            return Ok(None);
+        }
+        self.get_root().map(Some)
+    }
+
+    /// Returns the `(position, element)` of the peaks from left to right, which along with the
+    /// mmr_size are enough to calculate the root and to push new leaves.
+    pub fn get_peak_values(&self) -> Result<Vec<(u64, T)>> {
+        get_peaks(self.mmr_size)
             .into_iter()
             .map(|peak_pos| {
                 self.batch
                     .get_elem(peak_pos)
                     .and_then(|elem| elem.ok_or(Error::InconsistentStore))
+                    .map(|elem| (peak_pos, elem))
             })
-            .collect::<Result<Vec<T>>>()?;
-        self.bag_rhs_peaks(peaks)?.ok_or(Error::InconsistentStore)
+            .collect()
     }
 
-    fn bag_rhs_peaks(&self, mut rhs_peaks: Vec<T>) -> Result<Option<T>> {
-        while rhs_peaks.len() > 1 {
-            let right_peak = rhs_peaks.pop().expect("pop");
-            let left_peak = rhs_peaks.pop().expect("pop");
-            rhs_peaks.push(M::merge_peaks(&right_peak, &left_peak)?);
+    fn bag_rhs_peaks(&self, rhs_peaks: &[T]) -> Result<Option<T>> {
+        if rhs_peaks.is_empty() {
+            return Ok(None);
         }
-        Ok(rhs_peaks.pop())
+        bagging_peaks_hashes::<_, M>(rhs_peaks, &mut { usize::MAX }).map(Some)
+    }
+
+    /// Generate merkle proof for positions, a position may be an internal node to prove the
+    /// root of its subtree, but not together with its descendants.
+    /// 1. calculate the proof skeleton, the position and role of each proof item
+    /// 2. take the items from store by peak from left to right
+    /// 3. bag right hand side peaks into one item
+    pub fn gen_proof(&self, pos_list: Vec<u64>) -> Result<MerkleProof<T, M>> {
+        let skeleton = proof_skeleton(pos_list, self.mmr_size)?;
+        self.gen_proof_by_skeleton(&skeleton, &BTreeMap::new())
     }
 
-    /// generate merkle proof for a peak
-    /// the pos_list must be sorted, otherwise the behaviour is undefined
+    /// Same as `gen_proof`, also returns the node position of each proof item, the position of
+    /// the bagged right hand side peaks is the position of the left-most one, see `ProofItemInfo`.
+    pub fn gen_proof_with_positions(
+        &self,
+        pos_list: Vec<u64>,
+    ) -> Result<(MerkleProof<T, M>, Vec<u64>)> {
+        let skeleton = proof_skeleton(pos_list, self.mmr_size)?;
+        let proof = self.gen_proof_by_skeleton(&skeleton, &BTreeMap::new())?;
+        Ok((proof, skeleton.into_iter().map(|(pos, _)| pos).collect()))
+    }
+
+    /// Same as `gen_proof`, but the items are sorted by their node position in the MMR,
+    /// see `ProofFormat::Ordered`. Verify it by `MerkleProof::verify_with_format`.
+    pub fn gen_proof_ordered(&self, pos_list: Vec<u64>) -> Result<MerkleProof<T, M>> {
+        let (proof, positions) = self.gen_proof_with_positions(pos_list)?;
+        let mut items: Vec<(u64, T)> = positions.into_iter().zip(proof.proof).collect();
+        items.sort_unstable_by_key(|(pos, _)| *pos);
+        let (positions, proof) = items.into_iter().unzip();
+        Ok(MerkleProof::with_positions(self.mmr_size, proof, positions))
+    }
+
+    // the values of `known` positions are taken instead of reading the store
+    fn gen_proof_by_skeleton(
+        &self,
+        skeleton: &[(u64, ItemRole)],
+        known: &BTreeMap<u64, &T>,
+    ) -> Result<MerkleProof<T, M>> {
+        // the bagged rhs item is always the last one
+        let rhs_peaks: Vec<u64> = match skeleton.last() {
+            Some((rhs_pos, ItemRole::BaggedRhs)) => get_peaks(self.mmr_size)
+                .into_iter()
+                .filter(|peak_pos| peak_pos >= rhs_pos)
+                .collect(),
+            _ => Vec::new(),
+        };
+        let node_count = skeleton.len() - (!rhs_peaks.is_empty()) as usize;
+        // read every unknown node of the proof and the rhs peaks at once
+        let positions: Vec<u64> = skeleton[..node_count]
+            .iter()
+            .map(|(pos, _)| *pos)
+            .chain(rhs_peaks.iter().copied())
+            .collect();
+        let unknown: Vec<u64> = positions
+            .iter()
+            .copied()
+            .filter(|pos| !known.contains_key(pos))
+            .collect();
+        let mut unknown = self.get_existing_elems(&unknown)?.into_iter();
+        let mut proof = positions
+            .iter()
+            .map(|pos| match known.get(pos) {
+                Some(elem) => Ok((*elem).clone()),
+                None => unknown.next().ok_or(Error::InconsistentStore),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !rhs_peaks.is_empty() {
+            let rhs_peaks = proof.split_off(node_count);
+            proof.push(self.bag_rhs_peaks(&rhs_peaks)?.expect("bagging rhs peaks"));
+        }
+        let positions = skeleton.iter().map(|(pos, _)| *pos).collect();
+        Ok(MerkleProof::with_positions(self.mmr_size, proof, positions))
+    }
+
+    /// Generate the proof of `positions` at the current mmr_size from `old_proof`, their proof
+    /// at `old_mmr_size`. Nodes never change once pushed, so every item of `old_proof` which is
+    /// a node is reused, only the other siblings and peaks are read from the store. The result
+    /// is the same as `gen_proof(positions)`.
+    ///
+    /// Returns `Error::InvalidMMRSize` if `old_mmr_size` is greater than the mmr_size, or
+    /// `Error::CorruptedProof` if `old_proof` doesn't have the items of the positions at
+    /// `old_mmr_size`.
+    pub fn extend_proof(
+        &self,
+        old_proof: &MerkleProof<T, M>,
+        positions: Vec<u64>,
+        old_mmr_size: u64,
+    ) -> Result<MerkleProof<T, M>> {
+        if old_mmr_size > self.mmr_size {
+            return Err(Error::InvalidMMRSize);
+        }
+        let old_skeleton = proof_skeleton(positions.clone(), old_mmr_size)?;
+        if old_proof.mmr_size() != old_mmr_size || old_skeleton.len() != old_proof.proof.len() {
+            return Err(Error::CorruptedProof);
+        }
+        // the bagged rhs item isn't a node, the peaks it bags change as the mmr grows
+        let known = old_skeleton
+            .iter()
+            .zip(old_proof.proof.iter())
+            .filter(|((_, role), _)| *role != ItemRole::BaggedRhs)
+            .map(|((pos, _), item)| (*pos, item))
+            .collect();
+        let skeleton = proof_skeleton(positions, self.mmr_size)?;
+        self.gen_proof_by_skeleton(&skeleton, &known)
+    }
+
+    /// Same as `gen_proof`, but only the peaks are read from the store, every other proof item
+    /// is calculated from the leaves under it, taken by `leaf_provider(pos)`. It serves proofs
+    /// from a store holding only the peaks, at the cost of merging the subtrees of the items.
     ///
-    /// 1. find a lower tree in peak that can generate a complete merkle proof for position
-    /// 2. find that tree by compare positions
-    /// 3. generate proof for each positions
-    fn gen_proof_for_peak(
+    /// Returns `Error::InconsistentStore` if the provider doesn't have a required leaf.
+    pub fn gen_proof_from_leaves<F: Fn(u64) -> Result<Option<T>>>(
         &self,
-        proof: &mut Vec<T>,
         pos_list: Vec<u64>,
-        peak_pos: u64,
-    ) -> Result<()> {
-        // do nothing if position itself is the peak
-        if pos_list.len() == 1 && pos_list == [peak_pos] {
-            return Ok(());
-        }
-        // take peak root from store if no positions need to be proof
-        if pos_list.is_empty() {
-            proof.push(
-                self.batch
-                    .get_elem(peak_pos)?
-                    .ok_or(Error::InconsistentStore)?,
-            );
-            return Ok(());
+        leaf_provider: F,
+    ) -> Result<MerkleProof<T, M>> {
+        let skeleton = proof_skeleton(pos_list, self.mmr_size)?;
+        let peaks = get_peaks(self.mmr_size);
+        let calculated = skeleton
+            .iter()
+            .filter(|(pos, role)| *role != ItemRole::BaggedRhs && !peaks.contains(pos))
+            .map(|(pos, _)| {
+                calculate_node_from_leaves::<_, M, _>(*pos, &leaf_provider).map(|node| (*pos, node))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let known = calculated.iter().map(|(pos, node)| (*pos, node)).collect();
+        self.gen_proof_by_skeleton(&skeleton, &known)
+    }
+
+    /// Generate merkle proof for `prove`, `known` leaves are held by the verifier and must be
+    /// passed to the verification along with the proven leaves, so they are never proof items.
+    pub fn gen_proof_excluding(
+        &self,
+        mut prove: Vec<u64>,
+        known: Vec<u64>,
+    ) -> Result<MerkleProof<T, M>> {
+        prove.extend(known);
+        self.gen_proof(prove)
+    }
+
+    /// Generate merkle proof for the leaves from `start_leaf_index` to `end_leaf_index`
+    /// inclusive, verified by `MerkleProof::verify_range`.
+    ///
+    /// The range is walked by its fully covered subtrees instead of every leaf, the proof items
+    /// are the same as `gen_proof` of the leaves since a covered subtree never needs one.
+    pub fn gen_range_proof(
+        &self,
+        start_leaf_index: u64,
+        end_leaf_index: u64,
+    ) -> Result<MerkleProof<T, M>> {
+        if start_leaf_index > end_leaf_index || end_leaf_index >= self.leaf_count() {
+            return Err(Error::GenProofForInvalidLeaves);
         }
+        self.gen_proof(leaf_range_nodes(start_leaf_index, end_leaf_index))
+    }
 
-        let mut queue: VecDeque<_> = pos_list.into_iter().map(|pos| (pos, 0)).collect();
+    /// Generate a proof that the MMR is an append-only extension of the MMR at `old_mmr_size`,
+    /// returns `Error::InvalidMMRSize` if it is not a non-empty mmr size no greater than the
+    /// current one.
+    pub fn gen_consistency_proof(&self, old_mmr_size: u64) -> Result<ConsistencyProof<T, M>> {
+        if old_mmr_size == 0 || !is_valid_mmr_size(old_mmr_size) || old_mmr_size > self.mmr_size {
+            return Err(Error::InvalidMMRSize);
+        }
+        // the old peaks are nodes of the current MMR
+        let old_peak_positions = get_peaks(old_mmr_size);
+        let old_peaks = self.get_peak_elems(old_peak_positions.iter().copied())?;
+        let proof = self.gen_proof(old_peak_positions)?;
+        Ok(ConsistencyProof {
+            old_mmr_size,
+            old_peaks,
+            proof,
+        })
+    }
 
-        // Generate sub-tree merkle proof for positions
-        while let Some((pos, height)) = queue.pop_front() {
-            debug_assert!(pos <= peak_pos);
-            if pos == peak_pos {
-                if queue.is_empty() {
-                    break;
-                } else {
-                    return Err(Error::NodeProofsNotSupported);
-                }
+    /// Generate a proof that the leaf at `leaf_index` isn't pushed yet, which is a proof of
+    /// the current mmr_size by its peaks. Returns `Error::LeafIndexOutOfRange` if the leaf
+    /// exists, or `Error::GetRootOnEmpty` if the MMR is empty.
+    pub fn gen_non_membership_proof(&self, leaf_index: u64) -> Result<NonMembershipProof<T, M>> {
+        if self.mmr_size == 0 {
+            return Err(Error::GetRootOnEmpty);
+        }
+        if leaf_index < mmr_size_to_leaf_count(self.mmr_size) {
+            return Err(Error::LeafIndexOutOfRange);
+        }
+        let peaks = self.get_peak_elems(iter_peaks(self.mmr_size))?;
+        Ok(NonMembershipProof {
+            leaf_index,
+            mmr_size: self.mmr_size,
+            peaks,
+            merge: PhantomData,
+        })
+    }
+
+    // materialize a step of the proof skeleton
+    fn proof_item(&self, pos: u64, role: ItemRole) -> Result<T> {
+        if role == ItemRole::BaggedRhs {
+            let rhs_peaks = get_peaks(self.mmr_size)
+                .into_iter()
+                .filter(|peak_pos| *peak_pos >= pos)
+                .map(|peak_pos| {
+                    self.batch
+                        .get_elem(peak_pos)
+                        .and_then(|elem| elem.ok_or(Error::InconsistentStore))
+                })
+                .collect::<Result<Vec<T>>>()?;
+            return Ok(self.bag_rhs_peaks(&rhs_peaks)?.expect("bagging rhs peaks"));
+        }
+        self.batch.get_elem(pos)?.ok_or(Error::InconsistentStore)
+    }
+
+    fn get_peak_elems(&self, peaks: impl Iterator<Item = u64>) -> Result<Vec<T>> {
+        self.get_existing_elems(&peaks.collect::<Vec<_>>())
+    }
+
+    // read the elements by a single `get_elems`, all of them must exist
+    fn get_existing_elems(&self, positions: &[u64]) -> Result<Vec<T>> {
+        self.batch
+            .get_elems(positions)?
+            .into_iter()
+            .map(|elem| elem.ok_or(Error::InconsistentStore))
+            .collect()
+    }
+
+    /// get root in the given format, see `ProofFormat`
+    pub fn get_root_with_format(&self, format: ProofFormat) -> Result<T> {
+        match format {
+            ProofFormat::Ckb | ProofFormat::Ordered => self.get_root(),
+            ProofFormat::Grin => {
+                let peaks = self.get_peak_elems(get_peaks(self.mmr_size).into_iter())?;
+                bag_peaks_grin::<T, M>(peaks)?.ok_or(Error::GetRootOnEmpty)
             }
+        }
+    }
 
-            // calculate sibling
-            let (sib_pos, parent_pos) = {
-                let next_height = pos_height_in_tree(pos + 1);
-                let sibling_offset = sibling_offset(height);
-                if next_height > height {
-                    // implies pos is right sibling
-                    (pos - sibling_offset, pos + 1)
-                } else {
-                    // pos is left sibling
-                    (pos + sibling_offset, pos + parent_offset(height))
-                }
-            };
+    /// Generate merkle proof in the given format, see `ProofFormat`.
+    /// A `Grin` proof proves exactly one leaf.
+    pub fn gen_proof_with_format(
+        &self,
+        pos_list: Vec<u64>,
+        format: ProofFormat,
+    ) -> Result<MerkleProof<T, M>> {
+        match format {
+            ProofFormat::Ckb => return self.gen_proof(pos_list),
+            ProofFormat::Ordered => return self.gen_proof_ordered(pos_list),
+            ProofFormat::Grin => {}
+        }
+        if pos_list.len() != 1 || pos_list[0] >= self.mmr_size {
+            return Err(Error::GenProofForInvalidLeaves);
+        }
+        let pos = pos_list[0];
+        if pos_height_in_tree(pos) > 0 {
+            return Err(Error::NodeProofsNotSupported);
+        }
+        let peaks = get_peaks(self.mmr_size);
+        let peak_pos = *peaks
+            .iter()
+            .find(|peak_pos| **peak_pos >= pos)
+            .ok_or(Error::GenProofForInvalidLeaves)?;
+        // path from the leaf to its peak
+        let mut skeleton = Vec::new();
+        peak_skeleton(&mut skeleton, pos_list, peak_pos)?;
+        let mut proof = skeleton
+            .into_iter()
+            .map(|(pos, role)| self.proof_item(pos, role))
+            .collect::<Result<Vec<T>>>()?;
+        // bagged right hand side peaks
+        let rhs_peaks =
+            self.get_peak_elems(peaks.iter().copied().filter(|rhs_pos| *rhs_pos > peak_pos))?;
+        proof.extend(bag_peaks_grin::<T, M>(rhs_peaks)?);
+        // left hand side peaks, from right to left
+        let lhs_peaks = self.get_peak_elems(
+            peaks
+                .iter()
+                .rev()
+                .copied()
+                .filter(|lhs_pos| *lhs_pos < peak_pos),
+        )?;
+        proof.extend(lhs_peaks);
+        Ok(MerkleProof::new(self.mmr_size, proof))
+    }
+}
 
-            if Some(&sib_pos) == queue.front().map(|(pos, _)| pos) {
-                // drop sibling
-                queue.pop_front();
-            } else {
-                proof.push(
-                    self.batch
-                        .get_elem(sib_pos)?
-                        .ok_or(Error::InconsistentStore)?,
-                );
+impl<T, M, S: MMRStoreWriteOps<T>> MMR<T, M, S> {
+    /// Persist the staged nodes, returns the count of persisted positions, see `MMRBatch::commit`.
+    pub fn commit(&mut self) -> Result<u64> {
+        self.batch.commit()
+    }
+
+    /// Same as `commit`, without the count, for callers of its former `Result<()>` signature.
+    pub fn commit_unit(&mut self) -> Result<()> {
+        self.commit().map(|_| ())
+    }
+
+    /// Roll back to a previous `target_mmr_size`, the later nodes are dropped from the batch
+    /// and deleted from the store. Returns `Error::InvalidMMRSize` if the target is not a valid
+    /// mmr size no greater than the current one.
+    pub fn rollback(&mut self, target_mmr_size: u64) -> Result<()> {
+        if !is_valid_mmr_size(target_mmr_size) || target_mmr_size > self.mmr_size {
+            return Err(Error::InvalidMMRSize);
+        }
+        let staged_pos = self.batch.first_staged_pos().unwrap_or(self.mmr_size);
+        self.batch
+            .store_mut()
+            .delete_range(target_mmr_size..staged_pos)?;
+        self.batch.discard_from(target_mmr_size);
+        self.mmr_size = target_mmr_size;
+        // pushing back to the cached mmr_size may produce a different root
+        self.clear_root_cache();
+        Ok(())
+    }
+
+    /// Commit the staged nodes up to `pos`, returns the count of persisted positions, see
+    /// `MMRBatch::commit_up_to`.
+    pub fn commit_up_to(&mut self, pos: u64) -> Result<u64> {
+        self.batch.commit_up_to(pos)
+    }
+
+    /// Delete nodes which are no longer needed once leaves below `leaf_index` are archived.
+    ///
+    /// A node is deleted when its whole subtree and its sibling's subtree are below the
+    /// watermark, so the peaks and every sibling on the path of a retained leaf are kept,
+    /// which is enough to push new leaves and to prove leaves at or above the watermark.
+    /// Pending changes are committed before compaction.
+    pub fn compact_below(&mut self, leaf_index: u64) -> Result<()> {
+        self.commit()?;
+        let leaves_count = mmr_size_to_leaf_count(self.mmr_size);
+        let watermark_pos = if leaf_index < leaves_count {
+            leaf_index_to_pos(leaf_index)
+        } else {
+            self.mmr_size
+        };
+        let peaks = get_peaks(self.mmr_size);
+        // a node whose subtree is below the watermark always has a position below it
+        for pos in 0..watermark_pos {
+            if peaks.contains(&pos) {
+                continue;
             }
-            if parent_pos < peak_pos {
-                // save pos to tree buf
-                queue.push_back((parent_pos, height + 1));
+            let height = pos_height_in_tree(pos);
+            let next_height = pos_height_in_tree(pos + 1);
+            // the right-most leaf of the parent's subtree
+            let parent_last_leaf = if next_height > height {
+                // implies pos is right sibling
+                pos - height as u64
+            } else {
+                // pos is left sibling
+                pos + sibling_offset(height) - height as u64
+            };
+            if parent_last_leaf < watermark_pos {
+                self.batch.store_mut().delete(pos)?;
             }
         }
         Ok(())
     }
+}
 
-    /// Generate merkle proof for positions
-    /// 1. sort positions
-    /// 2. push merkle proof to proof by peak from left to right
-    /// 3. push bagged right hand side root
-    pub fn gen_proof(&self, mut pos_list: Vec<u64>) -> Result<MerkleProof<T, M>> {
-        if pos_list.is_empty() {
-            return Err(Error::GenProofForInvalidLeaves);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize",
+        deserialize = "T: serde::Deserialize<'de>"
+    ))
+)]
+pub struct MerkleProof<T, M> {
+    mmr_size: u64,
+    proof: Vec<T>,
+    // positions of the items, unknown if built by `new`
+    positions: Option<Vec<u64>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    merge: PhantomData<M>,
+}
+
+// subtrees of fewer leaves are built sequentially, spawning tasks costs more than merging
+#[cfg(feature = "rayon")]
+const PARALLEL_SUBTREE_LEAVES: usize = 1024;
+
+// nodes of the perfect tree of `leaves` in position order, halves are built in parallel
+#[cfg(feature = "rayon")]
+fn build_subtree<T: Send, M: Merge<Item = T>>(mut leaves: Vec<T>) -> Result<Vec<T>> {
+    if leaves.len() <= PARALLEL_SUBTREE_LEAVES {
+        let mut nodes = Vec::with_capacity(leaves.len() * 2);
+        for (peak_map, leaf) in (0u64..).zip(leaves) {
+            nodes.push(leaf);
+            let mut peak = 1;
+            while (peak_map & peak) != 0 {
+                peak <<= 1;
+                let left_elem = &nodes[nodes.len() - peak as usize];
+                let right_elem = nodes.last().expect("checked");
+                let parent_elem =
+                    M::merge_with_height(left_elem, right_elem, peak.trailing_zeros() as u8)?;
+                nodes.push(parent_elem);
+            }
         }
-        if self.mmr_size == 1 && pos_list == [0] {
-            return Ok(MerkleProof::new(self.mmr_size, Vec::new()));
+        return Ok(nodes);
+    }
+    let right = leaves.split_off(leaves.len() / 2);
+    let (left, right) = rayon::join(
+        || build_subtree::<T, M>(leaves),
+        || build_subtree::<T, M>(right),
+    );
+    let (mut left, mut right) = (left?, right?);
+    // each half of 2^h leaves has 2^(h+1) - 1 nodes, the parent is at height h + 1
+    let height = (left.len() + 1).trailing_zeros() as u8;
+    let parent_elem = M::merge_with_height(
+        left.last().expect("not empty"),
+        right.last().expect("not empty"),
+        height,
+    )?;
+    left.append(&mut right);
+    left.push(parent_elem);
+    Ok(left)
+}
+
+impl<T: Clone, M: Merge<Item = T>, S: MMRStoreReadOps<T> + MMRStoreWriteOps<T>> MMR<T, M, S> {
+    /// Remove the last leaf and the nodes merged by pushing it, returns the removed leaf or
+    /// `None` if the MMR is empty. The removed nodes are dropped from the batch and deleted
+    /// from the store, see `rollback`.
+    pub fn pop(&mut self) -> Result<Option<T>> {
+        let leaf_count = mmr_size_to_leaf_count(self.mmr_size);
+        if leaf_count == 0 {
+            return Ok(None);
         }
-        if pos_list.iter().any(|pos| pos_height_in_tree(*pos) > 0) {
-            return Err(Error::NodeProofsNotSupported);
+        let leaf_pos = leaf_index_to_pos(leaf_count - 1);
+        let leaf = self
+            .batch
+            .get_elem(leaf_pos)?
+            .ok_or(Error::InconsistentStore)?;
+        // the leaf is the first node pushed after the previous mmr
+        self.rollback(leaf_pos)?;
+        Ok(Some(leaf))
+    }
+
+    /// Replace the leaf at `pos` and recompute its ancestors up to the peak.
+    ///
+    /// Every replaced node is staged in the batch, committed ones are overwritten in the store
+    /// by `MMRStoreWriteOps::update` on `commit`, and dropped by a `rollback` below them.
+    /// Returns `Error::NotLeaf` if `pos` isn't a leaf of the MMR, or `Error::InconsistentStore`
+    /// if a sibling is missing, in which case nothing is staged.
+    pub fn update(&mut self, pos: u64, new_elem: T) -> Result<()> {
+        if pos >= self.mmr_size || pos_height_in_tree(pos) != 0 {
+            return Err(Error::NotLeaf(pos));
         }
-        // ensure positions are sorted and unique
-        pos_list.sort_unstable();
-        pos_list.dedup();
         let peaks = get_peaks(self.mmr_size);
-        let mut proof: Vec<T> = Vec::new();
-        // generate merkle proof for each peaks
-        let mut bagging_track = 0;
-        for peak_pos in peaks {
-            let pos_list: Vec<_> = take_while_vec(&mut pos_list, |&pos| pos <= peak_pos);
-            if pos_list.is_empty() {
-                bagging_track += 1;
+        let mut nodes = vec![(pos, new_elem)];
+        let mut height = 0u8;
+        let mut pos = pos;
+        while !peaks.contains(&pos) {
+            let (_, elem) = nodes.last().expect("not empty");
+            let sibling_pos = sibling_pos(pos, height);
+            let sibling = self.get_sibling(sibling_pos)?;
+            let parent = if sibling_pos < pos {
+                M::merge_with_height(&sibling, elem, height + 1)?
             } else {
-                bagging_track = 0;
+                M::merge_with_height(elem, &sibling, height + 1)?
+            };
+            pos = parent_pos(pos, height);
+            height += 1;
+            nodes.push((pos, parent));
+        }
+        self.clear_root_cache();
+        for (pos, elem) in nodes {
+            self.batch.stage_update(pos, elem);
+        }
+        Ok(())
+    }
+
+    /// Delete every node whose subtree is entirely below the leaf at `keep_from_leaf_index`,
+    /// except the peaks, returns the count of deleted positions.
+    ///
+    /// Unlike `compact_below`, siblings on the path of retained leaves are deleted too, so
+    /// proofs of leaves under the same peak as pruned ones can't be generated anymore, but
+    /// the peaks are enough to push new leaves and to get the root. Pending changes are
+    /// committed before pruning, positions already missing from the store are not counted.
+    pub fn prune_below(&mut self, keep_from_leaf_index: u64) -> Result<u64> {
+        self.commit()?;
+        let watermark_pos = if keep_from_leaf_index < self.leaf_count() {
+            leaf_index_to_pos(keep_from_leaf_index)
+        } else {
+            self.mmr_size
+        };
+        let peaks = get_peaks(self.mmr_size);
+        let mut pruned = 0;
+        // nodes are ordered by post-order, a node below the watermark has all its leaves below
+        for pos in 0..watermark_pos {
+            if peaks.contains(&pos) || self.batch.store().get_elem(pos)?.is_none() {
+                continue;
             }
-            self.gen_proof_for_peak(&mut proof, pos_list, peak_pos)?;
+            self.batch.store_mut().delete(pos)?;
+            pruned += 1;
         }
+        Ok(pruned)
+    }
 
-        // ensure no remain positions
-        if !pos_list.is_empty() {
-            return Err(Error::GenProofForInvalidLeaves);
+    fn get_sibling(&self, pos: u64) -> Result<T> {
+        self.batch.get_elem(pos)?.ok_or(Error::InconsistentStore)
+    }
+}
+
+/// Prints the mmr_size and each item, prefixed by its position if known.
+impl<T: Debug, M> Debug for MerkleProof<T, M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_items(f, |item, f| item.fmt(f))
+    }
+}
+
+impl<T, M> MerkleProof<T, M> {
+    /// Same as `Debug`, but prints a short hex of each item instead, e.g. for hashes.
+    pub fn short_hex(&self) -> impl Debug + '_
+    where
+        T: AsRef<[u8]>,
+    {
+        struct ShortHex<'a, T, M>(&'a MerkleProof<T, M>);
+        impl<T: AsRef<[u8]>, M> Debug for ShortHex<'_, T, M> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0
+                    .fmt_items(f, |item, f| fmt_short_hex(item.as_ref(), f))
+            }
         }
+        ShortHex(self)
+    }
 
-        if bagging_track > 1 {
-            let rhs_peaks = proof.split_off(proof.len() - bagging_track);
-            proof.push(self.bag_rhs_peaks(rhs_peaks)?.expect("bagging rhs peaks"));
+    fn fmt_items<F>(&self, f: &mut fmt::Formatter, fmt_item: F) -> fmt::Result
+    where
+        F: Fn(&T, &mut fmt::Formatter) -> fmt::Result,
+    {
+        // an item as `pos: item`
+        struct Item<'a, T, F>(Option<u64>, &'a T, &'a F);
+        impl<T, F: Fn(&T, &mut fmt::Formatter) -> fmt::Result> Debug for Item<'_, T, F> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                if let Some(pos) = self.0 {
+                    write!(f, "{}: ", pos)?;
+                }
+                (self.2)(self.1, f)
+            }
+        }
+        struct Items<'a, T, F>(&'a [T], Option<&'a [u64]>, &'a F);
+        impl<T, F: Fn(&T, &mut fmt::Formatter) -> fmt::Result> Debug for Items<'_, T, F> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let positions = self.1.map(|positions| positions.iter().copied().map(Some));
+                f.debug_list()
+                    .entries(
+                        positions
+                            .into_iter()
+                            .flatten()
+                            .chain(core::iter::repeat(None))
+                            .zip(self.0)
+                            .map(|(pos, item)| Item(pos, item, self.2)),
+                    )
+                    .finish()
+            }
         }
+        f.debug_struct("MerkleProof")
+            .field("mmr_size", &self.mmr_size)
+            .field(
+                "items",
+                &Items(&self.proof, self.positions.as_deref(), &fmt_item),
+            )
+            .finish()
+    }
+}
 
-        Ok(MerkleProof::new(self.mmr_size, proof))
+// hex of the first 4 bytes, followed by `..` if there are more
+fn fmt_short_hex(bytes: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    for byte in bytes.iter().take(4) {
+        write!(f, "{:02x}", byte)?;
+    }
+    if bytes.len() > 4 {
+        write!(f, "..")?;
     }
+    Ok(())
 }
 
-impl<T, M, S: MMRStoreWriteOps<T>> MMR<T, M, S> {
-    pub fn commit(&mut self) -> Result<()> {
-        self.batch.commit()
+/// Layout of proof items and bagging convention of a proof
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofFormat {
+    /// The format of this crate, see `MMR::gen_proof`
+    Ckb,
+    /// The Grin convention for a single leaf, proof items are the path to the peak, then
+    /// the bagged right hand side peaks, then left hand side peaks from right to left.
+    /// Peaks are bagged from right to left via `merge_peaks(left, right)`.
+    Grin,
+    /// The items of a `Ckb` proof sorted by node position, the position of the bagged right
+    /// hand side peaks is the position of the left-most one, so it's always the last item.
+    /// Peaks are bagged the same as `Ckb`, the root is `MMR::get_root`.
+    Ordered,
+}
+
+/// Result of `MerkleProof::verify_detailed`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport<T> {
+    /// root calculated from the proof and the leaves
+    pub computed_root: T,
+    pub expected_root: T,
+    /// positions of the leaves calculated into the root, sorted and deduplicated
+    pub leaf_positions: Vec<u64>,
+}
+
+impl<T: PartialEq> VerifyReport<T> {
+    pub fn is_valid(&self) -> bool {
+        self.computed_root == self.expected_root
     }
 }
 
-#[derive(Debug)]
-pub struct MerkleProof<T, M> {
-    mmr_size: u64,
-    proof: Vec<T>,
-    merge: PhantomData<M>,
+/// A proof item together with its node position and role,
+/// the position of a `BaggedRhs` item is the position of the left-most bagged peak.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofItemInfo<'a, T> {
+    pub value: &'a T,
+    pub pos: u64,
+    pub role: ItemRole,
 }
 
 impl<T: Clone + PartialEq, M: Merge<Item = T>> MerkleProof<T, M> {
@@ -244,10 +1115,42 @@ impl<T: Clone + PartialEq, M: Merge<Item = T>> MerkleProof<T, M> {
         MerkleProof {
             mmr_size,
             proof,
+            positions: None,
             merge: PhantomData,
         }
     }
 
+    // a proof of the positions in the skeleton, which are checked by generating it
+    pub(crate) fn with_positions(mmr_size: u64, proof: Vec<T>, positions: Vec<u64>) -> Self {
+        debug_assert_eq!(proof.len(), positions.len());
+        MerkleProof {
+            mmr_size,
+            proof,
+            positions: Some(positions),
+            merge: PhantomData,
+        }
+    }
+
+    /// Build a proof from `(position, item)` pairs in the proof order, the positions must be
+    /// distinct and within the mmr. A bagged right hand side item is at its left most peak.
+    pub fn from_items<I: IntoIterator<Item = (u64, T)>>(mmr_size: u64, items: I) -> Result<Self> {
+        let (positions, proof): (Vec<u64>, Vec<T>) = items.into_iter().unzip();
+        let mut sorted_positions = positions.clone();
+        sorted_positions.sort_unstable();
+        sorted_positions.dedup();
+        if sorted_positions.len() != positions.len()
+            || matches!(sorted_positions.last(), Some(pos) if *pos >= mmr_size)
+        {
+            return Err(Error::CorruptedProof);
+        }
+        Ok(MerkleProof {
+            mmr_size,
+            proof,
+            positions: Some(positions),
+            merge: PhantomData,
+        })
+    }
+
     pub fn mmr_size(&self) -> u64 {
         self.mmr_size
     }
@@ -256,8 +1159,99 @@ impl<T: Clone + PartialEq, M: Merge<Item = T>> MerkleProof<T, M> {
         &self.proof
     }
 
+    /// Positions of the proof items, known if the proof is generated by the MMR or built by
+    /// `from_items`, `None` if it's built by `new`, e.g. decoded by `encoding`.
+    pub fn item_positions(&self) -> Option<&[u64]> {
+        self.positions.as_deref()
+    }
+
+    /// Returns the proof items with their positions and roles,
+    /// `pos_list` is the positions of the proven leaves.
+    pub fn annotated_items(&self, pos_list: &[u64]) -> Result<Vec<ProofItemInfo<'_, T>>> {
+        let layout = proof_skeleton(pos_list.to_vec(), self.mmr_size)?;
+        if layout.len() != self.proof.len() {
+            return Err(Error::CorruptedProof);
+        }
+        Ok(self
+            .proof
+            .iter()
+            .zip(layout)
+            .map(|(value, (pos, role))| ProofItemInfo { value, pos, role })
+            .collect())
+    }
+
     pub fn calculate_root(&self, leaves: Vec<(u64, T)>) -> Result<T> {
-        calculate_root::<_, M, _>(leaves, self.mmr_size, self.proof.iter())
+        self.calculate_root_with_budget(leaves, usize::MAX)
+    }
+
+    /// Returns the roots of the peaks calculated from the leaves and the proof before bagging,
+    /// from left to right, the last one is the bagged right hand side peaks if the proof has
+    /// it. For example, the left-most ones can be compared with the peaks of an older MMR.
+    pub fn calculate_peaks(&self, leaves: Vec<(u64, T)>) -> Result<Vec<T>> {
+        calculate_peaks_hashes::<_, M, _>(leaves, self.mmr_size, self.proof.iter(), &mut {
+            usize::MAX
+        })
+    }
+
+    /// Same as `calculate_root`, but aborts with `Error::ProofBudgetExceeded` once more than
+    /// `max_ops` merge operations are required, so verifiers can cap the cost of untrusted proofs.
+    pub fn calculate_root_with_budget(&self, leaves: Vec<(u64, T)>, max_ops: usize) -> Result<T> {
+        calculate_root::<_, M, _>(leaves, self.mmr_size, self.proof.iter(), max_ops)
+    }
+
+    /// Checks the proof is well-formed for the leaves and returns the root it implies, for
+    /// relays which don't know the real root. Unlike `calculate_root`, it also rejects an invalid
+    /// mmr_size, and leaves or a count of items `gen_proof` wouldn't produce, with
+    /// `Error::CorruptedProof`.
+    pub fn self_check(&self, leaves: Vec<(u64, T)>) -> Result<T> {
+        if self.mmr_size == 0 || !is_valid_mmr_size(self.mmr_size) {
+            return Err(Error::CorruptedProof);
+        }
+        // a missing bagged rhs item isn't detected by `calculate_root`
+        let layout = proof_skeleton(leaves.iter().map(|(pos, _)| *pos).collect(), self.mmr_size)
+            .map_err(|_| Error::CorruptedProof)?;
+        if layout.len() != self.proof.len() {
+            return Err(Error::CorruptedProof);
+        }
+        self.calculate_root(leaves)
+    }
+
+    /// Combines with a proof of the same mmr into the proof `MMR::gen_proof` generates for the
+    /// union of their positions. Siblings covered by the union are dropped, the others are
+    /// taken from either proof, returns `Error::CorruptedProof` if the proofs are of different
+    /// mmr sizes or don't match their positions.
+    pub fn merge_with(
+        self,
+        other: MerkleProof<T, M>,
+        own_positions: Vec<u64>,
+        other_positions: Vec<u64>,
+    ) -> Result<MerkleProof<T, M>> {
+        if self.mmr_size != other.mmr_size {
+            return Err(Error::CorruptedProof);
+        }
+        let own_items = self.annotated_items(&own_positions)?;
+        let other_items = other.annotated_items(&other_positions)?;
+        let mut pos_list = own_positions;
+        pos_list.extend(other_positions);
+        // every item of the union is an item of the proof which proves the leaves below it
+        let skeleton = proof_skeleton(pos_list, self.mmr_size)?;
+        let proof = skeleton
+            .iter()
+            .map(|&(pos, role)| {
+                let mut candidates = own_items
+                    .iter()
+                    .chain(other_items.iter())
+                    .filter(|info| info.pos == pos && info.role == role);
+                let item = candidates.next().ok_or(Error::CorruptedProof)?;
+                // the same node in both proofs must have the same value
+                if candidates.any(|info| info.value != item.value) {
+                    return Err(Error::CorruptedProof);
+                }
+                Ok(item.value.clone())
+            })
+            .collect::<Result<Vec<T>>>()?;
+        let positions = skeleton.iter().map(|(pos, _)| *pos).collect();
+        Ok(MerkleProof::with_positions(self.mmr_size, proof, positions))
     }
 
     /// from merkle proof of leaf n to calculate merkle root of n + 1 leaves.
@@ -271,30 +1265,345 @@ impl<T: Clone + PartialEq, M: Merge<Item = T>> MerkleProof<T, M> {
         new_elem: T,
         new_mmr_size: u64,
     ) -> Result<T> {
+        // the new leaf must be the next leaf of this MMR
+        let new_leaf_index = mmr_size_to_leaf_count(self.mmr_size);
+        if new_pos != leaf_index_to_pos(new_leaf_index)
+            || new_mmr_size != leaf_index_to_mmr_size(new_leaf_index)
+        {
+            return Err(Error::InvalidNewLeaf);
+        }
         let pos_height = pos_height_in_tree(new_pos);
         let next_height = pos_height_in_tree(new_pos + 1);
         if next_height > pos_height {
-            let mut peaks_hashes =
-                calculate_peaks_hashes::<_, M, _>(leaves, self.mmr_size, self.proof.iter())?;
+            let mut peaks_hashes = calculate_peaks_hashes::<_, M, _>(
+                leaves,
+                self.mmr_size,
+                self.proof.iter(),
+                &mut { usize::MAX },
+            )?;
             let peaks_pos = get_peaks(new_mmr_size);
             // reverse touched peaks
-            let mut i = 0;
-            while peaks_pos[i] < new_pos {
-                i += 1
-            }
-            peaks_hashes[i..].reverse();
-            calculate_root::<_, M, _>(vec![(new_pos, new_elem)], new_mmr_size, peaks_hashes.iter())
+            let i = peaks_pos
+                .iter()
+                .position(|peak_pos| *peak_pos >= new_pos)
+                .ok_or(Error::InvalidNewLeaf)?;
+            peaks_hashes
+                .get_mut(i..)
+                .ok_or(Error::InvalidNewLeaf)?
+                .reverse();
+            calculate_root::<_, M, _>(
+                vec![(new_pos, new_elem)],
+                new_mmr_size,
+                peaks_hashes.iter(),
+                usize::MAX,
+            )
         } else {
             leaves.push((new_pos, new_elem));
-            calculate_root::<_, M, _>(leaves, new_mmr_size, self.proof.iter())
+            calculate_root::<_, M, _>(leaves, new_mmr_size, self.proof.iter(), usize::MAX)
         }
     }
 
+    /// Same as `calculate_root_with_new_leaf`, but appends `new_leaves` one by one and returns
+    /// the root of the MMR with `new_mmr_size` after the last one. The new leaves must follow
+    /// this MMR without gaps, and the proof must not bag rhs peaks, which can't be extended.
+    pub fn calculate_root_with_new_leaves(
+        &self,
+        leaves: Vec<(u64, T)>,
+        new_leaves: Vec<(u64, T)>,
+        new_mmr_size: u64,
+    ) -> Result<T> {
+        let leaf_count = mmr_size_to_leaf_count(self.mmr_size);
+        let contiguous = new_leaves
+            .iter()
+            .zip(leaf_count..)
+            .all(|((pos, _), index)| *pos == leaf_index_to_pos(index));
+        let expected_mmr_size = match new_leaves.len() as u64 {
+            0 => self.mmr_size,
+            count => leaf_index_to_mmr_size(leaf_count + count - 1),
+        };
+        if !contiguous || new_mmr_size != expected_mmr_size {
+            return Err(Error::InvalidNewLeaf);
+        }
+        let peaks_pos = get_peaks(self.mmr_size);
+        let peaks_hashes =
+            calculate_peaks_hashes::<_, M, _>(leaves, self.mmr_size, self.proof.iter(), &mut {
+                usize::MAX
+            })?;
+        if peaks_hashes.len() != peaks_pos.len() {
+            return Err(Error::CorruptedProof);
+        }
+        // (height, hash) of each peak, the new leaf merges with the peaks of its height
+        // from right to left as `push` does
+        let mut peaks: Vec<(u8, T)> = peaks_pos
+            .into_iter()
+            .map(pos_height_in_tree)
+            .zip(peaks_hashes)
+            .collect();
+        for (_, leaf) in new_leaves {
+            let (mut height, mut node) = (0, leaf);
+            while matches!(peaks.last(), Some((peak_height, _)) if *peak_height == height) {
+                let (_, left_peak) = peaks.pop().expect("checked");
+                node = M::merge_with_height(&left_peak, &node, height + 1)?;
+                height += 1;
+            }
+            peaks.push((height, node));
+        }
+        let peaks_hashes: Vec<T> = peaks.into_iter().map(|(_, peak)| peak).collect();
+        bagging_peaks_hashes::<T, M>(&peaks_hashes, &mut { usize::MAX })
+    }
+
     pub fn verify(&self, root: T, leaves: Vec<(u64, T)>) -> Result<bool> {
         self.calculate_root(leaves)
             .map(|calculated_root| calculated_root == root)
     }
 
+    /// Same as `verify`, but takes the leaves lazily, so only the leaves under one peak are
+    /// kept in memory at a time. The leaves must be in ascending position order, returns
+    /// `Error::UnsortedLeaves` at the first leaf which isn't after the previous one, or the
+    /// first error of the leaves.
+    pub fn verify_streaming<I: Iterator<Item = Result<(u64, T)>>>(
+        &self,
+        root: T,
+        leaves: I,
+    ) -> Result<bool> {
+        let mut prev_pos = None;
+        let leaves = leaves.map(|leaf| {
+            let (pos, item) = leaf?;
+            if matches!(prev_pos, Some(prev_pos) if pos <= prev_pos) {
+                return Err(Error::UnsortedLeaves(pos));
+            }
+            prev_pos = Some(pos);
+            Ok((pos, item))
+        });
+        let mut ops = usize::MAX;
+        let peaks_hashes = calculate_peaks_hashes_lazily::<_, M, _, _>(
+            leaves,
+            self.mmr_size,
+            self.proof.iter(),
+            &mut ops,
+        )?;
+        let calculated_root = bagging_peaks_hashes::<T, M>(&peaks_hashes, &mut ops)?;
+        Ok(calculated_root == root)
+    }
+
+    /// Same as `verify`, but reports the calculated root and the leaves calculated into it,
+    /// so a mismatch can be told from a malformed proof, which is still an error.
+    pub fn verify_detailed(&self, root: T, leaves: Vec<(u64, T)>) -> Result<VerifyReport<T>> {
+        let mut leaf_positions: Vec<u64> = leaves.iter().map(|(pos, _)| *pos).collect();
+        leaf_positions.sort_unstable();
+        leaf_positions.dedup();
+        Ok(VerifyReport {
+            computed_root: self.calculate_root(leaves)?,
+            expected_root: root,
+            leaf_positions,
+        })
+    }
+
+    /// Same as `verify`, but the leaves are given by leaf index instead of position.
+    pub fn verify_by_index(&self, root: T, leaves: Vec<(u64, T)>) -> Result<bool> {
+        self.verify(
+            root,
+            leaves
+                .into_iter()
+                .map(|(index, leaf)| (leaf_index_to_pos(index), leaf))
+                .collect(),
+        )
+    }
+
+    /// Verifies a proof generated by `MMR::gen_range_proof`, `leaves` are the values from
+    /// `start_leaf_index` to `end_leaf_index` inclusive.
+    pub fn verify_range(
+        &self,
+        root: T,
+        start_leaf_index: u64,
+        end_leaf_index: u64,
+        leaves: Vec<T>,
+    ) -> Result<bool> {
+        if start_leaf_index > end_leaf_index
+            || leaves.len() as u64 != end_leaf_index - start_leaf_index + 1
+        {
+            return Ok(false);
+        }
+        self.verify(
+            root,
+            (start_leaf_index..)
+                .map(leaf_index_to_pos)
+                .zip(leaves)
+                .collect(),
+        )
+    }
+
+    /// Verifies the leaves and that they occupy consecutive leaf indices without gaps.
+    pub fn verify_consecutive(&self, root: T, leaves: Vec<(u64, T)>) -> Result<bool> {
+        let mut positions: Vec<u64> = leaves.iter().map(|(pos, _)| *pos).collect();
+        positions.sort_unstable();
+        positions.dedup();
+        if positions.iter().any(|pos| pos_height_in_tree(*pos) > 0) {
+            return Err(Error::NodeProofsNotSupported);
+        }
+        // a leaf is pushed when the mmr_size equals its position,
+        // so the leaf index is the leaves count of that size
+        let consecutive = positions
+            .windows(2)
+            .all(|pair| mmr_size_to_leaf_count(pair[0]) + 1 == mmr_size_to_leaf_count(pair[1]));
+        if !consecutive {
+            return Ok(false);
+        }
+        self.verify(root, leaves)
+    }
+
+    /// Verifies a proof generated by `MMR::gen_proof_with_format`
+    pub fn verify_with_format(
+        &self,
+        root: T,
+        leaves: Vec<(u64, T)>,
+        format: ProofFormat,
+    ) -> Result<bool> {
+        let calculated_root = match format {
+            ProofFormat::Ckb => self.calculate_root(leaves)?,
+            ProofFormat::Grin => self.calculate_grin_root(leaves)?,
+            ProofFormat::Ordered => self.calculate_ordered_root(leaves)?,
+        };
+        Ok(calculated_root == root)
+    }
+
+    // put the items back into the order of `gen_proof`, which is the order of the skeleton
+    fn calculate_ordered_root(&self, leaves: Vec<(u64, T)>) -> Result<T> {
+        let pos_list: Vec<u64> = leaves.iter().map(|(pos, _)| *pos).collect();
+        let skeleton = proof_skeleton(pos_list, self.mmr_size)?;
+        if skeleton.len() != self.proof.len() {
+            return Err(Error::CorruptedProof);
+        }
+        let mut order: Vec<usize> = (0..skeleton.len()).collect();
+        order.sort_unstable_by_key(|i| skeleton[*i].0);
+        let mut items: Vec<(usize, T)> =
+            order.into_iter().zip(self.proof.iter().cloned()).collect();
+        items.sort_unstable_by_key(|(i, _)| *i);
+        let proof = MerkleProof::<T, M>::new(
+            self.mmr_size,
+            items.into_iter().map(|(_, item)| item).collect(),
+        );
+        proof.calculate_root(leaves)
+    }
+
+    fn calculate_grin_root(&self, leaves: Vec<(u64, T)>) -> Result<T> {
+        if leaves.len() != 1 {
+            return Err(Error::CorruptedProof);
+        }
+        if pos_height_in_tree(leaves[0].0) > 0 {
+            return Err(Error::NodeProofsNotSupported);
+        }
+        let peaks = get_peaks(self.mmr_size);
+        let peak_index = peaks
+            .iter()
+            .position(|peak_pos| *peak_pos >= leaves[0].0)
+            .ok_or(Error::PositionOutOfRange)?;
+        let mut proof_iter = self.proof.iter();
+        let mut root = if leaves[0].0 == peaks[peak_index] {
+            leaves.into_iter().next().expect("checked").1
+        } else {
+            calculate_peak_root::<_, M, _>(
+                with_height(leaves),
+                peaks[peak_index],
+                &mut proof_iter,
+                &mut { usize::MAX },
+            )?
+        };
+        if peak_index + 1 < peaks.len() {
+            let rhs_peaks = proof_iter.next().ok_or(Error::ProofItemsExhausted)?;
+            root = M::merge_peaks(&root, rhs_peaks)?;
+        }
+        for _ in 0..peak_index {
+            let lhs_peak = proof_iter.next().ok_or(Error::ProofItemsExhausted)?;
+            root = M::merge_peaks(lhs_peak, &root)?;
+        }
+        // ensure nothing left in proof_iter
+        if proof_iter.next().is_some() {
+            return Err(Error::ProofItemsLeftover);
+        }
+        Ok(root)
+    }
+
+    /// Verifies the leaves and that the MMR contains exactly `expected_leaf_count` leaves,
+    /// so a prover can't hide later leaves behind a smaller mmr_size.
+    pub fn verify_with_count(
+        &self,
+        root: T,
+        leaves: Vec<(u64, T)>,
+        expected_leaf_count: u64,
+    ) -> Result<bool> {
+        if mmr_size_to_leaf_count(self.mmr_size) != expected_leaf_count {
+            return Ok(false);
+        }
+        self.verify(root, leaves)
+    }
+
+    /// Verifies against a commitment which binds the root with the mmr_size,
+    /// `commit_fn(root, mmr_size)` must produce the commitment from the calculated root.
+    pub fn verify_sized<F: Fn(&T, u64) -> Result<T>>(
+        &self,
+        commitment: T,
+        leaves: Vec<(u64, T)>,
+        commit_fn: F,
+    ) -> Result<bool> {
+        let calculated_root = self.calculate_root(leaves)?;
+        commit_fn(&calculated_root, self.mmr_size).map(|calculated| calculated == commitment)
+    }
+
+    /// Verifies the leaves against trusted peaks `(peak_pos, peak)` instead of a root.
+    ///
+    /// Only the peaks containing the leaves are calculated, so the proof may carry only the
+    /// sibling items of those peaks, a proof from `MMR::gen_proof` is accepted as well.
+    pub fn verify_against_peaks(
+        &self,
+        trusted_peaks: Vec<(u64, T)>,
+        mut leaves: Vec<(u64, T)>,
+    ) -> Result<bool> {
+        let layout = proof_skeleton(leaves.iter().map(|(pos, _)| *pos).collect(), self.mmr_size)?;
+        let is_sibling =
+            |role: &ItemRole| matches!(role, ItemRole::LeftSibling | ItemRole::RightSibling);
+        let siblings: Vec<&T> = if layout.len() == self.proof.len() {
+            self.proof
+                .iter()
+                .zip(&layout)
+                .filter(|(_, (_, role))| is_sibling(role))
+                .map(|(item, _)| item)
+                .collect()
+        } else if layout.iter().filter(|(_, role)| is_sibling(role)).count() == self.proof.len() {
+            self.proof.iter().collect()
+        } else {
+            return Err(Error::CorruptedProof);
+        };
+        let mut proof_iter = siblings.into_iter();
+
+        sort_unique_leaves(&mut leaves)?;
+        for peak_pos in get_peaks(self.mmr_size) {
+            let mut leaves = take_while_vec(&mut leaves, |(pos, _)| *pos <= peak_pos);
+            let peak_root = if leaves.is_empty() {
+                continue;
+            } else if leaves.len() == 1 && leaves[0].0 == peak_pos {
+                // leaf is the peak
+                leaves.remove(0).1
+            } else {
+                calculate_peak_root::<_, M, _>(
+                    with_height(leaves),
+                    peak_pos,
+                    &mut proof_iter,
+                    &mut { usize::MAX },
+                )?
+            };
+            match trusted_peaks.iter().find(|(pos, _)| *pos == peak_pos) {
+                Some((_, trusted_peak)) if *trusted_peak == peak_root => {}
+                _ => return Ok(false),
+            }
+        }
+        // ensure nothing left in proof_iter
+        if proof_iter.next().is_some() {
+            return Err(Error::ProofItemsLeftover);
+        }
+        Ok(true)
+    }
+
     /// Verifies a old root and all incremental leaves.
     ///
     /// If this method returns `true`, it means the following assertion are true:
@@ -332,7 +1641,7 @@ impl<T: Clone + PartialEq, M: Merge<Item = T>> MerkleProof<T, M> {
         reverse_peaks.reverse();
         prev_peaks.extend(reverse_peaks);
 
-        let calculated_prev_root = bagging_peaks_hashes::<T, M>(prev_peaks)?;
+        let calculated_prev_root = bagging_peaks_hashes::<T, M>(&prev_peaks, &mut { usize::MAX })?;
         if calculated_prev_root != prev_root {
             return Ok(false);
         }
@@ -350,18 +1659,114 @@ impl<T: Clone + PartialEq, M: Merge<Item = T>> MerkleProof<T, M> {
     }
 }
 
-fn calculate_peak_root<'a, T: 'a, M: Merge<Item = T>, I: Iterator<Item = &'a T>>(
-    leaves: Vec<(u64, T)>,
+/// Proves a MMR is an append-only extension of an older one, see `MMR::gen_consistency_proof`.
+///
+/// It carries the old peaks, which bag into the old root, and a proof of them as internal
+/// nodes of the new MMR.
+pub struct ConsistencyProof<T, M> {
+    old_mmr_size: u64,
+    old_peaks: Vec<T>,
+    proof: MerkleProof<T, M>,
+}
+
+impl<T: Clone + PartialEq, M: Merge<Item = T>> ConsistencyProof<T, M> {
+    pub fn old_mmr_size(&self) -> u64 {
+        self.old_mmr_size
+    }
+
+    pub fn new_mmr_size(&self) -> u64 {
+        self.proof.mmr_size()
+    }
+
+    pub fn old_peaks(&self) -> &[T] {
+        &self.old_peaks
+    }
+
+    pub fn proof(&self) -> &MerkleProof<T, M> {
+        &self.proof
+    }
+
+    /// Verifies the old peaks bag into `old_root` and are nodes of the MMR of `new_root`.
+    pub fn verify(&self, old_root: T, new_root: T) -> Result<bool> {
+        let old_peak_positions = get_peaks(self.old_mmr_size);
+        if self.old_mmr_size == 0
+            || !is_valid_mmr_size(self.old_mmr_size)
+            || self.old_mmr_size > self.new_mmr_size()
+            || old_peak_positions.len() != self.old_peaks.len()
+        {
+            return Err(Error::CorruptedProof);
+        }
+        let calculated_old_root =
+            bagging_peaks_hashes::<T, M>(&self.old_peaks, &mut { usize::MAX })?;
+        if calculated_old_root != old_root {
+            return Ok(false);
+        }
+        self.proof.verify(
+            new_root,
+            old_peak_positions
+                .into_iter()
+                .zip(self.old_peaks.iter().cloned())
+                .collect(),
+        )
+    }
+}
+
+/// Proves a leaf index isn't pushed yet, see `MMR::gen_non_membership_proof`.
+///
+/// MMRs are append-only, so it carries the peaks, which prove the mmr_size by bagging into
+/// the root.
+pub struct NonMembershipProof<T, M> {
+    leaf_index: u64,
+    mmr_size: u64,
+    peaks: Vec<T>,
+    merge: PhantomData<M>,
+}
+
+impl<T: Clone + PartialEq, M: Merge<Item = T>> NonMembershipProof<T, M> {
+    pub fn leaf_index(&self) -> u64 {
+        self.leaf_index
+    }
+
+    pub fn mmr_size(&self) -> u64 {
+        self.mmr_size
+    }
+
+    pub fn peaks(&self) -> &[T] {
+        &self.peaks
+    }
+
+    /// Verifies the leaf is beyond the mmr_size and the peaks bag into `root`.
+    pub fn verify(&self, root: T) -> Result<bool> {
+        if self.mmr_size == 0
+            || !is_valid_mmr_size(self.mmr_size)
+            || iter_peaks(self.mmr_size).count() != self.peaks.len()
+        {
+            return Err(Error::CorruptedProof);
+        }
+        // same as `leaf_index_to_pos(leaf_index) >= mmr_size` without overflowing
+        if self.leaf_index < mmr_size_to_leaf_count(self.mmr_size) {
+            return Ok(false);
+        }
+        let calculated_root = bagging_peaks_hashes::<T, M>(&self.peaks, &mut { usize::MAX })?;
+        Ok(calculated_root == root)
+    }
+}
+
+/// Calculates the root of a peak from `(position, hash, height)` nodes under it, leaves are
+/// at height 0 and interior nodes can be seeded at their height.
+pub(crate) fn calculate_peak_root<'a, T: 'a, M: Merge<Item = T>, I: Iterator<Item = &'a T>>(
+    nodes: Vec<(u64, T, u8)>,
     peak_pos: u64,
     proof_iter: &mut I,
+    ops: &mut usize,
 ) -> Result<T> {
-    debug_assert!(!leaves.is_empty(), "can't be empty");
-    // (position, hash, height)
-
-    let mut queue: VecDeque<_> = leaves
-        .into_iter()
-        .map(|(pos, item)| (pos, item, 0))
-        .collect();
+    debug_assert!(!nodes.is_empty(), "can't be empty");
+    // (position, hash, height), ordered by height then position,
+    // so a node's sibling is always in front of it if known
+    let mut queue: VecDeque<_> = nodes.into_iter().collect();
+    queue
+        .make_contiguous()
+        .sort_by_key(|(pos, _, height)| (*height, *pos));
 
     // calculate tree root from each items
     while let Some((pos, item, height)) = queue.pop_front() {
@@ -370,9 +1775,11 @@ fn calculate_peak_root<'a, T: 'a, M: Merge<Item = T>, I: Iterator<Item = &'a T>>
                 // return root once queue is consumed
                 return Ok(item);
             } else {
-                return Err(Error::CorruptedProof);
+                // the peak is calculated before other nodes under it
+                return Err(Error::UnexpectedPeakRoot);
             }
         }
+        spend_merge_op(ops)?;
         // calculate sibling
         let next_height = pos_height_in_tree(pos + 1);
         let (parent_pos, parent_item) = {
@@ -383,10 +1790,10 @@ fn calculate_peak_root<'a, T: 'a, M: Merge<Item = T>, I: Iterator<Item = &'a T>>
                 let parent_pos = pos + 1;
                 let parent_item = if Some(&sib_pos) == queue.front().map(|(pos, _, _)| pos) {
                     let sibling_item = queue.pop_front().map(|(_, item, _)| item).unwrap();
-                    M::merge(&sibling_item, &item)?
+                    M::merge_with_height(&sibling_item, &item, height + 1)?
                 } else {
-                    let sibling_item = proof_iter.next().ok_or(Error::CorruptedProof)?;
-                    M::merge(sibling_item, &item)?
+                    let sibling_item = proof_iter.next().ok_or(Error::ProofItemsExhausted)?;
+                    M::merge_with_height(sibling_item, &item, height + 1)?
                 };
                 (parent_pos, parent_item)
             } else {
@@ -395,45 +1802,92 @@ fn calculate_peak_root<'a, T: 'a, M: Merge<Item = T>, I: Iterator<Item = &'a T>>
                 let parent_pos = pos + parent_offset(height);
                 let parent_item = if Some(&sib_pos) == queue.front().map(|(pos, _, _)| pos) {
                     let sibling_item = queue.pop_front().map(|(_, item, _)| item).unwrap();
-                    M::merge(&item, &sibling_item)?
+                    M::merge_with_height(&item, &sibling_item, height + 1)?
                 } else {
-                    let sibling_item = proof_iter.next().ok_or(Error::CorruptedProof)?;
-                    M::merge(&item, sibling_item)?
+                    let sibling_item = proof_iter.next().ok_or(Error::ProofItemsExhausted)?;
+                    M::merge_with_height(&item, sibling_item, height + 1)?
                 };
                 (parent_pos, parent_item)
             }
         };
 
         if parent_pos <= peak_pos {
-            queue.push_back((parent_pos, parent_item, height + 1))
+            // with leaves only, this is always the back of the queue
+            let parent_height = height + 1;
+            let index = queue
+                .partition_point(|(pos, _, height)| (*height, *pos) < (parent_height, parent_pos));
+            if queue.get(index).map(|(pos, _, _)| *pos) == Some(parent_pos) {
+                // a node is given along with its descendants
+                return Err(Error::CorruptedProof);
+            }
+            queue.insert(index, (parent_pos, parent_item, parent_height))
         } else {
-            return Err(Error::CorruptedProof);
+            return Err(Error::PositionOutOfRange);
         }
     }
     Err(Error::CorruptedProof)
 }
 
-fn calculate_peaks_hashes<'a, T: 'a + Clone, M: Merge<Item = T>, I: Iterator<Item = &'a T>>(
+// seed nodes at their height for `calculate_peak_root`
+fn with_height<T>(nodes: Vec<(u64, T)>) -> Vec<(u64, T, u8)> {
+    nodes
+        .into_iter()
+        .map(|(pos, item)| (pos, item, pos_height_in_tree(pos)))
+        .collect()
+}
+
+fn calculate_peaks_hashes<
+    'a,
+    T: 'a + Clone + PartialEq,
+    M: Merge<Item = T>,
+    I: Iterator<Item = &'a T>,
+>(
     mut leaves: Vec<(u64, T)>,
     mmr_size: u64,
-    mut proof_iter: I,
+    proof_iter: I,
+    ops: &mut usize,
 ) -> Result<Vec<T>> {
-    if leaves.iter().any(|(pos, _)| pos_height_in_tree(*pos) > 0) {
-        return Err(Error::NodeProofsNotSupported);
-    }
-
     // special handle the only 1 leaf MMR
     if mmr_size == 1 && leaves.len() == 1 && leaves[0].0 == 0 {
         return Ok(leaves.into_iter().map(|(_pos, item)| item).collect());
     }
-    // ensure leaves are sorted and unique
-    leaves.sort_by_key(|(pos, _)| *pos);
-    leaves.dedup_by(|a, b| a.0 == b.0);
+    sort_unique_leaves(&mut leaves)?;
+    calculate_peaks_hashes_lazily::<_, M, _, _>(
+        leaves.into_iter().map(Ok),
+        mmr_size,
+        proof_iter,
+        ops,
+    )
+}
+
+// same as `calculate_peaks_hashes`, the leaves must be in ascending position order and only
+// the leaves under one peak are taken at a time
+fn calculate_peaks_hashes_lazily<
+    'a,
+    T: 'a + Clone,
+    M: Merge<Item = T>,
+    L: Iterator<Item = Result<(u64, T)>>,
+    I: Iterator<Item = &'a T>,
+>(
+    leaves: L,
+    mmr_size: u64,
+    mut proof_iter: I,
+    ops: &mut usize,
+) -> Result<Vec<T>> {
+    let mut leaves = leaves.peekable();
     let peaks = get_peaks(mmr_size);
 
     let mut peaks_hashes: Vec<T> = Vec::with_capacity(peaks.len() + 1);
     for peak_pos in peaks {
-        let mut leaves: Vec<_> = take_while_vec(&mut leaves, |(pos, _)| *pos <= peak_pos);
+        let mut peak_leaves = Vec::new();
+        loop {
+            match leaves.peek() {
+                None => break,
+                Some(Ok((pos, _))) if *pos > peak_pos => break,
+                _ => peak_leaves.push(leaves.next().expect("peeked")?),
+            }
+        }
+        let mut leaves = peak_leaves;
         let peak_root = if leaves.len() == 1 && leaves[0].0 == peak_pos {
             // leaf is the peak
             leaves.remove(0).1
@@ -447,14 +1901,14 @@ fn calculate_peaks_hashes<'a, T: 'a + Clone, M: Merge<Item = T>, I: Iterator<Ite
                 break;
             }
         } else {
-            calculate_peak_root::<_, M, _>(leaves, peak_pos, &mut proof_iter)?
+            calculate_peak_root::<_, M, _>(with_height(leaves), peak_pos, &mut proof_iter, ops)?
         };
         peaks_hashes.push(peak_root.clone());
     }
 
     // ensure nothing left in leaves
-    if !leaves.is_empty() {
-        return Err(Error::CorruptedProof);
+    if leaves.next().transpose()?.is_some() {
+        return Err(Error::PositionOutOfRange);
     }
 
     // check rhs peaks
@@ -463,40 +1917,136 @@ fn calculate_peaks_hashes<'a, T: 'a + Clone, M: Merge<Item = T>, I: Iterator<Ite
     }
     // ensure nothing left in proof_iter
     if proof_iter.next().is_some() {
-        return Err(Error::CorruptedProof);
+        return Err(Error::ProofItemsLeftover);
     }
     Ok(peaks_hashes)
 }
 
-fn bagging_peaks_hashes<T, M: Merge<Item = T>>(mut peaks_hashes: Vec<T>) -> Result<T> {
+pub(crate) fn bagging_peaks_hashes<T: Clone + PartialEq, M: Merge<Item = T>>(
+    peaks_hashes: &[T],
+    ops: &mut usize,
+) -> Result<T> {
     // bagging peaks
-    // bagging from right to left via hash(right, left).
-    while peaks_hashes.len() > 1 {
-        let right_peak = peaks_hashes.pop().expect("pop");
-        let left_peak = peaks_hashes.pop().expect("pop");
-        peaks_hashes.push(M::merge_peaks(&right_peak, &left_peak)?);
+    // bagging from right to left via `Merge::bag(right, left)`.
+    let (right_peak, lhs_peaks) = peaks_hashes.split_last().ok_or(Error::CorruptedProof)?;
+    let mut lhs_peaks = lhs_peaks.iter().rev();
+    // fold into a single accumulator, the only clone is a lonely peak
+    let mut root = match lhs_peaks.next() {
+        Some(left_peak) => {
+            spend_merge_op(ops)?;
+            M::bag(right_peak, left_peak)?
+        }
+        None => return Ok(right_peak.clone()),
+    };
+    for left_peak in lhs_peaks {
+        spend_merge_op(ops)?;
+        root = M::bag(&root, left_peak)?;
     }
-    peaks_hashes.pop().ok_or(Error::CorruptedProof)
+    Ok(root)
+}
+
+// panic in debug builds if `M::PEAKS_DISTINCT` doesn't hold for the samples, an error of the
+// sample merges is left to the real ones
+fn debug_check_peaks_distinct<M: Merge>(a: &M::Item, b: &M::Item)
+where
+    M::Item: PartialEq,
+{
+    debug_assert!(
+        crate::merge::check_peaks_distinct::<M>(a, b) != Ok(false),
+        "Merge::PEAKS_DISTINCT is set but merge_peaks is the same as merge"
+    );
 }
 
 /// merkle proof
 /// 1. sort items by position
 /// 2. calculate root of each peak
 /// 3. bagging peaks
-fn calculate_root<'a, T: 'a + Clone, M: Merge<Item = T>, I: Iterator<Item = &'a T>>(
+fn calculate_root<'a, T: 'a + Clone + PartialEq, M: Merge<Item = T>, I: Iterator<Item = &'a T>>(
     leaves: Vec<(u64, T)>,
     mmr_size: u64,
     proof_iter: I,
+    max_ops: usize,
 ) -> Result<T> {
-    let peaks_hashes = calculate_peaks_hashes::<_, M, _>(leaves, mmr_size, proof_iter)?;
-    bagging_peaks_hashes::<_, M>(peaks_hashes)
+    let mut ops = max_ops;
+    let peaks_hashes = calculate_peaks_hashes::<_, M, _>(leaves, mmr_size, proof_iter, &mut ops)?;
+    bagging_peaks_hashes::<_, M>(&peaks_hashes, &mut ops)
+}
+
+// bagging from right to left via hash(left, right), as Grin does
+fn bag_peaks_grin<T, M: Merge<Item = T>>(mut peaks: Vec<T>) -> Result<Option<T>> {
+    let mut root = match peaks.pop() {
+        Some(peak) => peak,
+        None => return Ok(None),
+    };
+    while let Some(left_peak) = peaks.pop() {
+        root = M::merge_peaks(&left_peak, &root)?;
+    }
+    Ok(Some(root))
+}
+
+// returns `Error::CapacityExceeded` if pushing `new_leaves` exceeds `max_leaves`
+pub(crate) fn check_capacity(
+    max_leaves: Option<u64>,
+    mmr_size: u64,
+    new_leaves: u64,
+) -> Result<()> {
+    match max_leaves {
+        Some(max_leaves) if mmr_size_to_leaf_count(mmr_size) + new_leaves > max_leaves => {
+            Err(Error::CapacityExceeded)
+        }
+        _ => Ok(()),
+    }
+}
+
+// consume one merge operation from the remaining budget
+fn spend_merge_op(ops: &mut usize) -> Result<()> {
+    *ops = ops.checked_sub(1).ok_or(Error::ProofBudgetExceeded)?;
+    Ok(())
+}
+
+// positions of the largest subtrees covering the leaves from `start` to `end` inclusive
+fn leaf_range_nodes(start: u64, end: u64) -> Vec<u64> {
+    let mut nodes = Vec::new();
+    let mut index = start;
+    while index <= end {
+        // a subtree of height h starts at a multiple of 2^h
+        let mut height = 0;
+        while height < index.trailing_zeros() && index + (2 << height) - 1 <= end {
+            height += 1;
+        }
+        let last_leaf = index + (1 << height) - 1;
+        // the root follows the right most leaf of the subtree
+        nodes.push(leaf_index_to_pos(last_leaf) + height as u64);
+        index = last_leaf + 1;
+    }
+    nodes
 }
 
-fn take_while_vec<T, P: Fn(&T) -> bool>(v: &mut Vec<T>, p: P) -> Vec<T> {
-    for i in 0..v.len() {
-        if !p(&v[i]) {
-            return v.drain(..i).collect();
+// sort the leaves by position and drop repeated entries, an entry repeating a position with
+// another value fails with `ConflictingLeafValues`
+fn sort_unique_leaves<T: PartialEq>(leaves: &mut Vec<(u64, T)>) -> Result<()> {
+    leaves.sort_by_key(|(pos, _)| *pos);
+    if let Some(pair) = leaves
+        .windows(2)
+        .find(|pair| pair[0].0 == pair[1].0 && pair[0].1 != pair[1].1)
+    {
+        return Err(Error::ConflictingLeafValues(pair[0].0));
+    }
+    leaves.dedup_by(|a, b| a.0 == b.0);
+    Ok(())
+}
+
+// merge the node at `pos` from the leaves under it
+fn calculate_node_from_leaves<T, M: Merge<Item = T>, F: Fn(u64) -> Result<Option<T>>>(
+    pos: u64,
+    leaf_provider: &F,
+) -> Result<T> {
+    match children_pos(pos) {
+        None => leaf_provider(pos)?.ok_or(Error::InconsistentStore),
+        Some((left, right)) => {
+            let left = calculate_node_from_leaves::<_, M, _>(left, leaf_provider)?;
+            let right = calculate_node_from_leaves::<_, M, _>(right, leaf_provider)?;
+            M::merge_with_height(&left, &right, pos_height_in_tree(pos))
         }
     }
-    v.drain(..).collect()
 }
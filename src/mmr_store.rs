@@ -1,8 +1,11 @@
-use crate::{vec::Vec, Result};
+use crate::{vec::Vec, Error, Result};
+use core::ops::Range;
 
 #[derive(Default)]
 pub struct MMRBatch<Elem, Store> {
     memory_batch: Vec<(u64, Vec<Elem>)>,
+    // overwrites of committed elements, applied after the segments by `commit`
+    memory_updates: Vec<(u64, Elem)>,
     store: Store,
 }
 
@@ -10,6 +13,7 @@ impl<Elem, Store> MMRBatch<Elem, Store> {
     pub fn new(store: Store) -> Self {
         MMRBatch {
             memory_batch: Vec::new(),
+            memory_updates: Vec::new(),
             store,
         }
     }
@@ -21,29 +25,156 @@ impl<Elem, Store> MMRBatch<Elem, Store> {
     pub fn store(&self) -> &Store {
         &self.store
     }
+
+    pub fn store_mut(&mut self) -> &mut Store {
+        &mut self.store
+    }
+
+    /// Position of the first staged element, `None` if nothing is staged
+    pub fn first_staged_pos(&self) -> Option<u64> {
+        self.memory_batch.first().map(|(pos, _)| *pos)
+    }
+
+    // take all staged segments, for committing to a store without `MMRStoreWriteOps`
+    #[cfg(feature = "async")]
+    pub(crate) fn take_staged(&mut self) -> Vec<(u64, Vec<Elem>)> {
+        core::mem::take(&mut self.memory_batch)
+    }
+
+    /// Drop the staged elements and updates at `pos` or after
+    pub fn discard_from(&mut self, pos: u64) {
+        self.memory_updates
+            .retain(|(update_pos, _)| *update_pos < pos);
+        self.memory_batch
+            .retain(|(start_pos, elems)| *start_pos < pos && !elems.is_empty());
+        if let Some((start_pos, elems)) = self.memory_batch.last_mut() {
+            elems.truncate((pos - *start_pos) as usize);
+        }
+    }
 }
 
-impl<Elem: Clone, Store: MMRStoreReadOps<Elem>> MMRBatch<Elem, Store> {
-    pub fn get_elem(&self, pos: u64) -> Result<Option<Elem>> {
+impl<Elem: Clone, Store> MMRBatch<Elem, Store> {
+    // the staged element at `pos`, `None` if it's not staged
+    pub(crate) fn get_staged_elem(&self, pos: u64) -> Option<Elem> {
+        if let Some((_, elem)) = self
+            .memory_updates
+            .iter()
+            .find(|(update_pos, _)| *update_pos == pos)
+        {
+            return Some(elem.clone());
+        }
         for (start_pos, elems) in self.memory_batch.iter().rev() {
             if pos < *start_pos {
                 continue;
             } else if pos < start_pos + elems.len() as u64 {
-                return Ok(elems.get((pos - start_pos) as usize).cloned());
+                return elems.get((pos - start_pos) as usize).cloned();
             } else {
                 break;
             }
         }
-        self.store.get_elem(pos)
+        None
+    }
+
+    // replace the staged element at `pos`, gives `elem` back if `pos` isn't staged
+    pub(crate) fn replace_staged_elem(&mut self, pos: u64, elem: Elem) -> Option<Elem> {
+        for (start_pos, elems) in self.memory_batch.iter_mut().rev() {
+            if pos < *start_pos {
+                continue;
+            } else if let Some(staged) = elems.get_mut((pos - *start_pos) as usize) {
+                *staged = elem;
+                return None;
+            } else {
+                break;
+            }
+        }
+        Some(elem)
+    }
+
+    // replace the element at `pos`, a committed one is overwritten in the store by `commit`
+    pub(crate) fn stage_update(&mut self, pos: u64, elem: Elem) {
+        if let Some(elem) = self.replace_staged_elem(pos, elem) {
+            match self
+                .memory_updates
+                .iter_mut()
+                .find(|(update_pos, _)| *update_pos == pos)
+            {
+                Some((_, staged)) => *staged = elem,
+                None => self.memory_updates.push((pos, elem)),
+            }
+        }
+    }
+}
+
+impl<Elem: Clone, Store: MMRStoreReadOps<Elem>> MMRBatch<Elem, Store> {
+    pub fn get_elem(&self, pos: u64) -> Result<Option<Elem>> {
+        match self.get_staged_elem(pos) {
+            Some(elem) => Ok(Some(elem)),
+            None => self.store.get_elem(pos),
+        }
+    }
+
+    /// Same as `get_elem` for each position, the elements which aren't staged are read from the
+    /// store by a single `get_elems`.
+    pub fn get_elems(&self, positions: &[u64]) -> Result<Vec<Option<Elem>>> {
+        let mut elems: Vec<Option<Elem>> = positions
+            .iter()
+            .map(|pos| self.get_staged_elem(*pos))
+            .collect();
+        let missing: Vec<u64> = positions
+            .iter()
+            .zip(&elems)
+            .filter(|(_, elem)| elem.is_none())
+            .map(|(pos, _)| *pos)
+            .collect();
+        if missing.is_empty() {
+            return Ok(elems);
+        }
+        let mut stored = self.store.get_elems(&missing)?.into_iter();
+        for elem in elems.iter_mut().filter(|elem| elem.is_none()) {
+            *elem = stored.next().flatten();
+        }
+        Ok(elems)
     }
 }
 
 impl<Elem, Store: MMRStoreWriteOps<Elem>> MMRBatch<Elem, Store> {
-    pub fn commit(&mut self) -> Result<()> {
+    /// Append the staged segments to the store, then overwrite the updated elements by
+    /// `MMRStoreWriteOps::update`, returns the count of persisted positions.
+    pub fn commit(&mut self) -> Result<u64> {
+        let mut count = 0;
         for (pos, elems) in self.memory_batch.drain(..) {
+            count += elems.len() as u64;
             self.store.append(pos, elems)?;
         }
-        Ok(())
+        for (pos, elem) in self.memory_updates.drain(..) {
+            count += 1;
+            self.store.update(pos, elem)?;
+        }
+        Ok(count)
+    }
+
+    /// Commit the staged segments which end at or before `pos`, later segments stay pending.
+    /// Returns the count of persisted positions, the same as `commit`.
+    pub fn commit_up_to(&mut self, pos: u64) -> Result<u64> {
+        let segments = self
+            .memory_batch
+            .iter()
+            .take_while(|(start_pos, elems)| start_pos + elems.len() as u64 <= pos + 1)
+            .count();
+        let mut count = 0;
+        for (pos, elems) in self.memory_batch.drain(..segments) {
+            count += elems.len() as u64;
+            self.store.append(pos, elems)?;
+        }
+        let (updates, pending) = core::mem::take(&mut self.memory_updates)
+            .into_iter()
+            .partition(|(update_pos, _)| *update_pos <= pos);
+        self.memory_updates = pending;
+        for (pos, elem) in updates {
+            count += 1;
+            self.store.update(pos, elem)?;
+        }
+        Ok(count)
     }
 }
 
@@ -56,10 +187,61 @@ impl<Elem, Store> IntoIterator for MMRBatch<Elem, Store> {
     }
 }
 
+#[allow(clippy::len_without_is_empty)]
 pub trait MMRStoreReadOps<Elem> {
     fn get_elem(&self, pos: u64) -> Result<Option<Elem>>;
+
+    /// Read the elements at `positions` in order, stores with multi-get reads can override the
+    /// default, which calls `get_elem` for each position.
+    fn get_elems(&self, positions: &[u64]) -> Result<Vec<Option<Elem>>> {
+        positions.iter().map(|pos| self.get_elem(*pos)).collect()
+    }
+
+    /// The max stored position + 1, or 0 if empty, which is the mmr_size of a complete store.
+    /// Stores which can't tell can leave the default, see `MMR::resume`.
+    fn len(&self) -> Result<u64> {
+        Err(Error::StoreError("len is not supported".into()))
+    }
 }
 
 pub trait MMRStoreWriteOps<Elem> {
+    /// Append elements from `pos`, which must be the current length of the store.
+    /// Stores may return `Error::NonContiguousAppend` when it isn't, `MemStore` does so with
+    /// the `debug-checks` feature.
     fn append(&mut self, pos: u64, elems: Vec<Elem>) -> Result<()>;
+
+    /// Remove the element at `pos`, append-only stores can leave the default.
+    fn delete(&mut self, _pos: u64) -> Result<()> {
+        Err(Error::StoreError("delete is not supported".into()))
+    }
+
+    /// Remove the elements of `range`, the default deletes them one by one from the end, so
+    /// stores which only delete their last position work as well.
+    fn delete_range(&mut self, range: Range<u64>) -> Result<()> {
+        for pos in range.rev() {
+            self.delete(pos)?;
+        }
+        Ok(())
+    }
+
+    /// Overwrite the element at `pos`, append-only stores can leave the default.
+    fn update(&mut self, _pos: u64, _elem: Elem) -> Result<()> {
+        Err(Error::StoreError("update is not supported".into()))
+    }
+}
+
+/// Same as `MMRStoreReadOps` for stores which are read asynchronously, enabled by `async`.
+// the futures aren't required to be `Send`, so single threaded executors can use `!Send` stores
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait MMRStoreReadOpsAsync<Elem> {
+    async fn get_elem(&self, pos: u64) -> Result<Option<Elem>>;
+}
+
+/// Same as `MMRStoreWriteOps` for stores which are written asynchronously, enabled by `async`.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait MMRStoreWriteOpsAsync<Elem> {
+    /// Append elements from `pos`, which must be the current length of the store.
+    async fn append(&mut self, pos: u64, elems: Vec<Elem>) -> Result<()>;
 }
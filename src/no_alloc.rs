@@ -0,0 +1,196 @@
+//! Verification without heap allocation, for embedded verifiers.
+
+use crate::helper::{iter_peaks, parent_offset, pos_height_in_tree, sibling_offset};
+use crate::{Error, Merge, Result};
+
+/// Verifies the proof of a single leaf, `proof_items` are the items of a proof generated by
+/// `MMR::gen_proof(vec![pos])`, nothing is allocated.
+///
+/// The items are lhs peaks, then the path to the peak, then one rhs item, so the root is
+/// calculated by folding the items from both ends with a single accumulator.
+pub fn verify_no_alloc<T: Clone + PartialEq, M: Merge<Item = T>>(
+    proof_items: &[T],
+    root: &T,
+    mmr_size: u64,
+    pos: u64,
+    leaf: T,
+) -> Result<bool> {
+    if pos_height_in_tree(pos) > 0 {
+        return Err(Error::NodeProofsNotSupported);
+    }
+    let mut peaks = iter_peaks(mmr_size);
+    let peak_pos = peaks
+        .by_ref()
+        .find(|peak_pos| *peak_pos >= pos)
+        .ok_or(Error::PositionOutOfRange)?;
+    let has_rhs = peaks.next().is_some();
+    let lhs_count = iter_peaks(mmr_size)
+        .take_while(|lhs_pos| *lhs_pos < peak_pos)
+        .count();
+    if proof_items.len() < lhs_count {
+        return Err(Error::ProofItemsExhausted);
+    }
+    let (lhs_peaks, items) = proof_items.split_at(lhs_count);
+    let mut items = items.iter();
+
+    // calculate the peak root from the leaf
+    let mut peak_root = leaf;
+    let mut pos = pos;
+    let mut height = 0;
+    while pos < peak_pos {
+        let sibling = items.next().ok_or(Error::ProofItemsExhausted)?;
+        if pos_height_in_tree(pos + 1) > height {
+            // implies pos is right sibling
+            peak_root = M::merge_with_height(sibling, &peak_root, height + 1)?;
+            pos += 1;
+        } else {
+            // pos is left sibling
+            peak_root = M::merge_with_height(&peak_root, sibling, height + 1)?;
+            pos += parent_offset(height);
+        }
+        height += 1;
+    }
+    if pos != peak_pos {
+        return Err(Error::CorruptedProof);
+    }
+
+    // bagging from right to left via `Merge::bag(right, left)`.
+    let mut calculated_root = match items.next() {
+        Some(rhs_peaks) if has_rhs => M::bag(rhs_peaks, &peak_root)?,
+        None if !has_rhs => peak_root,
+        Some(_) => return Err(Error::ProofItemsLeftover),
+        None => return Err(Error::ProofItemsExhausted),
+    };
+    // ensure nothing left in items
+    if items.next().is_some() {
+        return Err(Error::ProofItemsLeftover);
+    }
+    for lhs_peak in lhs_peaks.iter().rev() {
+        calculated_root = M::bag(&calculated_root, lhs_peak)?;
+    }
+    Ok(&calculated_root == root)
+}
+
+/// Verifies the proof of the `(position, leaf)` of several leaves in ascending position order,
+/// `proof_items` are the items of a proof generated by `MMR::gen_proof` of the positions. The
+/// nodes are kept in a fixed stack of `MAX` slots, nothing is allocated.
+///
+/// The stack holds the calculated peaks followed by a layer of the nodes under the current
+/// peak, so `MAX` must be at least the count of peaks plus the count of leaves under any one
+/// peak, otherwise `Error::CorruptedStack` is returned. Returns `Error::UnsortedLeaves` if the
+/// leaves aren't in ascending order, or the errors of `MerkleProof::verify` otherwise.
+pub fn verify_leaves_no_alloc<const MAX: usize, T: Clone + PartialEq, M: Merge<Item = T>>(
+    proof_items: &[T],
+    root: &T,
+    mmr_size: u64,
+    leaves: &[(u64, T)],
+) -> Result<bool> {
+    let mut prev_pos = None;
+    for (pos, _) in leaves {
+        if pos_height_in_tree(*pos) > 0 {
+            return Err(Error::NodeProofsNotSupported);
+        }
+        if matches!(prev_pos, Some(prev_pos) if *pos <= prev_pos) {
+            return Err(Error::UnsortedLeaves(*pos));
+        }
+        prev_pos = Some(*pos);
+    }
+
+    let mut stack: [Option<(u64, T)>; MAX] = core::array::from_fn(|_| None);
+    let mut items = proof_items.iter();
+    let mut leaves = leaves.iter().peekable();
+    // the peaks calculated so far are at the bottom of the stack
+    let mut peak_count = 0;
+    for peak_pos in iter_peaks(mmr_size) {
+        let mut layer_len = 0;
+        while let Some((pos, leaf)) = leaves.next_if(|(pos, _)| *pos <= peak_pos) {
+            let slot = stack
+                .get_mut(peak_count + layer_len)
+                .ok_or(Error::CorruptedStack)?;
+            *slot = Some((*pos, leaf.clone()));
+            layer_len += 1;
+        }
+        let peak_root = if layer_len == 0 {
+            // a lhs peak, or the bagged rhs peaks after the last peak with leaves
+            match items.next() {
+                Some(peak_root) => peak_root.clone(),
+                None => break,
+            }
+        } else {
+            let layer = &mut stack[peak_count..peak_count + layer_len];
+            calculate_layers::<_, M, _>(layer, peak_pos, &mut items)?
+        };
+        *stack.get_mut(peak_count).ok_or(Error::CorruptedStack)? = Some((peak_pos, peak_root));
+        peak_count += 1;
+    }
+    // ensure nothing left in leaves
+    if leaves.next().is_some() {
+        return Err(Error::PositionOutOfRange);
+    }
+    if let Some(rhs_peaks) = items.next() {
+        *stack.get_mut(peak_count).ok_or(Error::CorruptedStack)? =
+            Some((mmr_size, rhs_peaks.clone()));
+        peak_count += 1;
+    }
+    // ensure nothing left in items
+    if items.next().is_some() {
+        return Err(Error::ProofItemsLeftover);
+    }
+
+    // bagging from right to left via `Merge::bag(right, left)`.
+    let mut peaks = stack[..peak_count]
+        .iter_mut()
+        .rev()
+        .map(|slot| slot.take().expect("filled").1);
+    let mut calculated_root = peaks.next().ok_or(Error::CorruptedProof)?;
+    for lhs_peak in peaks {
+        calculated_root = M::bag(&calculated_root, &lhs_peak)?;
+    }
+    Ok(&calculated_root == root)
+}
+
+// merge the nodes of `layer`, in ascending position order and at the same height, layer by
+// layer into the peak at `peak_pos`, the parents are written over the merged nodes
+fn calculate_layers<'a, T: 'a, M: Merge<Item = T>, I: Iterator<Item = &'a T>>(
+    layer: &mut [Option<(u64, T)>],
+    peak_pos: u64,
+    items: &mut I,
+) -> Result<T> {
+    let mut len = layer.len();
+    let mut height = 0;
+    loop {
+        if len == 1 && matches!(&layer[0], Some((pos, _)) if *pos == peak_pos) {
+            return Ok(layer[0].take().expect("filled").1);
+        }
+        let (mut read, mut write) = (0, 0);
+        while read < len {
+            let (pos, node) = layer[read].take().expect("filled");
+            read += 1;
+            let (parent_pos, parent) = if pos_height_in_tree(pos + 1) > height {
+                // pos is right sibling, its left sibling isn't in the layer
+                let sibling = items.next().ok_or(Error::ProofItemsExhausted)?;
+                (pos + 1, M::merge_with_height(sibling, &node, height + 1)?)
+            } else {
+                // pos is left sibling, the right one is either the next node or a proof item
+                let sibling_pos = pos + sibling_offset(height);
+                let parent = if matches!(layer.get(read), Some(Some((next_pos, _))) if *next_pos == sibling_pos)
+                {
+                    let (_, sibling) = layer[read].take().expect("filled");
+                    read += 1;
+                    M::merge_with_height(&node, &sibling, height + 1)?
+                } else {
+                    let sibling = items.next().ok_or(Error::ProofItemsExhausted)?;
+                    M::merge_with_height(&node, sibling, height + 1)?
+                };
+                (pos + parent_offset(height), parent)
+            };
+            if parent_pos > peak_pos {
+                return Err(Error::PositionOutOfRange);
+            }
+            layer[write] = Some((parent_pos, parent));
+            write += 1;
+        }
+        len = write;
+        height += 1;
+    }
+}
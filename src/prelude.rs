@@ -0,0 +1,41 @@
+//! The common API surface, `use ckb_merkle_mountain_range::prelude::*;` is enough to build an
+//! MMR, generate proofs and verify them.
+//!
+//! ```
+//! use ckb_merkle_mountain_range::prelude::*;
+//!
+//! struct MergeU64;
+//!
+//! impl Merge for MergeU64 {
+//!     type Item = u64;
+//!     fn merge(lhs: &u64, rhs: &u64) -> Result<u64> {
+//!         Ok(lhs.wrapping_mul(31).wrapping_add(*rhs))
+//!     }
+//! }
+//!
+//! let store = MemStore::default();
+//! let mut mmr = MemMMR::<_, MergeU64>::new(0, &store);
+//! for i in 0..11 {
+//!     mmr.push(i).unwrap();
+//! }
+//! assert_eq!(mmr.mmr_size(), leaf_index_to_mmr_size(10));
+//! let root = mmr.get_root().unwrap();
+//! let pos = leaf_index_to_pos(5);
+//! let proof: MerkleProof<u64, MergeU64> = mmr.gen_proof(vec![pos]).unwrap();
+//! mmr.commit().unwrap();
+//! assert_eq!((&store).get_elem(pos).unwrap(), Some(5));
+//! assert!(proof.verify(root, vec![(pos, 5)]).unwrap());
+//! ```
+
+#[doc(inline)]
+pub use crate::error::{Error, Result};
+#[doc(inline)]
+pub use crate::helper::{leaf_index_to_mmr_size, leaf_index_to_pos};
+#[doc(inline)]
+pub use crate::merge::Merge;
+#[doc(inline)]
+pub use crate::mmr::{MerkleProof, MMR};
+#[doc(inline)]
+pub use crate::mmr_store::{MMRStoreReadOps, MMRStoreWriteOps};
+#[doc(inline)]
+pub use crate::util::{MemMMR, MemStore};
@@ -1,8 +1,17 @@
 mod test_accumulate_headers;
+#[cfg(feature = "async")]
+mod test_async;
+mod test_compaction;
+mod test_encoding;
 mod test_helper;
 mod test_incremental;
 mod test_mmr;
+mod test_no_alloc;
 mod test_sequence;
+mod test_sync_store;
+#[cfg(feature = "typed-positions")]
+mod test_typed;
+mod test_util;
 
 use crate::{Merge, Result};
 use blake2b_rs::{Blake2b, Blake2bBuilder};
@@ -24,6 +33,37 @@ impl From<u32> for NumberHash {
     }
 }
 
+impl AsRef<[u8]> for NumberHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for NumberHash {
+    fn from(data: Vec<u8>) -> Self {
+        NumberHash(data.into())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for NumberHash {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for NumberHash {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(NumberHash::from)
+    }
+}
+
 struct MergeNumberHash;
 
 impl Merge for MergeNumberHash {
@@ -135,7 +135,8 @@ impl Prover {
             self.positions.push(pos);
             self.headers.push((block, previous.td));
         }
-        mmr.commit()
+        mmr.commit()?;
+        Ok(())
     }
 
     fn get_header(&self, number: u64) -> (Header, u64) {
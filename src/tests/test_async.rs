@@ -0,0 +1,89 @@
+use super::{MergeNumberHash, NumberHash};
+use crate::util::{AsyncMemMMR, AsyncMemStore, MemMMR, MemStore};
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+// the in-memory store is always ready, so polling in a loop never spins
+fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+#[test]
+fn test_async_mmr() {
+    block_on(async {
+        let store = AsyncMemStore::default();
+        let mut mmr = AsyncMemMMR::<_, MergeNumberHash>::new(0, &store);
+        let sync_store = MemStore::default();
+        let mut sync_mmr = MemMMR::<_, MergeNumberHash>::new(0, &sync_store);
+        let mut positions = Vec::new();
+        for i in 0u32..50 {
+            let pos = mmr.push(NumberHash::from(i)).await.unwrap();
+            assert_eq!(pos, sync_mmr.push(NumberHash::from(i)).unwrap());
+            positions.push(pos);
+            assert_eq!(mmr.get_root().await, sync_mmr.get_root());
+            // half of the leaves are read back from the store
+            if i == 24 {
+                assert_eq!(mmr.commit().await, Ok(mmr.mmr_size()));
+            }
+        }
+        let root = mmr.get_root().await.unwrap();
+        let pos_list = vec![positions[3], positions[30], positions[49]];
+        let proof = mmr.gen_proof(pos_list.clone()).await.unwrap();
+        assert_eq!(
+            proof.proof_items(),
+            sync_mmr.gen_proof(pos_list).unwrap().proof_items()
+        );
+        assert!(proof
+            .verify(
+                root.clone(),
+                vec![
+                    (positions[3], NumberHash::from(3)),
+                    (positions[30], NumberHash::from(30)),
+                    (positions[49], NumberHash::from(49))
+                ]
+            )
+            .unwrap());
+
+        // everything is read from the store once committed
+        mmr.commit().await.unwrap();
+        let mmr = AsyncMemMMR::<_, MergeNumberHash>::new(mmr.mmr_size(), &store);
+        assert_eq!(mmr.get_root().await, Ok(root.clone()));
+        let proof = mmr.gen_proof(vec![positions[0]]).await.unwrap();
+        assert!(proof
+            .verify(root, vec![(positions[0], NumberHash::from(0))])
+            .unwrap());
+    });
+}
+
+#[test]
+fn test_async_mmr_capacity() {
+    block_on(async {
+        let store = AsyncMemStore::default();
+        let mut mmr = AsyncMemMMR::<_, MergeNumberHash>::with_capacity(0, &store, 3);
+        for i in 0u32..3 {
+            mmr.push(NumberHash::from(i)).await.unwrap();
+        }
+        let mmr_size = mmr.mmr_size();
+        assert_eq!(
+            mmr.push(NumberHash::from(3)).await,
+            Err(crate::Error::CapacityExceeded)
+        );
+        assert_eq!(mmr.mmr_size(), mmr_size);
+    });
+}
@@ -0,0 +1,103 @@
+use super::{MergeNumberHash, NumberHash};
+use crate::{
+    leaf_index_to_pos,
+    util::{MemMMR, MemStore},
+    Error, MMRStoreReadOps,
+};
+
+#[test]
+fn test_compact_below() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    for i in 0u32..20 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    let root = mmr.get_root().unwrap();
+    mmr.compact_below(5).unwrap();
+
+    // leaves and nodes fully below the watermark are gone
+    for pos in 0..=5 {
+        assert_eq!((&store).get_elem(pos).unwrap(), None, "pos {}", pos);
+    }
+    // the sibling of the retained path and the peaks are kept
+    for pos in [6, 7, 14, 30, 37] {
+        assert!((&store).get_elem(pos).unwrap().is_some(), "pos {}", pos);
+    }
+    assert_eq!(mmr.get_root().unwrap(), root);
+
+    let pos = leaf_index_to_pos(10);
+    let proof = mmr.gen_proof(vec![pos]).unwrap();
+    assert!(proof
+        .verify(root, vec![(pos, NumberHash::from(10))])
+        .unwrap());
+
+    // appends still work after compaction
+    let new_pos = mmr.push(NumberHash::from(20)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![pos, new_pos]).unwrap();
+    assert!(proof
+        .verify(
+            root,
+            vec![(pos, NumberHash::from(10)), (new_pos, NumberHash::from(20))]
+        )
+        .unwrap());
+}
+
+#[test]
+fn test_prune_below() {
+    let reference_store = MemStore::default();
+    let mut reference = MemMMR::<_, MergeNumberHash>::new(0, &reference_store);
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    for i in 0u32..20 {
+        reference.push(NumberHash::from(i)).unwrap();
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    let root = mmr.get_root().unwrap();
+
+    // 38 nodes with the peaks 30 and 37, the leaf 10 is at 18
+    assert_eq!(mmr.prune_below(10), Ok(18));
+    for pos in 0..18 {
+        assert_eq!((&store).get_elem(pos).unwrap(), None, "pos {}", pos);
+    }
+    for pos in 18..38 {
+        assert!((&store).get_elem(pos).unwrap().is_some(), "pos {}", pos);
+    }
+    assert_eq!(mmr.get_root().unwrap(), root);
+    // the sibling 14 of retained leaves is pruned
+    let pos = leaf_index_to_pos(10);
+    assert_eq!(
+        mmr.gen_proof(vec![pos]).map(|_| ()),
+        Err(Error::InconsistentStore)
+    );
+    // already pruned positions are not counted again
+    assert_eq!(mmr.prune_below(10), Ok(0));
+
+    for i in 20u32..40 {
+        reference.push(NumberHash::from(i)).unwrap();
+        mmr.push(NumberHash::from(i)).unwrap();
+        assert_eq!(mmr.get_root(), reference.get_root());
+    }
+    let new_pos = leaf_index_to_pos(35);
+    let proof = mmr.gen_proof(vec![new_pos]).unwrap();
+    assert!(proof
+        .verify(
+            mmr.get_root().unwrap(),
+            vec![(new_pos, NumberHash::from(35))]
+        )
+        .unwrap());
+
+    // pruning everything keeps the peaks only
+    let peaks = crate::helper::get_peaks(mmr.mmr_size());
+    let pruned = mmr.prune_below(u64::MAX).unwrap();
+    assert_eq!(
+        (0..mmr.mmr_size())
+            .filter(|pos| (&store).get_elem(*pos).unwrap().is_some())
+            .collect::<Vec<_>>(),
+        peaks
+    );
+    assert!(pruned > 0);
+    mmr.push(NumberHash::from(40)).unwrap();
+    reference.push(NumberHash::from(40)).unwrap();
+    assert_eq!(mmr.get_root(), reference.get_root());
+}
@@ -0,0 +1,391 @@
+use super::{MergeNumberHash, NumberHash};
+use crate::{
+    encoding::{
+        pack_merkle_proof, packed_merkle_proof_size, unpack_merkle_proof, LengthPrefixed,
+        LengthWidth, PROOF_MAGIC,
+    },
+    util::{self, MemMMR, MemStore, Packable},
+    Error, Merge, MerkleProof, Result,
+};
+use proptest::prelude::*;
+
+fn gen_proof() -> (
+    NumberHash,
+    MerkleProof<NumberHash, MergeNumberHash>,
+    Vec<u64>,
+) {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[3], positions[7]]).unwrap();
+    (root, proof, positions)
+}
+
+#[test]
+fn test_encode_v1_round_trip() {
+    let (root, proof, positions) = gen_proof();
+    let data = proof.encode_v1().unwrap();
+    assert_eq!(&data[..2], &PROOF_MAGIC);
+    assert_eq!(data[2], 1);
+    assert_eq!(data[3..11], proof.mmr_size().to_le_bytes());
+    assert_eq!(
+        data[11..15],
+        (proof.proof_items().len() as u32).to_le_bytes()
+    );
+    let decoded = MerkleProof::<NumberHash, MergeNumberHash>::decode_v1(&data).unwrap();
+    assert_eq!(decoded.mmr_size(), proof.mmr_size());
+    assert_eq!(decoded.proof_items(), proof.proof_items());
+    assert!(decoded
+        .verify(
+            root,
+            vec![
+                (positions[3], NumberHash::from(3)),
+                (positions[7], NumberHash::from(7))
+            ]
+        )
+        .unwrap());
+}
+
+#[test]
+fn test_decode_v1_wrong_magic() {
+    let (_, proof, _) = gen_proof();
+    let mut data = proof.encode_v1().unwrap();
+    data[0] ^= 0xff;
+    assert_eq!(
+        MerkleProof::<NumberHash, MergeNumberHash>::decode_v1(&data).map(|_| ()),
+        Err(Error::InvalidEncoding)
+    );
+}
+
+#[test]
+fn test_decode_v1_item_count_exceeds_buffer() {
+    let (_, proof, _) = gen_proof();
+    let mut data = proof.encode_v1().unwrap();
+    data[11..15].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        MerkleProof::<NumberHash, MergeNumberHash>::decode_v1(&data).map(|_| ()),
+        Err(Error::UnpackEof)
+    );
+    // one more item than encoded
+    let count = proof.proof_items().len() as u32 + 1;
+    data[11..15].copy_from_slice(&count.to_le_bytes());
+    assert_eq!(
+        MerkleProof::<NumberHash, MergeNumberHash>::decode_v1(&data).map(|_| ()),
+        Err(Error::UnpackEof)
+    );
+    // truncated item
+    let data = proof.encode_v1().unwrap();
+    assert_eq!(
+        MerkleProof::<NumberHash, MergeNumberHash>::decode_v1(&data[..data.len() - 1]).map(|_| ()),
+        Err(Error::UnpackEof)
+    );
+}
+
+#[test]
+fn test_length_prefixed_u16_boundary() {
+    let codec = LengthPrefixed::<Vec<u8>>::new(LengthWidth::U16);
+    let items = vec![
+        vec![],
+        vec![1u8; u16::MAX as usize - 1],
+        vec![2u8; u16::MAX as usize],
+    ];
+    let mut data = Vec::new();
+    for item in &items {
+        codec.pack(item, &mut data).unwrap();
+    }
+    assert_eq!(data[..2], 0u16.to_le_bytes());
+    assert_eq!(
+        codec.pack(&vec![3u8; u16::MAX as usize + 1], &mut data),
+        Err(Error::InvalidEncoding)
+    );
+
+    let mut rest = &data[..];
+    for item in &items {
+        assert_eq!(&codec.unpack(&mut rest).unwrap(), item);
+    }
+    assert!(rest.is_empty());
+
+    // the wider length field accepts items past the u16 boundary
+    let codec = LengthPrefixed::<Vec<u8>>::new(LengthWidth::U32);
+    let item = vec![3u8; u16::MAX as usize + 1];
+    let mut data = Vec::new();
+    codec.pack(&item, &mut data).unwrap();
+    assert_eq!(data[..4], (item.len() as u32).to_le_bytes());
+    assert_eq!(codec.unpack(&mut &data[..]).unwrap(), item);
+}
+
+// packs the items back to back then unpacks them by any `Packable`
+fn round_trip<P: Packable>(packer: &P, items: &[P::Item]) -> Result<Vec<P::Item>> {
+    let mut data = Vec::new();
+    for item in items {
+        packer.pack(item, &mut data)?;
+    }
+    let size: usize = items
+        .iter()
+        .map(|item| packer.packed_size(item))
+        .sum::<Result<usize>>()?;
+    assert_eq!(data.len(), size);
+    let mut rest = &data[..];
+    let mut unpacked = Vec::new();
+    while !rest.is_empty() {
+        unpacked.push(packer.unpack(&mut rest)?);
+    }
+    Ok(unpacked)
+}
+
+#[test]
+fn test_length_prefixed_packable() {
+    let items = vec![
+        NumberHash::from(1),
+        NumberHash(vec![2u8; u16::MAX as usize].into()),
+    ];
+    let packer = util::LengthPrefixed::<NumberHash>::new(LengthWidth::U16);
+    assert_eq!(round_trip(&packer, &items), Ok(items.clone()));
+    let packer = util::LengthPrefixed::<NumberHash>::new(LengthWidth::U32);
+    assert_eq!(round_trip(&packer, &items), Ok(items));
+
+    let packer = util::LengthPrefixed::<Vec<u8>>::new(LengthWidth::U16);
+    let mut data = Vec::new();
+    Packable::pack(&packer, &vec![1u8; 10], &mut data).unwrap();
+    assert_eq!(
+        Packable::unpack(&packer, &mut &data[..data.len() - 1]),
+        Err(Error::UnpackEof)
+    );
+}
+
+#[test]
+fn test_length_prefixed_truncated() {
+    let codec = LengthPrefixed::<Vec<u8>>::new(LengthWidth::U16);
+    let mut data = Vec::new();
+    codec.pack(&vec![1u8; 300], &mut data).unwrap();
+    // truncated length field and truncated item
+    for len in [0, 1, 2, data.len() - 1] {
+        let mut rest = &data[..len];
+        assert_eq!(
+            codec.unpack(&mut rest),
+            Err(Error::UnpackEof),
+            "len {}",
+            len
+        );
+        // the input is left untouched on failure
+        assert_eq!(rest.len(), len);
+    }
+}
+
+// the same hash as `MergeNumberHash` under another id
+struct IdentifiedMergeNumberHash;
+
+impl Merge for IdentifiedMergeNumberHash {
+    type Item = NumberHash;
+
+    const ID: u32 = 0x6e68_0001;
+
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> Result<Self::Item> {
+        MergeNumberHash::merge(lhs, rhs)
+    }
+}
+
+#[test]
+fn test_encode_v2_merge_id() {
+    let (root, proof, positions) = gen_proof();
+    let data = proof.encode_v2().unwrap();
+    assert_eq!(data[2], 2);
+    assert_eq!(data[3..7], MergeNumberHash::ID.to_le_bytes());
+    let decoded = MerkleProof::<NumberHash, MergeNumberHash>::decode(&data).unwrap();
+    assert_eq!(decoded.proof_items(), proof.proof_items());
+    assert!(decoded
+        .verify(
+            root,
+            vec![
+                (positions[3], NumberHash::from(3)),
+                (positions[7], NumberHash::from(7))
+            ]
+        )
+        .unwrap());
+
+    // decoding with another merge is rejected
+    assert_eq!(
+        MerkleProof::<NumberHash, IdentifiedMergeNumberHash>::decode_v2(&data).map(|_| ()),
+        Err(Error::MergeMismatch)
+    );
+    let proof = MerkleProof::<NumberHash, IdentifiedMergeNumberHash>::new(
+        proof.mmr_size(),
+        proof.proof_items().to_vec(),
+    );
+    let data = proof.encode_v2().unwrap();
+    assert_eq!(
+        MerkleProof::<NumberHash, MergeNumberHash>::decode(&data).map(|_| ()),
+        Err(Error::MergeMismatch)
+    );
+    assert!(MerkleProof::<NumberHash, IdentifiedMergeNumberHash>::decode(&data).is_ok());
+
+    // version 1 doesn't record the merge
+    let data = proof.encode_v1().unwrap();
+    assert!(MerkleProof::<NumberHash, MergeNumberHash>::decode(&data).is_ok());
+}
+
+#[test]
+fn test_pack_merkle_proof_round_trip() {
+    let (root, proof, positions) = gen_proof();
+    let data = pack_merkle_proof(&proof).unwrap();
+    assert_eq!(data[..8], proof.mmr_size().to_le_bytes());
+    assert_eq!(data.len(), 8 + proof.proof_items().len() * (4 + 32));
+    let unpacked = unpack_merkle_proof::<NumberHash, MergeNumberHash>(&data).unwrap();
+    assert_eq!(unpacked.mmr_size(), proof.mmr_size());
+    assert_eq!(unpacked.proof_items(), proof.proof_items());
+    assert!(unpacked
+        .verify(
+            root,
+            vec![
+                (positions[3], NumberHash::from(3)),
+                (positions[7], NumberHash::from(7))
+            ]
+        )
+        .unwrap());
+
+    // items of variable length keep their order
+    let items: Vec<NumberHash> = vec![vec![1, 2, 3].into(), vec![].into(), vec![4].into()];
+    let proof = MerkleProof::<_, MergeNumberHash>::new(19, items.clone());
+    let data = pack_merkle_proof(&proof).unwrap();
+    let unpacked = unpack_merkle_proof::<NumberHash, MergeNumberHash>(&data).unwrap();
+    assert_eq!(unpacked.proof_items(), &items[..]);
+
+    // a truncated item
+    assert_eq!(
+        unpack_merkle_proof::<NumberHash, MergeNumberHash>(&data[..data.len() - 1]).map(|_| ()),
+        Err(Error::UnpackEof)
+    );
+    assert_eq!(
+        unpack_merkle_proof::<NumberHash, MergeNumberHash>(&data[..7]).map(|_| ()),
+        Err(Error::UnpackEof)
+    );
+}
+
+#[test]
+fn test_hex_round_trip() {
+    let (root, proof, positions) = gen_proof();
+    let hex = proof.to_hex();
+    assert_eq!(hex.len(), proof.proof_items().len());
+    for (hex, item) in hex.iter().zip(proof.proof_items()) {
+        assert_eq!(hex, &faster_hex::hex_string(&item.0));
+    }
+    let bytes = proof.proof_bytes();
+    assert!(bytes
+        .iter()
+        .zip(proof.proof_items())
+        .all(|(b, item)| b[..] == item.0[..]));
+
+    let rebuilt =
+        MerkleProof::<NumberHash, MergeNumberHash>::from_hex(proof.mmr_size(), &hex).unwrap();
+    assert_eq!(rebuilt.mmr_size(), proof.mmr_size());
+    assert_eq!(rebuilt.proof_items(), proof.proof_items());
+    assert!(rebuilt
+        .verify(
+            root,
+            vec![
+                (positions[3], NumberHash::from(3)),
+                (positions[7], NumberHash::from(7))
+            ]
+        )
+        .unwrap());
+
+    // upper case is accepted, odd lengths and other characters aren't
+    let upper: Vec<String> = hex.iter().map(|h| h.to_uppercase()).collect();
+    assert_eq!(
+        MerkleProof::<NumberHash, MergeNumberHash>::from_hex(proof.mmr_size(), &upper)
+            .unwrap()
+            .proof_items(),
+        proof.proof_items()
+    );
+    for invalid in &["abc", "0g", "+1"] {
+        assert_eq!(
+            MerkleProof::<NumberHash, MergeNumberHash>::from_hex(19, &[invalid]).map(|_| ()),
+            Err(Error::InvalidEncoding)
+        );
+    }
+}
+
+#[test]
+fn test_packed_size() {
+    let (_, proof, _) = gen_proof();
+    assert_eq!(
+        packed_merkle_proof_size(&proof),
+        Ok(pack_merkle_proof(&proof).unwrap().len())
+    );
+    let packer = LengthPrefixed::new(LengthWidth::U16);
+    let mut buf = Vec::new();
+    let item = vec![1u8; u16::MAX as usize];
+    packer.pack(&item, &mut buf).unwrap();
+    assert_eq!(packer.packed_size(&item), Ok(buf.len()));
+    assert_eq!(
+        packer.packed_size(&vec![1u8; u16::MAX as usize + 1]),
+        Err(Error::InvalidEncoding)
+    );
+}
+
+#[test]
+fn test_decode_malformed() {
+    let (_, proof, _) = gen_proof();
+    for data in [proof.encode_v1().unwrap(), proof.encode_v2().unwrap()] {
+        // every truncation ends before the content
+        for len in 0..data.len() {
+            assert_eq!(
+                MerkleProof::<NumberHash, MergeNumberHash>::decode(&data[..len]).map(|_| ()),
+                Err(Error::UnpackEof),
+                "truncated at {}",
+                len
+            );
+        }
+        // trailing garbage
+        let mut garbage = data.clone();
+        garbage.push(0);
+        assert_eq!(
+            MerkleProof::<NumberHash, MergeNumberHash>::decode(&garbage).map(|_| ()),
+            Err(Error::InvalidEncoding)
+        );
+        // an oversized length prefix of the last item
+        let mut oversized = data.clone();
+        let len_offset = data.len() - 32 - 4;
+        oversized[len_offset..len_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            MerkleProof::<NumberHash, MergeNumberHash>::decode(&oversized).map(|_| ()),
+            Err(Error::UnpackEof)
+        );
+    }
+
+    let data = pack_merkle_proof(&proof).unwrap();
+    let mut oversized = data.clone();
+    oversized[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        unpack_merkle_proof::<NumberHash, MergeNumberHash>(&oversized).map(|_| ()),
+        Err(Error::UnpackEof)
+    );
+    // a length prefix cut in the middle
+    let mut garbage = data;
+    garbage.extend_from_slice(&[1, 0]);
+    assert_eq!(
+        unpack_merkle_proof::<NumberHash, MergeNumberHash>(&garbage).map(|_| ()),
+        Err(Error::UnpackEof)
+    );
+}
+
+proptest! {
+    #[test]
+    fn test_decode_arbitrary_bytes(data in prop::collection::vec(any::<u8>(), 0..200)) {
+        // untrusted data is rejected cleanly, not by a panic
+        let _ = MerkleProof::<NumberHash, MergeNumberHash>::decode(&data);
+        let _ = unpack_merkle_proof::<NumberHash, MergeNumberHash>(&data);
+        let mut header = PROOF_MAGIC.to_vec();
+        header.push(1);
+        header.extend_from_slice(&data);
+        let _ = MerkleProof::<NumberHash, MergeNumberHash>::decode(&header);
+    }
+
+    #[test]
+    fn test_packed_size_randomly(mmr_size in any::<u64>(), items in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..100), 0..20)) {
+        let items = items.into_iter().map(NumberHash::from).collect();
+        let proof = MerkleProof::<NumberHash, MergeNumberHash>::new(mmr_size, items);
+        prop_assert_eq!(packed_merkle_proof_size(&proof), Ok(pack_merkle_proof(&proof).unwrap().len()));
+    }
+}
@@ -1,7 +1,12 @@
 use super::{MergeNumberHash, NumberHash};
 use crate::{
-    helper::{get_peak_map, get_peaks, pos_height_in_tree},
-    leaf_index_to_mmr_size, leaf_index_to_pos,
+    helper::{
+        added_positions, ancestors, children_pos, containing_peak, expected_proof_len,
+        get_peak_map, get_peaks, mmr_position_to_k_index, parent_pos, peaks, pos_height_in_tree,
+        position_to_peak_and_k_index, proofs_equivalent, sibling_pos,
+    },
+    is_valid_mmr_size, leaf_index_to_mmr_size, leaf_index_to_pos, mmr_size_to_leaf_count,
+    pos_to_leaf_index,
     util::MemStore,
     MMR,
 };
@@ -76,7 +81,7 @@ fn test_get_peak_map() {
 
 #[test]
 fn test_get_peaks() {
-    assert_eq!(get_peaks(0), vec![]);
+    assert_eq!(get_peaks(0), Vec::<u64>::new());
     assert_eq!(get_peaks(1), vec![0]);
     assert_eq!(get_peaks(3), vec![2]);
     assert_eq!(get_peaks(4), vec![2, 3]);
@@ -93,6 +98,201 @@ fn test_get_peaks() {
     assert_eq!(get_peaks(19), vec![14, 17, 18]);
 }
 
+#[test]
+fn test_ancestors() {
+    assert_eq!(ancestors(0, 7), vec![2, 6]);
+    assert_eq!(ancestors(0, 19), vec![2, 6, 14]);
+    assert_eq!(ancestors(8, 19), vec![9, 13, 14]);
+    assert_eq!(ancestors(15, 19), vec![17]);
+    assert_eq!(ancestors(5, 19), vec![6, 14]);
+    // peaks have no ancestors
+    assert_eq!(ancestors(14, 19), Vec::<u64>::new());
+    assert_eq!(ancestors(18, 19), Vec::<u64>::new());
+    // beyond the mmr
+    assert_eq!(ancestors(19, 19), Vec::<u64>::new());
+}
+
+#[test]
+fn test_navigation() {
+    //           14
+    //        /       \
+    //      6          13
+    //    /   \       /   \
+    //   2     5     9     12     17
+    //  / \   /  \  / \   /  \   /  \
+    // 0   1 3   4 7   8 10  11 15  16 18
+    assert_eq!(sibling_pos(3, 0), 4);
+    assert_eq!(sibling_pos(4, 0), 3);
+    assert_eq!(sibling_pos(2, 1), 5);
+    assert_eq!(sibling_pos(13, 2), 6);
+    assert_eq!(sibling_pos(15, 0), 16);
+    assert_eq!(parent_pos(3, 0), 5);
+    assert_eq!(parent_pos(4, 0), 5);
+    assert_eq!(parent_pos(2, 1), 6);
+    assert_eq!(parent_pos(6, 2), 14);
+    assert_eq!(parent_pos(13, 2), 14);
+    assert_eq!(children_pos(6), Some((2, 5)));
+    assert_eq!(children_pos(14), Some((6, 13)));
+    assert_eq!(children_pos(17), Some((15, 16)));
+    assert_eq!(children_pos(2), Some((0, 1)));
+    assert_eq!(children_pos(0), None);
+    assert_eq!(children_pos(18), None);
+}
+
+#[test]
+fn test_containing_peak() {
+    assert_eq!(containing_peak(3, 19), Some(14));
+    assert_eq!(containing_peak(0, 19), Some(14));
+    assert_eq!(containing_peak(14, 19), Some(14));
+    assert_eq!(containing_peak(15, 19), Some(17));
+    assert_eq!(containing_peak(17, 19), Some(17));
+    assert_eq!(containing_peak(18, 19), Some(18));
+    assert_eq!(containing_peak(0, 1), Some(0));
+    // beyond the mmr
+    assert_eq!(containing_peak(19, 19), None);
+    assert_eq!(containing_peak(0, 0), None);
+}
+
+#[test]
+fn test_proofs_equivalent() {
+    // order and duplicates of positions don't change the proof
+    assert!(proofs_equivalent(19, &[0, 7, 18], &[18, 0, 7, 0]));
+    assert!(proofs_equivalent(19, &[15], &[15, 15]));
+    // same count of items but different siblings
+    assert!(!proofs_equivalent(19, &[0], &[1]));
+    assert!(!proofs_equivalent(19, &[15], &[16]));
+    assert!(!proofs_equivalent(19, &[0, 1], &[0]));
+    // a list which can't be proven is never equivalent
+    assert!(!proofs_equivalent(19, &[0, 19], &[0, 19]));
+    assert!(!proofs_equivalent(19, &[], &[]));
+}
+
+#[test]
+fn test_expected_proof_len() {
+    // siblings 1, 5, 13 and the bagged 17 and 18
+    assert_eq!(expected_proof_len(19, &[0]), 4);
+    // sibling 16 and peaks 14 and 18
+    assert_eq!(expected_proof_len(19, &[15]), 3);
+    // the subtree of 6 is covered, sibling 13 and the bagged 17 and 18
+    assert_eq!(expected_proof_len(19, &[0, 1, 3, 4]), 2);
+    assert_eq!(expected_proof_len(1, &[0]), 0);
+    assert_eq!(expected_proof_len(19, &[19]), 0);
+    assert_eq!(expected_proof_len(19, &[]), 0);
+}
+
+#[test]
+fn test_is_valid_mmr_size() {
+    for mmr_size in [0, 1, 3, 4, 7, 8, 19] {
+        assert!(is_valid_mmr_size(mmr_size), "{}", mmr_size);
+    }
+    for mmr_size in [2, 5, 6, 9, 12] {
+        assert!(!is_valid_mmr_size(mmr_size), "{}", mmr_size);
+    }
+    let valid_sizes: Vec<u64> = (0..40).map(leaf_index_to_mmr_size).collect();
+    for mmr_size in 1..40 {
+        assert_eq!(
+            is_valid_mmr_size(mmr_size),
+            valid_sizes.contains(&mmr_size),
+            "{}",
+            mmr_size
+        );
+    }
+}
+
+#[test]
+fn test_peaks() {
+    assert_eq!(peaks(0).count(), 0);
+    assert_eq!(peaks(1).collect::<Vec<_>>(), vec![(0, 0)]);
+    assert_eq!(peaks(3).collect::<Vec<_>>(), vec![(2, 1)]);
+    assert_eq!(peaks(8).collect::<Vec<_>>(), vec![(6, 2), (7, 0)]);
+    assert_eq!(
+        peaks(19).collect::<Vec<_>>(),
+        vec![(14, 3), (17, 1), (18, 0)]
+    );
+}
+
+#[test]
+fn test_pos_to_leaf_index() {
+    assert_eq!(pos_to_leaf_index(0), Some(0));
+    assert_eq!(pos_to_leaf_index(1), Some(1));
+    assert_eq!(pos_to_leaf_index(3), Some(2));
+    assert_eq!(pos_to_leaf_index(2), None);
+    assert_eq!(pos_to_leaf_index(6), None);
+}
+
+#[test]
+fn test_mmr_size_to_leaf_count() {
+    assert_eq!(mmr_size_to_leaf_count(0), 0);
+    assert_eq!(mmr_size_to_leaf_count(1), 1);
+    assert_eq!(mmr_size_to_leaf_count(10), 6);
+    assert_eq!(mmr_size_to_leaf_count(19), 11);
+}
+
+#[test]
+fn test_added_positions() {
+    assert_eq!(added_positions(7, 8), vec![7]);
+    assert_eq!(added_positions(8, 15), (8..15).collect::<Vec<_>>());
+    assert_eq!(added_positions(0, 1), vec![0]);
+    // 4 to 7 merges two peaks
+    assert_eq!(added_positions(4, 7), vec![4, 5, 6]);
+    assert!(added_positions(8, 8).is_empty());
+    assert!(added_positions(8, 7).is_empty());
+    // 5 and 6 are not valid mmr sizes
+    assert!(added_positions(5, 7).is_empty());
+    assert!(added_positions(4, 6).is_empty());
+}
+
+#[test]
+fn test_mmr_position_to_k_index() {
+    //           14
+    //        /       \
+    //      6          13
+    //    /   \       /   \
+    //   2     5     9     12     17
+    //  / \   /  \  / \   /  \   /  \
+    // 0   1 3   4 7   8 10  11 15  16 18
+    assert_eq!(
+        mmr_position_to_k_index(vec![0, 1, 3, 4, 7, 8, 10, 11, 15, 16, 18], 19),
+        vec![
+            (0, 0),
+            (1, 1),
+            (3, 2),
+            (4, 3),
+            (7, 4),
+            (8, 5),
+            (10, 6),
+            (11, 7),
+            (15, 0),
+            (16, 1),
+            (18, 0)
+        ]
+    );
+    // internal nodes are indexed among the nodes at their height
+    assert_eq!(
+        mmr_position_to_k_index(vec![2, 13, 14, 17], 19),
+        vec![(2, 0), (13, 1), (14, 0), (17, 0)]
+    );
+    // the position order is kept, positions beyond the mmr are skipped
+    assert_eq!(
+        mmr_position_to_k_index(vec![8, 19, 3], 19),
+        vec![(8, 5), (3, 2)]
+    );
+    assert_eq!(mmr_position_to_k_index(vec![0], 0), vec![]);
+}
+
+#[test]
+fn test_position_to_peak_and_k_index() {
+    assert_eq!(position_to_peak_and_k_index(7, 19), Some((14, 4, 3)));
+    assert_eq!(position_to_peak_and_k_index(0, 19), Some((14, 0, 3)));
+    assert_eq!(position_to_peak_and_k_index(11, 19), Some((14, 7, 3)));
+    assert_eq!(position_to_peak_and_k_index(12, 19), Some((14, 3, 3)));
+    assert_eq!(position_to_peak_and_k_index(14, 19), Some((14, 0, 3)));
+    assert_eq!(position_to_peak_and_k_index(16, 19), Some((17, 1, 1)));
+    assert_eq!(position_to_peak_and_k_index(18, 19), Some((18, 0, 0)));
+    assert_eq!(position_to_peak_and_k_index(19, 19), None);
+    assert_eq!(position_to_peak_and_k_index(0, 0), None);
+}
+
 proptest! {
     #[test]
     fn test_leaf_index_to_pos_randomly(index in 0..INDEX_TO_POS.len()) {
@@ -104,4 +304,69 @@ proptest! {
     fn test_leaf_index_to_mmr_size_randomly(index in 0..INDEX_TO_MMR_SIZE.len()) {
         assert_eq!(leaf_index_to_mmr_size(index as u64), INDEX_TO_MMR_SIZE[index]);
     }
+
+    #[test]
+    fn test_peaks_randomly(index in 0..INDEX_TO_MMR_SIZE.len()) {
+        let mmr_size = INDEX_TO_MMR_SIZE[index];
+        let positions: Vec<u64> = peaks(mmr_size).map(|(pos, _)| pos).collect();
+        assert_eq!(positions, get_peaks(mmr_size));
+        assert!(peaks(mmr_size).all(|(pos, height)| pos_height_in_tree(pos) == height));
+    }
+
+    #[test]
+    fn test_pos_to_leaf_index_randomly(index in 0..INDEX_TO_POS.len()) {
+        assert_eq!(pos_to_leaf_index(leaf_index_to_pos(index as u64)), Some(index as u64));
+    }
+
+    #[test]
+    fn test_mmr_size_to_leaf_count_randomly(index in 0..INDEX_TO_MMR_SIZE.len()) {
+        assert_eq!(mmr_size_to_leaf_count(INDEX_TO_MMR_SIZE[index]), index as u64 + 1);
+    }
+
+    #[test]
+    fn test_expected_proof_len_randomly(count in 1u32..200, seed in prop::collection::vec(any::<u32>(), 1..20)) {
+        let store = MemStore::default();
+        let mut mmr = MMR::<_, MergeNumberHash, _>::new(0, &store);
+        let positions: Vec<u64> = (0..count).map(|i| mmr.push(NumberHash::from(i)).unwrap()).collect();
+        let pos_list: Vec<u64> = seed.iter().map(|i| positions[*i as usize % positions.len()]).collect();
+        let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+        prop_assert_eq!(expected_proof_len(mmr.mmr_size(), &pos_list), proof.proof_items().len());
+    }
+
+    #[test]
+    fn test_navigation_randomly(pos in 0u64..1_000_000) {
+        let height = pos_height_in_tree(pos);
+        let parent = parent_pos(pos, height);
+        let sibling = sibling_pos(pos, height);
+        prop_assert_eq!(pos_height_in_tree(parent), height + 1);
+        prop_assert_eq!(sibling_pos(sibling, height), pos);
+        prop_assert_eq!(parent_pos(sibling, height), parent);
+        let children = children_pos(parent).unwrap();
+        prop_assert_eq!(children, (pos.min(sibling), pos.max(sibling)));
+    }
+
+    #[test]
+    fn test_added_positions_randomly(count in 1u32..300) {
+        let store = MemStore::default();
+        let mut mmr = MMR::<_, MergeNumberHash, _>::new(0, &store);
+        let mut positions = Vec::new();
+        for i in 0..count {
+            let old_mmr_size = mmr.mmr_size();
+            let leaf_pos = mmr.push(NumberHash::from(i)).unwrap();
+            let added = added_positions(old_mmr_size, mmr.mmr_size());
+            prop_assert_eq!(added[0], leaf_pos);
+            positions.extend(added);
+        }
+        prop_assert_eq!(positions, (0..mmr.mmr_size()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_is_valid_mmr_size_randomly(count in 0u32..500) {
+        let store = MemStore::default();
+        let mut mmr = MMR::<_, MergeNumberHash, _>::new(0, &store);
+        for i in 0..count {
+            mmr.push(NumberHash::from(i)).unwrap();
+            prop_assert!(is_valid_mmr_size(mmr.mmr_size()));
+        }
+    }
 }
@@ -1,13 +1,14 @@
 use super::{MergeNumberHash, NumberHash};
 use crate::{
-    helper::pos_height_in_tree,
-    leaf_index_to_mmr_size,
+    check_peaks_distinct,
+    helper::{get_peaks, pos_height_in_tree},
+    leaf_index_to_mmr_size, leaf_index_to_pos,
     util::{MemMMR, MemStore},
-    Error,
+    Error, ItemRole, MMRStoreReadOps, MMRStoreWriteOps, MerkleProof, MmrStats, PushInfo, MMR,
 };
 use faster_hex::hex_string;
 use proptest::prelude::*;
-use rand::{seq::SliceRandom, thread_rng};
+use rand::{seq::SliceRandom, thread_rng, Rng};
 
 fn test_mmr(count: u32, proof_elem: Vec<u32>) {
     let store = MemStore::default();
@@ -286,4 +287,2626 @@ proptest! {
     fn test_random_gen_root_with_new_leaf(count in 1u32..500u32) {
         test_gen_new_root_from_proof(count);
     }
+
+    #[test]
+    fn test_random_gen_root_with_new_leaves(count in 1u32..300u32, new_count in 0u32..50u32) {
+        test_gen_new_root_from_proof_with_new_leaves(count, new_count);
+    }
+}
+
+fn test_gen_new_root_from_proof_with_new_leaves(count: u32, new_count: u32) {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..count).map(NumberHash::from)).unwrap();
+    let elem = count - 1;
+    let pos = positions[elem as usize];
+    let proof = mmr.gen_proof(vec![pos]).expect("gen proof");
+    let new_leaves: Vec<(u64, NumberHash)> = (count..count + new_count)
+        .map(|i| (mmr.push(NumberHash::from(i)).unwrap(), NumberHash::from(i)))
+        .collect();
+    let root = mmr.get_root().expect("get root");
+    let calculated_root = proof
+        .calculate_root_with_new_leaves(
+            vec![(pos, NumberHash::from(elem))],
+            new_leaves,
+            mmr.mmr_size(),
+        )
+        .unwrap();
+    assert_eq!(calculated_root, root);
+}
+
+#[test]
+fn test_gen_root_from_proof_with_invalid_new_leaves() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let proof = mmr.gen_proof(vec![positions[10]]).unwrap();
+    let bagged_proof = mmr.gen_proof(vec![positions[0]]).unwrap();
+    let leaves = vec![(positions[10], NumberHash::from(10))];
+    let new_positions: Vec<u64> = (11u32..14)
+        .map(|i| mmr.push(NumberHash::from(i)).unwrap())
+        .collect();
+    let new_leaf = |i: usize| (new_positions[i], NumberHash::from(11 + i as u32));
+
+    // a gap between the new leaves
+    assert_eq!(
+        proof.calculate_root_with_new_leaves(
+            leaves.clone(),
+            vec![new_leaf(0), new_leaf(2)],
+            mmr.mmr_size()
+        ),
+        Err(Error::InvalidNewLeaf)
+    );
+    // the size is not after the last new leaf
+    assert_eq!(
+        proof.calculate_root_with_new_leaves(
+            leaves.clone(),
+            vec![new_leaf(0), new_leaf(1)],
+            mmr.mmr_size()
+        ),
+        Err(Error::InvalidNewLeaf)
+    );
+    assert_eq!(
+        proof.calculate_root_with_new_leaves(leaves.clone(), vec![new_leaf(1)], mmr.mmr_size()),
+        Err(Error::InvalidNewLeaf)
+    );
+    // rhs peaks are bagged in the proof of the first leaf
+    assert_eq!(
+        bagged_proof.calculate_root_with_new_leaves(
+            vec![(positions[0], NumberHash::from(0))],
+            (0..3).map(new_leaf).collect(),
+            mmr.mmr_size()
+        ),
+        Err(Error::CorruptedProof)
+    );
+}
+
+#[test]
+fn test_push_observed() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    for i in 0u32..11 {
+        let mmr_size = mmr.mmr_size();
+        let (pos, nodes) = mmr.push_observed(NumberHash::from(i)).unwrap();
+        assert_eq!(pos, mmr_size);
+        assert_eq!(nodes[0], (pos, NumberHash::from(i)));
+        let positions: Vec<u64> = nodes.iter().map(|(pos, _)| *pos).collect();
+        assert_eq!(positions, (mmr_size..mmr.mmr_size()).collect::<Vec<_>>());
+        for (pos, node) in nodes {
+            assert_eq!(mmr.batch().get_elem(pos).unwrap(), Some(node));
+        }
+    }
+    // the 12th leaf completes two subtrees
+    let (pos, nodes) = mmr.push_observed(NumberHash::from(11)).unwrap();
+    assert_eq!(pos, 19);
+    assert_eq!(
+        nodes.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(),
+        vec![19, 20, 21]
+    );
+}
+
+#[test]
+fn test_verify_sized() {
+    use crate::Merge;
+
+    fn commit(root: &NumberHash, mmr_size: u64) -> crate::Result<NumberHash> {
+        let size = NumberHash::from(mmr_size as u32);
+        MergeNumberHash::merge(root, &size)
+    }
+
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    let positions: Vec<u64> = (0u32..11)
+        .map(|i| mmr.push(NumberHash::from(i)).unwrap())
+        .collect();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[5]]).unwrap();
+    let leaves = vec![(positions[5], NumberHash::from(5))];
+
+    let commitment = commit(&root, mmr.mmr_size()).unwrap();
+    assert!(proof
+        .verify_sized(commitment, leaves.clone(), commit)
+        .unwrap());
+    let wrong_commitment = commit(&root, mmr.mmr_size() - 1).unwrap();
+    assert!(!proof
+        .verify_sized(wrong_commitment, leaves, commit)
+        .unwrap());
+}
+
+#[test]
+fn test_calculate_root_with_budget() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    let positions: Vec<u64> = (0u32..11)
+        .map(|i| mmr.push(NumberHash::from(i)).unwrap())
+        .collect();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[5]]).unwrap();
+    let leaves = vec![(positions[5], NumberHash::from(5))];
+
+    // 3 merges to the peak, 1 merge to bag the peaks
+    assert_eq!(
+        proof.calculate_root_with_budget(leaves.clone(), 4),
+        Ok(root)
+    );
+    assert_eq!(
+        proof.calculate_root_with_budget(leaves, 2),
+        Err(Error::ProofBudgetExceeded)
+    );
+}
+
+#[test]
+fn test_annotated_items() {
+    use crate::ItemRole::*;
+
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    (0u32..11).for_each(|i| {
+        mmr.push(NumberHash::from(i)).unwrap();
+    });
+    assert_eq!(mmr.mmr_size(), 19);
+
+    let check = |pos_list: Vec<u64>, expected: Vec<(u64, crate::ItemRole)>| {
+        let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+        let items = proof.annotated_items(&pos_list).unwrap();
+        assert_eq!(
+            items
+                .iter()
+                .map(|info| (info.pos, info.role))
+                .collect::<Vec<_>>(),
+            expected
+        );
+        for (info, item) in items.iter().zip(proof.proof_items()) {
+            assert_eq!(info.value, item);
+            if info.role != BaggedRhs {
+                assert_eq!(mmr.batch().get_elem(info.pos).unwrap().as_ref(), Some(item));
+            }
+        }
+    };
+    check(
+        vec![8],
+        vec![
+            (7, LeftSibling),
+            (12, RightSibling),
+            (6, LeftSibling),
+            (17, BaggedRhs),
+        ],
+    );
+    check(vec![18], vec![(14, Peak), (17, Peak)]);
+    check(
+        vec![0, 16],
+        vec![
+            (1, RightSibling),
+            (5, RightSibling),
+            (13, RightSibling),
+            (15, LeftSibling),
+            (18, Peak),
+        ],
+    );
+}
+
+#[test]
+fn test_build() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..50).map(NumberHash::from)).unwrap();
+
+    let expected_store = MemStore::default();
+    let mut expected_mmr = MemMMR::<_, MergeNumberHash>::new(0, &expected_store);
+    let expected_positions: Vec<u64> = (0u32..50)
+        .map(|i| expected_mmr.push(NumberHash::from(i)).unwrap())
+        .collect();
+
+    assert_eq!(positions, expected_positions);
+    assert_eq!(mmr.mmr_size(), expected_mmr.mmr_size());
+    assert_eq!(mmr.get_root(), expected_mmr.get_root());
+    // leaves are not committed
+    assert_eq!((&store).get_elem(0).unwrap(), None);
+}
+
+#[test]
+fn test_commit_up_to() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    for i in 0u32..20 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    let root = mmr.get_root().unwrap();
+    let boundary = leaf_index_to_mmr_size(9) - 1;
+    assert_eq!(mmr.commit_up_to(boundary), Ok(boundary + 1));
+
+    assert!((&store).get_elem(boundary).unwrap().is_some());
+    assert_eq!((&store).get_elem(boundary + 1).unwrap(), None);
+    // reads resolve on both sides of the boundary
+    for pos in [boundary - 1, boundary, boundary + 1, boundary + 2] {
+        assert!(mmr.batch().get_elem(pos).unwrap().is_some());
+    }
+    assert_eq!(mmr.get_root().unwrap(), root);
+
+    assert_eq!(mmr.commit_unit(), Ok(()));
+    for pos in 0..mmr.mmr_size() {
+        assert!((&store).get_elem(pos).unwrap().is_some());
+    }
+    let mmr = MemMMR::<_, MergeNumberHash>::new(mmr.mmr_size(), &store);
+    assert_eq!(mmr.get_root().unwrap(), root);
+}
+
+#[test]
+fn test_proof_has_no_derivable_peaks() {
+    use crate::{helper::get_peaks, ItemRole};
+
+    for count in [32u32, 33, 50, 63] {
+        let store = MemStore::default();
+        let (mmr, _) =
+            MemMMR::<_, MergeNumberHash>::build(&store, (0..count).map(NumberHash::from)).unwrap();
+        // prove the right-most leaf under each peak
+        let peaks = get_peaks(mmr.mmr_size());
+        let pos_list: Vec<u64> = peaks
+            .iter()
+            .map(|peak_pos| peak_pos - pos_height_in_tree(*peak_pos) as u64)
+            .collect();
+        let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+        let items = proof.annotated_items(&pos_list).unwrap();
+        // peak roots which contain proven leaves are derived by the verifier, never sent
+        assert!(items
+            .iter()
+            .all(|info| info.role != ItemRole::Peak && info.role != ItemRole::BaggedRhs));
+        let siblings: u64 = peaks
+            .iter()
+            .map(|peak_pos| pos_height_in_tree(*peak_pos) as u64)
+            .sum();
+        assert_eq!(proof.proof_items().len() as u64, siblings);
+        let root = mmr.get_root().unwrap();
+        let leaves = pos_list
+            .iter()
+            .map(|pos| (*pos, mmr.batch().get_elem(*pos).unwrap().unwrap()))
+            .collect();
+        assert!(proof.verify(root, leaves).unwrap());
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_proof_skeleton_matches_store(count in 10u32..300u32) {
+        use crate::{ItemRole, Merge};
+
+        let store = MemStore::default();
+        let (mmr, mut pos_list) =
+            MemMMR::<_, MergeNumberHash>::build(&store, (0..count).map(NumberHash::from)).unwrap();
+        let mut rng = thread_rng();
+        pos_list.shuffle(&mut rng);
+        pos_list.truncate(rng.gen_range(1..count - 1) as usize);
+        let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+        let items = proof.annotated_items(&pos_list).unwrap();
+        for info in items {
+            let expected = if info.role == ItemRole::BaggedRhs {
+                let mut rhs_peaks: Vec<NumberHash> = crate::helper::get_peaks(mmr.mmr_size())
+                    .into_iter()
+                    .filter(|peak_pos| *peak_pos >= info.pos)
+                    .map(|peak_pos| mmr.batch().get_elem(peak_pos).unwrap().unwrap())
+                    .collect();
+                let mut bagged = rhs_peaks.pop().unwrap();
+                while let Some(left_peak) = rhs_peaks.pop() {
+                    bagged = MergeNumberHash::merge_peaks(&bagged, &left_peak).unwrap();
+                }
+                bagged
+            } else {
+                mmr.batch().get_elem(info.pos).unwrap().unwrap()
+            };
+            prop_assert_eq!(info.value, &expected);
+        }
+    }
+}
+
+#[test]
+fn test_verify_with_count() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[3]]).unwrap();
+    let leaves = vec![(positions[3], NumberHash::from(3))];
+    assert!(proof.verify(root.clone(), leaves.clone()).unwrap());
+    assert!(proof
+        .verify_with_count(root.clone(), leaves.clone(), 11)
+        .unwrap());
+    assert!(!proof
+        .verify_with_count(root.clone(), leaves.clone(), 10)
+        .unwrap());
+    assert!(!proof.verify_with_count(root, leaves, 12).unwrap());
+}
+
+#[test]
+fn test_gen_root_from_proof_at_peak_boundaries() {
+    // 1 -> 2 and 3 -> 4 merge into a new peak, 2 -> 3 and 4 -> 5 add a standalone peak
+    for count in 1..=8 {
+        test_gen_new_root_from_proof(count);
+    }
+}
+
+#[test]
+fn test_gen_root_from_proof_with_invalid_new_leaf() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..4).map(NumberHash::from)).unwrap();
+    let proof = mmr.gen_proof(vec![positions[3]]).unwrap();
+    let leaves = vec![(positions[3], NumberHash::from(3))];
+    let new_pos = mmr.mmr_size();
+    let new_mmr_size = leaf_index_to_mmr_size(4);
+    // mmr_size which doesn't contain the new leaf
+    assert_eq!(
+        proof.calculate_root_with_new_leaf(
+            leaves.clone(),
+            new_pos,
+            NumberHash::from(4),
+            mmr.mmr_size()
+        ),
+        Err(Error::InvalidNewLeaf)
+    );
+    // position beyond the next leaf
+    assert_eq!(
+        proof.calculate_root_with_new_leaf(
+            leaves.clone(),
+            new_pos + 1,
+            NumberHash::from(4),
+            new_mmr_size
+        ),
+        Err(Error::InvalidNewLeaf)
+    );
+    assert!(proof
+        .calculate_root_with_new_leaf(leaves, new_pos, NumberHash::from(4), new_mmr_size)
+        .is_ok());
+}
+
+#[test]
+fn test_grin_proof_format() {
+    use crate::{Merge, ProofFormat};
+
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let elem = |pos: u64| mmr.batch().get_elem(pos).unwrap().unwrap();
+    // Grin bags the peaks 14, 17, 18 as hash(14, hash(17, 18))
+    let grin_root = MergeNumberHash::merge(
+        &elem(14),
+        &MergeNumberHash::merge(&elem(17), &elem(18)).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        mmr.get_root_with_format(ProofFormat::Grin).unwrap(),
+        grin_root
+    );
+    assert_ne!(mmr.get_root().unwrap(), grin_root);
+
+    // path siblings, then bagged rhs peaks, then lhs peaks from right to left
+    let proof = mmr
+        .gen_proof_with_format(vec![positions[8]], ProofFormat::Grin)
+        .unwrap();
+    assert_eq!(proof.proof_items(), &[elem(16), elem(18), elem(14)]);
+
+    for (i, pos) in positions.iter().enumerate() {
+        let proof = mmr
+            .gen_proof_with_format(vec![*pos], ProofFormat::Grin)
+            .unwrap();
+        let leaves = vec![(*pos, NumberHash::from(i as u32))];
+        assert!(proof
+            .verify_with_format(grin_root.clone(), leaves.clone(), ProofFormat::Grin)
+            .unwrap());
+        assert!(!proof
+            .verify_with_format(mmr.get_root().unwrap(), leaves, ProofFormat::Grin)
+            .unwrap());
+    }
+    assert_eq!(
+        mmr.gen_proof_with_format(vec![positions[0], positions[1]], ProofFormat::Grin)
+            .map(|_| ()),
+        Err(Error::GenProofForInvalidLeaves)
+    );
+}
+
+#[test]
+fn test_pos_classification() {
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    assert_eq!(mmr.mmr_size(), 19);
+    for pos in [0, 1, 3, 7, 15, 16, 18] {
+        assert!(mmr.pos_is_leaf(pos));
+        assert_eq!(mmr.pos_height(pos), Some(0));
+    }
+    assert!(!mmr.pos_is_leaf(2));
+    assert_eq!(mmr.pos_height(2), Some(1));
+    assert!(!mmr.pos_is_leaf(14));
+    assert_eq!(mmr.pos_height(14), Some(3));
+    assert_eq!(mmr.pos_height(17), Some(1));
+    // beyond mmr_size
+    assert!(!mmr.pos_is_leaf(19));
+    assert_eq!(mmr.pos_height(19), None);
+    assert_eq!(mmr.pos_height(21), None);
+}
+
+#[test]
+fn test_verify_consecutive() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..20).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let leaves = |indices: &[usize]| -> Vec<(u64, NumberHash)> {
+        indices
+            .iter()
+            .map(|i| (positions[*i], NumberHash::from(*i as u32)))
+            .collect()
+    };
+    let verify = |indices: &[usize]| {
+        let leaves = leaves(indices);
+        let proof = mmr
+            .gen_proof(leaves.iter().map(|(pos, _)| *pos).collect())
+            .unwrap();
+        assert!(proof.verify(root.clone(), leaves.clone()).unwrap());
+        proof.verify_consecutive(root.clone(), leaves).unwrap()
+    };
+    assert!(verify(&[5]));
+    assert!(verify(&[3, 4, 5, 6]));
+    // across peaks and unsorted
+    assert!(verify(&[17, 15, 16]));
+    assert!(!verify(&[3, 5]));
+    assert!(!verify(&[3, 4, 6, 7]));
+    assert!(!verify(&[0, 19]));
+}
+
+#[test]
+fn test_verify_against_peaks() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    let trusted_peaks: Vec<_> = get_peaks(mmr.mmr_size())
+        .into_iter()
+        .map(|pos| (pos, (&store).get_elem(pos).unwrap().unwrap()))
+        .collect();
+    let leaves = vec![(positions[3], NumberHash::from(3))];
+
+    // a full proof proves the leaf in the first peak
+    let proof = mmr.gen_proof(vec![positions[3]]).unwrap();
+    assert!(proof
+        .verify_against_peaks(trusted_peaks.clone(), leaves.clone())
+        .unwrap());
+
+    // the sibling items are enough, the other peaks need no items
+    let siblings: Vec<_> = proof
+        .annotated_items(&[positions[3]])
+        .unwrap()
+        .into_iter()
+        .filter(|info| info.role != ItemRole::Peak && info.role != ItemRole::BaggedRhs)
+        .map(|info| info.value.clone())
+        .collect();
+    assert_eq!(siblings.len(), 3);
+    let proof = MerkleProof::<_, MergeNumberHash>::new(mmr.mmr_size(), siblings);
+    assert!(proof
+        .verify_against_peaks(trusted_peaks.clone(), leaves.clone())
+        .unwrap());
+    // only the peak containing the leaf needs to be trusted
+    assert!(proof
+        .verify_against_peaks(trusted_peaks[..1].to_vec(), leaves.clone())
+        .unwrap());
+    assert!(!proof
+        .verify_against_peaks(trusted_peaks[1..].to_vec(), leaves)
+        .unwrap());
+    assert!(!proof
+        .verify_against_peaks(
+            trusted_peaks.clone(),
+            vec![(positions[3], NumberHash::from(4))]
+        )
+        .unwrap());
+
+    // the leaf is a peak
+    let proof = MerkleProof::<_, MergeNumberHash>::new(mmr.mmr_size(), vec![]);
+    assert!(proof
+        .verify_against_peaks(trusted_peaks, vec![(positions[10], NumberHash::from(10))])
+        .unwrap());
+}
+
+#[test]
+fn test_stats() {
+    let stats = |mmr_size| {
+        MemMMR::<NumberHash, MergeNumberHash>::new(mmr_size, &MemStore::default()).stats()
+    };
+    let expected = |mmr_size, leaf_count, peak_count, height, node_count| MmrStats {
+        mmr_size,
+        leaf_count,
+        peak_count,
+        height,
+        node_count,
+    };
+    assert_eq!(stats(0), expected(0, 0, 0, 0, 0));
+    assert_eq!(stats(1), expected(1, 1, 1, 0, 0));
+    // peaks 6, 7
+    assert_eq!(stats(8), expected(8, 5, 2, 2, 3));
+    // peaks 6, 9, 10
+    assert_eq!(stats(11), expected(11, 7, 3, 2, 4));
+    // peaks 14, 17, 18
+    assert_eq!(stats(19), expected(19, 11, 3, 3, 8));
+}
+
+// the bagging before it was rewritten to fold over a slice
+fn bagging_peaks_hashes_by_vec(mut peaks_hashes: Vec<NumberHash>) -> NumberHash {
+    use crate::Merge;
+    while peaks_hashes.len() > 1 {
+        let right_peak = peaks_hashes.pop().expect("pop");
+        let left_peak = peaks_hashes.pop().expect("pop");
+        peaks_hashes.push(MergeNumberHash::merge_peaks(&right_peak, &left_peak).unwrap());
+    }
+    peaks_hashes.pop().expect("pop")
+}
+
+proptest! {
+    #[test]
+    fn test_bagging_peaks_hashes_matches_vec_bagging(peaks in prop::collection::vec(any::<u32>(), 1..=64)) {
+        let peaks: Vec<_> = peaks.into_iter().map(NumberHash::from).collect();
+        let root = crate::mmr::bagging_peaks_hashes::<_, MergeNumberHash>(&peaks, &mut { usize::MAX }).unwrap();
+        prop_assert_eq!(root, bagging_peaks_hashes_by_vec(peaks));
+    }
+}
+
+// records the positions read from the inner store
+struct ReadRecordingStore<'a> {
+    store: &'a MemStore<NumberHash>,
+    reads: std::cell::RefCell<Vec<u64>>,
+}
+
+impl MMRStoreReadOps<NumberHash> for ReadRecordingStore<'_> {
+    fn get_elem(&self, pos: u64) -> crate::Result<Option<NumberHash>> {
+        self.reads.borrow_mut().push(pos);
+        self.store.get_elem(pos)
+    }
+}
+
+#[test]
+fn test_gen_proof_reads_no_proven_leaves() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    let mmr_size = mmr.mmr_size();
+
+    for leaves in [
+        vec![0],
+        vec![0, 1],
+        vec![3, 4, 5],
+        vec![7],
+        // the last leaf is a peak
+        vec![10],
+        vec![0, 5, 10],
+        (0..11).collect(),
+    ] {
+        let pos_list: Vec<u64> = leaves.iter().map(|i| positions[*i]).collect();
+        let mmr = MMR::<_, MergeNumberHash, _>::new(
+            mmr_size,
+            ReadRecordingStore {
+                store: &store,
+                reads: Default::default(),
+            },
+        );
+        mmr.gen_proof(pos_list.clone()).unwrap();
+        let reads = mmr.store().reads.borrow();
+        assert!(
+            reads.iter().all(|pos| !pos_list.contains(pos)),
+            "leaves {:?} reads {:?}",
+            leaves,
+            reads
+        );
+    }
+}
+
+#[test]
+fn test_from_items() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let pos_list = vec![positions[2], positions[9]];
+    let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+    let items: Vec<_> = proof
+        .annotated_items(&pos_list)
+        .unwrap()
+        .into_iter()
+        .map(|info| (info.pos, info.value.clone()))
+        .collect();
+
+    let rebuilt =
+        MerkleProof::<_, MergeNumberHash>::from_items(mmr.mmr_size(), items.clone()).unwrap();
+    assert_eq!(rebuilt.proof_items(), proof.proof_items());
+    assert_eq!(
+        rebuilt.item_positions().unwrap(),
+        items
+            .iter()
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>()
+            .as_slice()
+    );
+    // the positions of a generated proof are known from its skeleton
+    assert_eq!(proof.item_positions(), rebuilt.item_positions());
+    assert_eq!(
+        MerkleProof::<_, MergeNumberHash>::new(proof.mmr_size(), proof.proof_items().to_vec())
+            .item_positions(),
+        None
+    );
+    assert!(rebuilt
+        .verify(
+            root,
+            vec![
+                (positions[2], NumberHash::from(2)),
+                (positions[9], NumberHash::from(9))
+            ]
+        )
+        .unwrap());
+
+    // duplicated positions
+    let mut duplicated = items.clone();
+    duplicated.push(items[0].clone());
+    assert_eq!(
+        MerkleProof::<_, MergeNumberHash>::from_items(mmr.mmr_size(), duplicated).map(|_| ()),
+        Err(Error::CorruptedProof)
+    );
+    // beyond the mmr
+    let mut beyond = items;
+    beyond.push((mmr.mmr_size(), NumberHash::from(0)));
+    assert_eq!(
+        MerkleProof::<_, MergeNumberHash>::from_items(mmr.mmr_size(), beyond).map(|_| ()),
+        Err(Error::CorruptedProof)
+    );
+}
+
+#[test]
+fn test_proof_debug() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let pos_list = vec![positions[5]];
+    let proof = mmr.gen_proof(pos_list).unwrap();
+    assert_eq!(
+        format!("{:?}", proof.short_hex()),
+        "MerkleProof { mmr_size: 19, items: [7: 26a08e4d.., 12: 64fa1a16.., 6: f0c1d8dd.., 17: 5e7bc663..] }"
+    );
+    // positions are unknown without the skeleton
+    let proof =
+        MerkleProof::<_, MergeNumberHash>::new(proof.mmr_size(), proof.proof_items().to_vec());
+    assert_eq!(
+        format!("{:?}", proof.short_hex()),
+        "MerkleProof { mmr_size: 19, items: [26a08e4d.., 64fa1a16.., f0c1d8dd.., 5e7bc663..] }"
+    );
+    let proof = MerkleProof::<_, MergeNumberHash>::new(19, vec![NumberHash(vec![1, 2].into())]);
+    assert_eq!(
+        format!("{:?}", proof.short_hex()),
+        "MerkleProof { mmr_size: 19, items: [0102] }"
+    );
+    // the items are printed by `Debug` of any other item
+    assert_eq!(
+        format!("{:?}", proof),
+        r#"MerkleProof { mmr_size: 19, items: [NumberHash(b"\x01\x02")] }"#
+    );
+}
+
+#[test]
+fn test_gen_proof_excluding() {
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    // the leaves at position 3 and 4 are siblings
+    let proof = mmr.gen_proof(vec![3]).unwrap();
+    let excluding = mmr.gen_proof_excluding(vec![3], vec![4]).unwrap();
+    assert_eq!(excluding.proof_items().len(), proof.proof_items().len() - 1);
+    assert!(excluding
+        .annotated_items(&[3, 4])
+        .unwrap()
+        .iter()
+        .all(|info| info.pos != 4));
+    assert!(excluding
+        .verify(
+            root.clone(),
+            vec![(3, NumberHash::from(2)), (4, NumberHash::from(3))]
+        )
+        .unwrap());
+    // the known leaf must be supplied
+    assert_ne!(
+        excluding.verify(root, vec![(3, NumberHash::from(2))]),
+        Ok(true)
+    );
+}
+
+#[test]
+fn test_self_check() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let leaves = vec![(positions[5], NumberHash::from(5))];
+    let proof = mmr.gen_proof(vec![positions[5]]).unwrap();
+    assert_eq!(proof.self_check(leaves.clone()), Ok(root));
+
+    // truncated proof, dropping the bagged rhs item still calculates a root
+    let items = proof.proof_items();
+    let truncated =
+        MerkleProof::<_, MergeNumberHash>::new(proof.mmr_size(), items[..items.len() - 1].to_vec());
+    assert!(truncated.calculate_root(leaves.clone()).is_ok());
+    assert_eq!(
+        truncated.self_check(leaves.clone()),
+        Err(Error::CorruptedProof)
+    );
+    let truncated = MerkleProof::<_, MergeNumberHash>::new(proof.mmr_size(), items[1..].to_vec());
+    assert_eq!(
+        truncated.self_check(leaves.clone()),
+        Err(Error::CorruptedProof)
+    );
+    // a proof of only peaks proves nothing
+    assert_eq!(proof.self_check(vec![]), Err(Error::CorruptedProof));
+    // invalid mmr_size
+    let invalid = MerkleProof::<_, MergeNumberHash>::new(proof.mmr_size() + 1, items.to_vec());
+    assert_eq!(invalid.self_check(leaves), Err(Error::CorruptedProof));
+    // leaf beyond the mmr
+    assert_eq!(
+        proof.self_check(vec![(mmr.mmr_size(), NumberHash::from(11))]),
+        Err(Error::CorruptedProof)
+    );
+}
+
+#[test]
+fn test_with_capacity() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::with_capacity(0, &store, 5);
+    for i in 0u32..5 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    assert_eq!(mmr.push(NumberHash::from(5)), Err(Error::CapacityExceeded));
+    assert_eq!(
+        mmr.push_observed(NumberHash::from(5)).map(|_| ()),
+        Err(Error::CapacityExceeded)
+    );
+    assert_eq!(mmr.mmr_size(), leaf_index_to_mmr_size(4));
+    mmr.commit().unwrap();
+
+    // reopening at an earlier size restores the headroom
+    let mut mmr = MemMMR::<_, MergeNumberHash>::with_capacity(leaf_index_to_mmr_size(3), &store, 5);
+    mmr.push(NumberHash::from(4)).unwrap();
+    assert_eq!(mmr.push(NumberHash::from(5)), Err(Error::CapacityExceeded));
+
+    // a batch is rejected as a whole
+    let mut mmr = MemMMR::<_, MergeNumberHash>::with_capacity(leaf_index_to_mmr_size(2), &store, 5);
+    let elems: Vec<_> = (3u32..6).map(NumberHash::from).collect();
+    assert_eq!(mmr.push_batch(elems), Err(Error::CapacityExceeded));
+    assert_eq!(mmr.mmr_size(), leaf_index_to_mmr_size(2));
+    let elems: Vec<_> = (3u32..5).map(NumberHash::from).collect();
+    assert_eq!(
+        mmr.push_batch(elems).map(|positions| positions.len()),
+        Ok(2)
+    );
+}
+
+#[test]
+fn test_calculate_peak_root_from_interior_nodes() {
+    use crate::mmr::calculate_peak_root;
+
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    let elem = |pos| (&store).get_elem(pos).unwrap().unwrap();
+    let node = |pos| (pos, elem(pos), pos_height_in_tree(pos));
+
+    // node 5 at height 1 needs its sibling 2 and then 13
+    let root = calculate_peak_root::<_, MergeNumberHash, _>(
+        vec![node(5)],
+        14,
+        &mut [elem(2), elem(13)].iter(),
+        &mut { usize::MAX },
+    )
+    .unwrap();
+    assert_eq!(root, elem(14));
+
+    // nodes of different heights, 5 and 9 are only reachable after merging the leaf 0
+    let proof = [elem(1), elem(12)];
+    let mut proof_iter = proof.iter();
+    let root = calculate_peak_root::<_, MergeNumberHash, _>(
+        vec![node(9), node(0), node(5)],
+        14,
+        &mut proof_iter,
+        &mut { usize::MAX },
+    )
+    .unwrap();
+    assert_eq!(root, elem(14));
+    assert!(proof_iter.next().is_none());
+}
+
+// claims distinct peak hashing but forgets to override `merge_peaks`
+struct ForgotMergePeaks;
+
+impl crate::Merge for ForgotMergePeaks {
+    type Item = NumberHash;
+
+    const PEAKS_DISTINCT: bool = true;
+
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> crate::Result<Self::Item> {
+        <MergeNumberHash as crate::Merge>::merge(lhs, rhs)
+    }
+}
+
+struct DistinctMergePeaks;
+
+impl crate::Merge for DistinctMergePeaks {
+    type Item = NumberHash;
+
+    const PEAKS_DISTINCT: bool = true;
+
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> crate::Result<Self::Item> {
+        <MergeNumberHash as crate::Merge>::merge(lhs, rhs)
+    }
+
+    fn merge_peaks(lhs: &Self::Item, rhs: &Self::Item) -> crate::Result<Self::Item> {
+        <MergeNumberHash as crate::Merge>::merge(rhs, lhs)
+    }
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "merge_peaks is the same as merge")]
+fn test_peaks_distinct_without_override() {
+    let store = MemStore::default();
+    let _ = MemMMR::<_, ForgotMergePeaks>::build(&store, (0u32..3).map(NumberHash::from));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "merge_peaks is the same as merge")]
+fn test_peaks_distinct_without_override_from_peaks() {
+    let store = MemStore::default();
+    // 3 leaves have 2 peaks
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..3).map(NumberHash::from)).unwrap();
+    let peaks = mmr.get_peak_values().unwrap();
+    let store = MemStore::default();
+    let _ = MMR::<_, ForgotMergePeaks, _>::from_peaks(mmr.mmr_size(), peaks, &store);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "merge_peaks is the same as merge")]
+fn test_peaks_distinct_without_override_push() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, ForgotMergePeaks>::new(0, &store);
+    mmr.push(NumberHash::from(0)).unwrap();
+    // the check runs once the second leaf is pushed
+    let _ = mmr.push(NumberHash::from(1));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic(expected = "merge_peaks is the same as merge")]
+fn test_peaks_distinct_without_override_push_batch() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, ForgotMergePeaks>::new(0, &store);
+    let _ = mmr.push_batch(vec![NumberHash::from(0), NumberHash::from(1)]);
+}
+
+#[test]
+fn test_peaks_distinct_push_committed_leaf() {
+    // the first leaf is read from the store when the second one is pushed
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, DistinctMergePeaks>::new(0, &store);
+    mmr.push(NumberHash::from(0)).unwrap();
+    mmr.commit().unwrap();
+    let mut mmr = MemMMR::<_, DistinctMergePeaks>::new(1, &store);
+    assert_eq!(mmr.push(NumberHash::from(1)), Ok(1));
+    assert_eq!(mmr.mmr_size(), 3);
+}
+
+#[test]
+fn test_check_peaks_distinct() {
+    let (a, b) = (NumberHash::from(0), NumberHash::from(1));
+    assert_eq!(check_peaks_distinct::<ForgotMergePeaks>(&a, &b), Ok(false));
+    assert_eq!(check_peaks_distinct::<DistinctMergePeaks>(&a, &b), Ok(true));
+    assert_eq!(check_peaks_distinct::<MergeNumberHash>(&a, &b), Ok(true));
+
+    // verification doesn't check it
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..3).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[2]]).unwrap();
+    let proof =
+        MerkleProof::<_, ForgotMergePeaks>::new(proof.mmr_size(), proof.proof_items().to_vec());
+    assert_eq!(
+        proof.verify(root, vec![(positions[2], NumberHash::from(2))]),
+        Ok(true)
+    );
+}
+
+#[test]
+fn test_peaks_distinct_with_override() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, DistinctMergePeaks>::build(&store, (0u32..3).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[2]]).unwrap();
+    assert!(proof
+        .verify(root, vec![(positions[2], NumberHash::from(2))])
+        .unwrap());
+}
+
+#[test]
+fn test_push_info() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    let infos: Vec<_> = (0u32..4)
+        .map(|i| mmr.push_info(NumberHash::from(i)).unwrap())
+        .collect();
+    let info = |leaf_pos, new_mmr_size, merges_performed, new_peak_pos| PushInfo {
+        leaf_pos,
+        new_mmr_size,
+        merges_performed,
+        new_peak_pos,
+    };
+    // a new peak
+    assert_eq!(infos[0], info(0, 1, 0, 0));
+    // completes the subtree of 2
+    assert_eq!(infos[1], info(1, 3, 1, 2));
+    assert_eq!(infos[2], info(3, 4, 0, 3));
+    // completes the subtrees of 4 and 6
+    assert_eq!(infos[3], info(4, 7, 2, 6));
+    assert_eq!(mmr.mmr_size(), 7);
+
+    // the same nodes as `push`
+    let other_store = MemStore::default();
+    let mut other = MemMMR::<_, MergeNumberHash>::new(0, &other_store);
+    for i in 0u32..4 {
+        other.push(NumberHash::from(i)).unwrap();
+    }
+    assert_eq!(mmr.get_root(), other.get_root());
+}
+
+proptest! {
+    #[test]
+    fn test_push_batch_matches_push(prefix in 0u32..50, counts in prop::collection::vec(0u32..40, 1..5)) {
+        let store = MemStore::default();
+        let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+        let other_store = MemStore::default();
+        let mut other = MemMMR::<_, MergeNumberHash>::new(0, &other_store);
+        let mut next = 0u32;
+        for _ in 0..prefix {
+            mmr.push(NumberHash::from(next)).unwrap();
+            other.push(NumberHash::from(next)).unwrap();
+            next += 1;
+        }
+        mmr.commit().unwrap();
+        for count in counts {
+            let elems: Vec<_> = (next..next + count).map(NumberHash::from).collect();
+            next += count;
+            let expected: Vec<u64> = elems.iter().map(|elem| other.push(elem.clone()).unwrap()).collect();
+            prop_assert_eq!(mmr.push_batch(elems).unwrap(), expected);
+            prop_assert_eq!(mmr.mmr_size(), other.mmr_size());
+            if next > 0 {
+                prop_assert_eq!(mmr.get_root().unwrap(), other.get_root().unwrap());
+            }
+        }
+        // the parents are persisted by commit
+        mmr.commit().unwrap();
+        other.commit().unwrap();
+        for pos in 0..mmr.mmr_size() {
+            prop_assert_eq!((&store).get_elem(pos).unwrap(), (&other_store).get_elem(pos).unwrap());
+        }
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_push_batch_parallel() {
+    for prefix in [0u32, 1, 7, 100] {
+        let store = MemStore::default();
+        let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+        let other_store = MemStore::default();
+        let mut other = MemMMR::<_, MergeNumberHash>::new(0, &other_store);
+        let prefix_elems: Vec<_> = (0..prefix).map(NumberHash::from).collect();
+        mmr.push_batch(prefix_elems.clone()).unwrap();
+        other.push_batch(prefix_elems).unwrap();
+        mmr.commit().unwrap();
+        let mut next = prefix;
+        for count in [5000u32, 1, 0, 3000] {
+            let elems: Vec<_> = (next..next + count).map(NumberHash::from).collect();
+            next += count;
+            assert_eq!(
+                mmr.push_batch_parallel(elems.clone()).unwrap(),
+                other.push_batch(elems).unwrap()
+            );
+            assert_eq!(mmr.mmr_size(), other.mmr_size());
+            assert_eq!(mmr.get_root(), other.get_root());
+        }
+        mmr.commit().unwrap();
+        other.commit().unwrap();
+        for pos in 0..mmr.mmr_size() {
+            assert_eq!(
+                (&store).get_elem(pos).unwrap(),
+                (&other_store).get_elem(pos).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_rollback() {
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..20).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    let target = leaf_index_to_mmr_size(11);
+    assert_eq!(mmr.rollback(target - 1), Err(Error::InvalidMMRSize));
+    assert_eq!(mmr.rollback(mmr.mmr_size() + 1), Err(Error::InvalidMMRSize));
+    mmr.rollback(target).unwrap();
+    assert_eq!(mmr.mmr_size(), target);
+    for pos in target..leaf_index_to_mmr_size(19) {
+        assert_eq!((&store).get_elem(pos).unwrap(), None, "pos {}", pos);
+    }
+
+    let expected_store = MemStore::default();
+    let (expected, _) =
+        MemMMR::<_, MergeNumberHash>::build(&expected_store, (0u32..12).map(NumberHash::from))
+            .unwrap();
+    let root_of_12 = expected.get_root();
+    assert_eq!(mmr.get_root(), root_of_12);
+
+    // staged nodes are dropped too, committed and staged ones mixed
+    for i in 12u32..15 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    mmr.commit().unwrap();
+    for i in 15u32..18 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    mmr.rollback(target).unwrap();
+    assert_eq!(mmr.get_root(), root_of_12);
+    mmr.push(NumberHash::from(12)).unwrap();
+    mmr.commit().unwrap();
+    let (expected, _) =
+        MemMMR::<_, MergeNumberHash>::build(&expected_store, (0u32..13).map(NumberHash::from))
+            .unwrap();
+    assert_eq!(mmr.get_root(), expected.get_root());
+
+    // staged only, rolling back inside a segment
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..20).map(NumberHash::from)).unwrap();
+    mmr.rollback(target).unwrap();
+    mmr.commit().unwrap();
+    assert_eq!((&store).get_elem(target).unwrap(), None);
+    assert_eq!(mmr.get_root(), root_of_12);
+    mmr.rollback(0).unwrap();
+    assert_eq!(mmr.get_root(), Err(Error::GetRootOnEmpty));
+}
+
+#[test]
+fn test_update() {
+    let root_of = |leaves: &[u32]| {
+        let store = MemStore::default();
+        let (mmr, _) = MemMMR::<_, MergeNumberHash>::build(
+            &store,
+            leaves.iter().copied().map(NumberHash::from),
+        )
+        .unwrap();
+        mmr.get_root()
+    };
+    let mut leaves: Vec<u32> = (0..11).collect();
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, leaves.iter().copied().map(NumberHash::from))
+            .unwrap();
+    mmr.commit().unwrap();
+    // staged leaves after the committed ones
+    for i in 11u32..14 {
+        leaves.push(i);
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    let positions: Vec<u64> = positions
+        .into_iter()
+        .chain((11..14).map(leaf_index_to_pos))
+        .collect();
+
+    for (index, value) in [(5, 100), (0, 101), (10, 102), (12, 103), (13, 104)] {
+        leaves[index] = value;
+        mmr.update(positions[index], NumberHash::from(value))
+            .unwrap();
+        let expected_root = root_of(&leaves);
+        assert_eq!(mmr.get_root(), expected_root, "leaf {}", index);
+        let proof = mmr.gen_proof(vec![positions[index]]).unwrap();
+        assert!(proof
+            .verify(
+                expected_root.unwrap(),
+                vec![(positions[index], NumberHash::from(value))]
+            )
+            .unwrap());
+    }
+    // the committed nodes are overwritten by the commit
+    assert_eq!(
+        (&store).get_elem(positions[5]),
+        Ok(Some(NumberHash::from(5)))
+    );
+    mmr.commit().unwrap();
+    assert_eq!(
+        (&store).get_elem(positions[5]),
+        Ok(Some(NumberHash::from(100)))
+    );
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(mmr.mmr_size(), &store);
+    assert_eq!(mmr.get_root(), root_of(&leaves));
+
+    // a rollback drops the pending updates of the removed nodes
+    let mmr_size = mmr.mmr_size();
+    mmr.update(positions[13], NumberHash::from(105)).unwrap();
+    mmr.rollback(leaf_index_to_mmr_size(12)).unwrap();
+    mmr.push(NumberHash::from(leaves[13])).unwrap();
+    assert_eq!(mmr.mmr_size(), mmr_size);
+    assert_eq!(mmr.get_root(), root_of(&leaves));
+    mmr.commit().unwrap();
+    assert_eq!(
+        (&store).get_elem(positions[13]),
+        Ok(Some(NumberHash::from(leaves[13])))
+    );
+
+    assert_eq!(mmr.update(2, NumberHash::from(0)), Err(Error::NotLeaf(2)));
+    let mmr_size = mmr.mmr_size();
+    assert_eq!(
+        mmr.update(mmr_size, NumberHash::from(0)),
+        Err(Error::NotLeaf(mmr_size))
+    );
+    // a missing sibling leaves the MMR untouched
+    (&store).delete(1).unwrap();
+    assert_eq!(
+        mmr.update(0, NumberHash::from(0)),
+        Err(Error::InconsistentStore)
+    );
+    assert_eq!(mmr.get_root(), root_of(&leaves));
+}
+
+#[test]
+fn test_gen_proof_with_positions() {
+    use crate::Merge;
+
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    assert_eq!(mmr.mmr_size(), 19);
+    let elem = |pos| mmr.batch().get_elem(pos).unwrap().unwrap();
+    //           14
+    //        /       \
+    //      6          13
+    //    /   \       /   \
+    //   2     5     9     12     17
+    //  / \   /  \  / \   /  \   /  \
+    // 0   1 3   4 7   8 10  11 15  16 18
+    let (proof, positions) = mmr.gen_proof_with_positions(vec![3]).unwrap();
+    // 17 and 18 are bagged
+    assert_eq!(positions, vec![4, 2, 13, 17]);
+    let items = proof.proof_items();
+    assert_eq!(items[..3], [elem(4), elem(2), elem(13)]);
+    assert_eq!(
+        items[3],
+        MergeNumberHash::merge_peaks(&elem(18), &elem(17)).unwrap()
+    );
+
+    let (proof, positions) = mmr.gen_proof_with_positions(vec![15, 7]).unwrap();
+    assert_eq!(positions, vec![8, 12, 6, 16, 18]);
+    let expected: Vec<_> = positions.iter().map(|pos| elem(*pos)).collect();
+    assert_eq!(proof.proof_items(), &expected[..]);
+    assert_eq!(
+        proof.proof_items(),
+        mmr.gen_proof(vec![7, 15]).unwrap().proof_items()
+    );
+}
+
+#[test]
+fn test_gen_proof_ordered() {
+    use crate::ProofFormat;
+
+    for (count, pos_lists) in [
+        (11u32, vec![vec![3], vec![7, 15], vec![0, 10, 18], vec![13]]),
+        (15, vec![vec![0], vec![4, 11, 23], vec![16, 24]]),
+        (100, vec![vec![0, 50, 196], vec![120, 150, 190], vec![195]]),
+    ] {
+        let store = MemStore::default();
+        let (mmr, _) =
+            MemMMR::<_, MergeNumberHash>::build(&store, (0u32..count).map(NumberHash::from))
+                .unwrap();
+        let root = mmr.get_root().unwrap();
+        for pos_list in pos_lists {
+            let (proof, positions) = mmr.gen_proof_with_positions(pos_list.clone()).unwrap();
+            let mut sorted: Vec<_> = positions
+                .into_iter()
+                .zip(proof.proof_items().iter().cloned())
+                .collect();
+            sorted.sort_by_key(|(pos, _)| *pos);
+            let ordered = mmr.gen_proof_ordered(pos_list.clone()).unwrap();
+            let expected: Vec<_> = sorted.into_iter().map(|(_, item)| item).collect();
+            assert_eq!(ordered.proof_items(), &expected[..], "{:?}", pos_list);
+
+            let leaves: Vec<_> = pos_list
+                .iter()
+                .map(|pos| (*pos, mmr.batch().get_elem(*pos).unwrap().unwrap()))
+                .collect();
+            assert!(ordered
+                .verify_with_format(root.clone(), leaves.clone(), ProofFormat::Ordered)
+                .unwrap());
+            let mut wrong_leaves = leaves;
+            wrong_leaves[0].1 = NumberHash::from(1000);
+            assert!(!ordered
+                .verify_with_format(root.clone(), wrong_leaves, ProofFormat::Ordered)
+                .unwrap());
+        }
+    }
+
+    // the path of 7 goes down and up, 12 comes before 6 in a `Ckb` proof
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let proof = mmr
+        .gen_proof_with_format(vec![7], ProofFormat::Ordered)
+        .unwrap();
+    let elem = |pos| mmr.batch().get_elem(pos).unwrap().unwrap();
+    assert_eq!(proof.proof_items()[..3], [elem(6), elem(8), elem(12)]);
+    assert_eq!(
+        mmr.get_root_with_format(ProofFormat::Ordered),
+        mmr.get_root()
+    );
+}
+
+#[test]
+fn test_non_membership_proof() {
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    assert_eq!(
+        mmr.gen_non_membership_proof(10).map(|_| ()),
+        Err(Error::LeafIndexOutOfRange)
+    );
+    assert_eq!(
+        mmr.gen_non_membership_proof(0).map(|_| ()),
+        Err(Error::LeafIndexOutOfRange)
+    );
+    for leaf_index in [11, 12, 1000, u64::MAX] {
+        let proof = mmr.gen_non_membership_proof(leaf_index).unwrap();
+        assert_eq!(proof.leaf_index(), leaf_index);
+        assert_eq!(proof.mmr_size(), mmr.mmr_size());
+        assert_eq!(proof.peaks().len(), 3);
+        assert!(proof.verify(root.clone()).unwrap());
+        assert!(!proof.verify(NumberHash::from(0)).unwrap());
+    }
+
+    // the proof doesn't verify against a MMR where the leaf exists
+    let other_store = MemStore::default();
+    let (other, _) =
+        MemMMR::<_, MergeNumberHash>::build(&other_store, (0u32..12).map(NumberHash::from))
+            .unwrap();
+    let proof = mmr.gen_non_membership_proof(11).unwrap();
+    assert!(!proof.verify(other.get_root().unwrap()).unwrap());
+    assert!(other
+        .gen_non_membership_proof(12)
+        .unwrap()
+        .verify(other.get_root().unwrap())
+        .unwrap());
+
+    let empty_store = MemStore::<NumberHash>::default();
+    let empty = MemMMR::<_, MergeNumberHash>::new(0, &empty_store);
+    assert_eq!(
+        empty.gen_non_membership_proof(0).map(|_| ()),
+        Err(Error::GetRootOnEmpty)
+    );
+}
+
+#[test]
+fn test_merge_with_height() {
+    use crate::{helper::get_peak_map, Merge, Result};
+
+    // tags every parent with its height
+    struct HeightMerge;
+
+    impl Merge for HeightMerge {
+        type Item = NumberHash;
+        fn merge(lhs: &Self::Item, rhs: &Self::Item) -> Result<Self::Item> {
+            Self::merge_with_height(lhs, rhs, 0)
+        }
+
+        fn merge_with_height(lhs: &Self::Item, rhs: &Self::Item, height: u8) -> Result<Self::Item> {
+            let mut data = vec![height];
+            data.extend_from_slice(&lhs.0);
+            data.extend_from_slice(&rhs.0);
+            Ok(NumberHash::from(data))
+        }
+
+        fn merge_peaks(lhs: &Self::Item, rhs: &Self::Item) -> Result<Self::Item> {
+            MergeNumberHash::merge(lhs, rhs)
+        }
+    }
+
+    let leaves: Vec<NumberHash> = (0u32..4).map(NumberHash::from).collect();
+    let store = MemStore::default();
+    let (mmr, positions) = MemMMR::<_, HeightMerge>::build(&store, leaves.clone()).unwrap();
+    let node = |left: &NumberHash, right: &NumberHash, height| {
+        HeightMerge::merge_with_height(left, right, height).unwrap()
+    };
+    let internal_nodes = vec![
+        node(&leaves[0], &leaves[1], 1),
+        node(&leaves[2], &leaves[3], 1),
+    ];
+    let root = node(&internal_nodes[0], &internal_nodes[1], 2);
+    assert_eq!(mmr.get_root(), Ok(root.clone()));
+    let proof = mmr.gen_proof(vec![positions[2]]).unwrap();
+    assert!(proof
+        .verify(root.clone(), vec![(positions[2], leaves[2].clone())])
+        .unwrap());
+
+    // the internal nodes pushed as leaves build a tree of another root
+    let other_store = MemStore::default();
+    let (other, _) = MemMMR::<_, HeightMerge>::build(&other_store, internal_nodes.clone()).unwrap();
+    assert_ne!(other.get_root(), Ok(root));
+    // which is a second preimage if the height isn't tagged
+    let plain_internal_nodes = vec![
+        MergeNumberHash::merge(&leaves[0], &leaves[1]).unwrap(),
+        MergeNumberHash::merge(&leaves[2], &leaves[3]).unwrap(),
+    ];
+    let plain_store = MemStore::default();
+    let (plain, _) = MemMMR::<_, MergeNumberHash>::build(&plain_store, leaves).unwrap();
+    let plain_other_store = MemStore::default();
+    let (plain_other, _) =
+        MemMMR::<_, MergeNumberHash>::build(&plain_other_store, plain_internal_nodes).unwrap();
+    assert_eq!(plain.get_root(), plain_other.get_root());
+
+    // every path merges by the height, bit-identical to pushing one by one
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, HeightMerge>::new(0, &store);
+    let other_store = MemStore::default();
+    let mut other = MemMMR::<_, HeightMerge>::new(0, &other_store);
+    for i in 0u32..20 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    other
+        .push_batch((0u32..20).map(NumberHash::from).collect())
+        .unwrap();
+    assert_eq!(mmr.get_root(), other.get_root());
+    assert_eq!(get_peak_map(mmr.mmr_size()), 20);
+    let root = mmr.get_root().unwrap();
+    let pos_list = vec![leaf_index_to_pos(3), leaf_index_to_pos(17)];
+    let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+    let proven: Vec<_> = pos_list
+        .iter()
+        .zip([3u32, 17])
+        .map(|(pos, i)| (*pos, NumberHash::from(i)))
+        .collect();
+    assert!(proof.verify(root, proven).unwrap());
+}
+
+#[test]
+fn test_new_checked() {
+    let store = MemStore::<NumberHash>::default();
+    for mmr_size in [0, 1, 4, 19] {
+        let mmr = MemMMR::<_, MergeNumberHash>::new_checked(mmr_size, &store).unwrap();
+        assert_eq!(mmr.mmr_size(), mmr_size);
+    }
+    for mmr_size in [2, 5, 6, 9] {
+        assert_eq!(
+            MemMMR::<_, MergeNumberHash>::new_checked(mmr_size, &store).map(|_| ()),
+            Err(Error::InvalidMMRSize)
+        );
+    }
+}
+
+#[test]
+fn test_proof_errors() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let leaves = vec![(positions[2], NumberHash::from(2))];
+    let proof = mmr.gen_proof(vec![positions[2]]).unwrap();
+    let items = proof.proof_items();
+    let verify = |items: &[NumberHash], leaves: Vec<(u64, NumberHash)>| {
+        MerkleProof::<_, MergeNumberHash>::new(mmr.mmr_size(), items.to_vec())
+            .verify(root.clone(), leaves)
+    };
+    assert_eq!(verify(items, leaves.clone()), Ok(true));
+    // the path to the peak is cut
+    assert_eq!(
+        verify(&items[..2], leaves.clone()),
+        Err(Error::ProofItemsExhausted)
+    );
+    // extra items are taken as the peaks 17 and 18 and the bagged rhs, one more is too many
+    let mut longer = items.to_vec();
+    for _ in 0..2 {
+        longer.push(NumberHash::from(0));
+        assert_eq!(verify(&longer, leaves.clone()), Ok(false));
+    }
+    longer.push(NumberHash::from(0));
+    assert_eq!(
+        verify(&longer, leaves.clone()),
+        Err(Error::ProofItemsLeftover)
+    );
+    assert_eq!(
+        verify(items, vec![(mmr.mmr_size(), NumberHash::from(2))]),
+        Err(Error::PositionOutOfRange)
+    );
+    // 0 and 6 are merged up to 14 before their siblings
+    assert_eq!(
+        verify(
+            items,
+            vec![(0, NumberHash::from(0)), (6, NumberHash::from(6))]
+        ),
+        Err(Error::CorruptedProof)
+    );
+}
+
+#[test]
+fn test_verify_detailed() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let indices = [9usize, 1, 5, 1];
+    let pos_list: Vec<u64> = indices.iter().map(|i| positions[*i]).collect();
+    let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+    let mut leaves: Vec<_> = indices
+        .iter()
+        .map(|i| (positions[*i], NumberHash::from(*i as u32)))
+        .collect();
+    let report = proof.verify_detailed(root.clone(), leaves.clone()).unwrap();
+    assert!(report.is_valid());
+    assert_eq!(report.computed_root, root);
+    assert_eq!(
+        report.leaf_positions,
+        vec![positions[1], positions[5], positions[9]]
+    );
+
+    leaves[2].1 = NumberHash::from(100);
+    let report = proof.verify_detailed(root.clone(), leaves.clone()).unwrap();
+    assert!(!report.is_valid());
+    assert_eq!(report.expected_root, root);
+    assert_ne!(report.computed_root, root);
+    assert_eq!(
+        Ok(report.computed_root),
+        proof.calculate_root(leaves.clone())
+    );
+    assert_eq!(proof.verify(root.clone(), leaves), Ok(false));
+
+    // malformed proofs are still errors
+    let truncated =
+        MerkleProof::<_, MergeNumberHash>::new(proof.mmr_size(), proof.proof_items()[..1].to_vec());
+    let leaves: Vec<_> = pos_list
+        .iter()
+        .zip(indices)
+        .map(|(pos, i)| (*pos, NumberHash::from(i as u32)))
+        .collect();
+    assert_eq!(
+        truncated.verify_detailed(root, leaves),
+        Err(Error::ProofItemsExhausted)
+    );
+}
+
+#[test]
+fn test_leaves() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    assert_eq!(mmr.leaves().count(), 0);
+    let mut positions: Vec<u64> = (0u32..12)
+        .map(|i| mmr.push(NumberHash::from(i)).unwrap())
+        .collect();
+    mmr.commit().unwrap();
+    // staged leaves are yielded too
+    positions.extend((12u32..20).map(|i| mmr.push(NumberHash::from(i)).unwrap()));
+    let leaves: Vec<_> = mmr.leaves().collect::<crate::Result<_>>().unwrap();
+    let expected: Vec<_> = positions
+        .into_iter()
+        .zip((0u32..20).map(NumberHash::from))
+        .collect();
+    assert_eq!(leaves, expected);
+    assert!(leaves.iter().all(|(pos, _)| pos_height_in_tree(*pos) == 0));
+
+    // lazy, a missing leaf fails only once reached
+    (&store).delete(leaf_index_to_pos(3)).unwrap();
+    let mut leaves = mmr.leaves();
+    assert_eq!(leaves.nth(2).unwrap().unwrap().1, NumberHash::from(2));
+    assert_eq!(leaves.next(), Some(Err(Error::InconsistentStore)));
+    assert_eq!(leaves.count(), 16);
+}
+
+#[test]
+fn test_export_import_nodes() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..30).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    let nodes: Vec<_> = mmr.nodes().collect::<crate::Result<_>>().unwrap();
+    assert_eq!(nodes.len() as u64, mmr.mmr_size());
+    assert!(nodes.iter().map(|(pos, _)| *pos).eq(0..mmr.mmr_size()));
+
+    let imported_store = MemStore::default();
+    let mut imported =
+        MemMMR::<_, MergeNumberHash>::import_nodes(mmr.mmr_size(), mmr.nodes(), &imported_store)
+            .unwrap();
+    assert_eq!(imported.get_root(), mmr.get_root());
+    imported.commit().unwrap();
+    for (pos, elem) in &nodes {
+        assert_eq!(
+            (&imported_store).get_elem(*pos).unwrap().as_ref(),
+            Some(elem)
+        );
+    }
+    let proof = imported.gen_proof(vec![positions[17]]).unwrap();
+    assert!(proof
+        .verify(
+            mmr.get_root().unwrap(),
+            vec![(positions[17], NumberHash::from(17))]
+        )
+        .unwrap());
+
+    let import = |mmr_size, nodes: Vec<(u64, NumberHash)>| {
+        let store = MemStore::default();
+        MemMMR::<_, MergeNumberHash>::import_nodes(mmr_size, nodes.into_iter().map(Ok), &store)
+            .map(|mmr| mmr.mmr_size())
+    };
+    assert_eq!(import(0, Vec::new()), Ok(0));
+    assert_eq!(import(4, nodes[..4].to_vec()), Ok(4));
+    assert_eq!(import(5, nodes[..5].to_vec()), Err(Error::InvalidMMRSize));
+    assert_eq!(import(4, nodes[..3].to_vec()), Err(Error::InvalidMMRSize));
+    assert_eq!(import(3, nodes[..4].to_vec()), Err(Error::InvalidMMRSize));
+    assert_eq!(import(3, nodes[1..4].to_vec()), Err(Error::InvalidMMRSize));
+    let failing = vec![Ok(nodes[0].clone()), Err(Error::InconsistentStore)];
+    assert_eq!(
+        MemMMR::<_, MergeNumberHash>::import_nodes(3, failing, &MemStore::default()).map(|_| ()),
+        Err(Error::InconsistentStore)
+    );
+}
+
+#[test]
+fn test_try_get_root() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    assert_eq!(mmr.try_get_root(), Ok(None));
+    for i in 0u32..5 {
+        mmr.push(NumberHash::from(i)).unwrap();
+        assert_eq!(mmr.try_get_root(), mmr.get_root().map(Some));
+        assert!(mmr.try_get_root().unwrap().is_some());
+    }
+    mmr.rollback(0).unwrap();
+    assert_eq!(mmr.try_get_root(), Ok(None));
+}
+
+#[test]
+fn test_root_cache() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    mmr.commit().unwrap();
+
+    let mut mmr = MMR::<_, MergeNumberHash, _>::new(
+        mmr.mmr_size(),
+        CountingStore {
+            store: &store,
+            get_elem_calls: Default::default(),
+            get_elems_calls: Default::default(),
+        },
+    );
+    let reads = |mmr: &MMR<_, _, CountingStore>| {
+        mmr.store().get_elem_calls.get() + mmr.store().get_elems_calls.get()
+    };
+    assert_eq!(mmr.get_root(), Ok(root.clone()));
+    assert_eq!(mmr.get_root(), Ok(root.clone()));
+    assert_eq!(reads(&mmr), 1);
+
+    // pushing changes the mmr_size, the root is calculated again
+    mmr.push(NumberHash::from(11)).unwrap();
+    let pushed_root = mmr.get_root().unwrap();
+    assert_ne!(pushed_root, root);
+    let after_push = reads(&mmr);
+    assert_eq!(mmr.get_root(), Ok(pushed_root.clone()));
+    assert_eq!(reads(&mmr), after_push);
+
+    // pushing another leaf after rollback reaches the cached mmr_size again
+    let mmr_size = mmr.mmr_size();
+    mmr.rollback(leaf_index_to_mmr_size(10)).unwrap();
+    mmr.push(NumberHash::from(12)).unwrap();
+    assert_eq!(mmr.mmr_size(), mmr_size);
+    let other_root = mmr.get_root().unwrap();
+    assert_ne!(other_root, pushed_root);
+
+    let expected = {
+        let store = MemStore::default();
+        let mut leaves: Vec<_> = (0u32..11).map(NumberHash::from).collect();
+        leaves[5] = NumberHash::from(42);
+        leaves.push(NumberHash::from(12));
+        let (mmr, _) = MemMMR::<_, MergeNumberHash>::build(&store, leaves).unwrap();
+        mmr.get_root().unwrap()
+    };
+    mmr.update(positions[5], NumberHash::from(42)).unwrap();
+    assert_eq!(mmr.get_root(), Ok(expected));
+}
+
+// bags the peaks by hashing `merge_peaks(left, right)`
+struct LeftFirstBag;
+
+impl crate::Merge for LeftFirstBag {
+    type Item = NumberHash;
+
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> crate::Result<Self::Item> {
+        <MergeNumberHash as crate::Merge>::merge(lhs, rhs)
+    }
+
+    fn bag(accumulated: &Self::Item, next: &Self::Item) -> crate::Result<Self::Item> {
+        Self::merge_peaks(next, accumulated)
+    }
+}
+
+#[test]
+fn test_custom_bag() {
+    use crate::{no_alloc::verify_no_alloc, ProofFormat};
+
+    let leaves: Vec<_> = (0u32..11).map(NumberHash::from).collect();
+    let store = MemStore::default();
+    let (default_mmr, _) = MemMMR::<_, MergeNumberHash>::build(&store, leaves.clone()).unwrap();
+    let store = MemStore::default();
+    let (mmr, positions) = MemMMR::<_, LeftFirstBag>::build(&store, leaves.clone()).unwrap();
+    let root = mmr.get_root().unwrap();
+    assert_ne!(root, default_mmr.get_root().unwrap());
+    // hashing the left peak first across a right to left fold is the grin convention
+    assert_eq!(
+        Ok(root.clone()),
+        default_mmr.get_root_with_format(ProofFormat::Grin)
+    );
+
+    let pos_list = vec![positions[0], positions[5], positions[10]];
+    let proof_leaves: Vec<_> = pos_list
+        .iter()
+        .map(|pos| (*pos, mmr.batch().get_elem(*pos).unwrap().unwrap()))
+        .collect();
+    let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+    assert_eq!(proof.verify(root.clone(), proof_leaves.clone()), Ok(true));
+    // the same items don't verify with the default order
+    let default_proof =
+        MerkleProof::<_, MergeNumberHash>::new(proof.mmr_size(), proof.proof_items().to_vec());
+    assert_eq!(default_proof.verify(root.clone(), proof_leaves), Ok(false));
+
+    for (i, pos) in positions.iter().enumerate() {
+        let proof = mmr.gen_proof(vec![*pos]).unwrap();
+        assert_eq!(
+            verify_no_alloc::<_, LeftFirstBag>(
+                proof.proof_items(),
+                &root,
+                mmr.mmr_size(),
+                *pos,
+                leaves[i].clone(),
+            ),
+            Ok(true)
+        );
+    }
+}
+
+#[test]
+fn test_gen_proof_out_of_range() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..12).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+
+    // the store has nodes beyond the mmr_size
+    let mmr_size = leaf_index_to_mmr_size(10);
+    let mmr = MMR::<_, MergeNumberHash, _>::new(
+        mmr_size,
+        CountingStore {
+            store: &store,
+            get_elem_calls: Default::default(),
+            get_elems_calls: Default::default(),
+        },
+    );
+    for pos_list in [
+        vec![mmr_size],
+        vec![positions[0], positions[11]],
+        vec![positions[11], positions[0]],
+        vec![u64::MAX],
+    ] {
+        assert_eq!(
+            mmr.gen_proof(pos_list.clone()).map(|_| ()),
+            Err(Error::GenProofForInvalidLeaves),
+            "positions {:?}",
+            pos_list
+        );
+        assert_eq!(
+            mmr.gen_proof_with_positions(pos_list).map(|_| ()),
+            Err(Error::GenProofForInvalidLeaves)
+        );
+    }
+    assert_eq!(mmr.store().get_elem_calls.get(), 0);
+    assert_eq!(mmr.store().get_elems_calls.get(), 0);
+
+    // 6 isn't a valid mmr size, 5 is below it but not under the peaks 2 and 3
+    let mmr = MMR::<_, MergeNumberHash, _>::new(6, &store);
+    assert_eq!(
+        mmr.gen_proof(vec![5]).map(|_| ()),
+        Err(Error::GenProofForInvalidLeaves)
+    );
+    assert_eq!(
+        mmr.gen_proof(vec![4]).map(|_| ()),
+        Err(Error::GenProofForInvalidLeaves)
+    );
+    assert!(mmr.gen_proof(vec![3]).is_ok());
+
+    let empty_store = MemStore::default();
+    let mmr = MemMMR::<NumberHash, MergeNumberHash>::new(0, &empty_store);
+    assert_eq!(
+        mmr.gen_proof(vec![0]).map(|_| ()),
+        Err(Error::GenProofForInvalidLeaves)
+    );
+}
+
+#[test]
+fn test_verify_streaming() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..100).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let indexes = [0usize, 3, 31, 32, 64, 98, 99];
+    let pos_list: Vec<u64> = indexes.iter().map(|i| positions[*i]).collect();
+    let proof = mmr.gen_proof(pos_list).unwrap();
+    let leaves = || {
+        indexes
+            .iter()
+            .map(|i| Ok((positions[*i], NumberHash::from(*i as u32))))
+    };
+    assert_eq!(proof.verify_streaming(root.clone(), leaves()), Ok(true));
+    assert_eq!(
+        proof.verify_streaming(
+            mmr.get_root_with_format(crate::ProofFormat::Grin).unwrap(),
+            leaves()
+        ),
+        Ok(false)
+    );
+
+    // stops at the first leaf out of order
+    let taken = std::cell::Cell::new(0);
+    let unsorted = [0usize, 31, 3, 32].iter().map(|i| {
+        taken.set(taken.get() + 1);
+        Ok((positions[*i], NumberHash::from(*i as u32)))
+    });
+    assert_eq!(
+        proof.verify_streaming(root.clone(), unsorted),
+        Err(Error::UnsortedLeaves(positions[3]))
+    );
+    assert_eq!(taken.get(), 3);
+    let duplicated = leaves().take(2).chain(leaves().skip(1));
+    assert_eq!(
+        proof.verify_streaming(root.clone(), duplicated),
+        Err(Error::UnsortedLeaves(positions[3]))
+    );
+    // the errors of the leaves are returned
+    let failing = leaves()
+        .take(2)
+        .chain(std::iter::once(Err(Error::StoreError("broken".into()))));
+    assert_eq!(
+        proof.verify_streaming(root.clone(), failing),
+        Err(Error::StoreError("broken".into()))
+    );
+    assert_eq!(
+        proof.verify_streaming(
+            root,
+            leaves().chain(std::iter::once(Ok((mmr.mmr_size(), NumberHash::from(0)))))
+        ),
+        Err(Error::PositionOutOfRange)
+    );
+}
+
+#[test]
+fn test_calculate_peaks() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..8).map(NumberHash::from)).unwrap();
+    let old_peaks = mmr.get_peak_values().unwrap();
+    for i in 8u32..14 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    // 14 leaves have the peaks 14, 21 and 24
+    let pos = leaf_index_to_pos(9);
+    let proof = mmr.gen_proof(vec![pos]).unwrap();
+    let leaves = vec![(pos, NumberHash::from(9))];
+    let peaks = proof.calculate_peaks(leaves.clone()).unwrap();
+    assert_eq!(peaks.len(), 3);
+    assert_eq!(peaks[0], old_peaks[0].1);
+    assert_eq!(
+        peaks[..2],
+        mmr.get_peak_values()
+            .unwrap()
+            .into_iter()
+            .map(|(_, peak)| peak)
+            .collect::<Vec<_>>()[..2]
+    );
+    // bagging from right to left
+    let merge_peaks = <MergeNumberHash as crate::Merge>::merge_peaks;
+    let bagged = merge_peaks(&merge_peaks(&peaks[2], &peaks[1]).unwrap(), &peaks[0]).unwrap();
+    assert_eq!(proof.calculate_root(leaves.clone()), Ok(bagged));
+    assert_eq!(proof.calculate_root(leaves), mmr.get_root());
+
+    // the rhs peaks are bagged into one item
+    let proof = mmr.gen_proof(vec![positions[3]]).unwrap();
+    let peaks = proof
+        .calculate_peaks(vec![(positions[3], NumberHash::from(3))])
+        .unwrap();
+    assert_eq!(peaks.len(), 2);
+    assert_eq!(peaks[0], old_peaks[0].1);
+    assert_eq!(
+        proof
+            .calculate_peaks(vec![(positions[3], NumberHash::from(4))])
+            .map(|peaks| peaks[0] == old_peaks[0].1),
+        Ok(false)
+    );
+}
+
+#[test]
+fn test_extend_proof() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..10).map(NumberHash::from)).unwrap();
+    let pos_list = vec![positions[2], positions[9]];
+    let old_mmr_size = mmr.mmr_size();
+    let old_proof = mmr.gen_proof(pos_list.clone()).unwrap();
+    // the siblings of the old proof are no longer in the store
+    mmr.prune_below(8).unwrap();
+    for i in 10u32..20 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    assert_eq!(
+        mmr.gen_proof(pos_list.clone()).map(|_| ()),
+        Err(Error::InconsistentStore)
+    );
+
+    let extended = mmr
+        .extend_proof(&old_proof, pos_list.clone(), old_mmr_size)
+        .unwrap();
+    let reference_store = MemStore::default();
+    let (reference, _) =
+        MemMMR::<_, MergeNumberHash>::build(&reference_store, (0u32..20).map(NumberHash::from))
+            .unwrap();
+    let expected = reference.gen_proof(pos_list.clone()).unwrap();
+    assert_eq!(extended.mmr_size(), expected.mmr_size());
+    assert_eq!(extended.proof_items(), expected.proof_items());
+    assert!(extended
+        .verify(
+            mmr.get_root().unwrap(),
+            vec![
+                (positions[2], NumberHash::from(2)),
+                (positions[9], NumberHash::from(9))
+            ]
+        )
+        .unwrap());
+
+    assert_eq!(
+        mmr.extend_proof(&old_proof, pos_list.clone(), mmr.mmr_size() + 1)
+            .map(|_| ()),
+        Err(Error::InvalidMMRSize)
+    );
+    assert_eq!(
+        mmr.extend_proof(&old_proof, vec![positions[2], positions[3]], old_mmr_size)
+            .map(|_| ()),
+        Err(Error::CorruptedProof)
+    );
+    assert_eq!(
+        mmr.extend_proof(&old_proof, pos_list, leaf_index_to_mmr_size(10))
+            .map(|_| ()),
+        Err(Error::CorruptedProof)
+    );
+}
+
+proptest! {
+    #[test]
+    fn test_extend_proof_randomly(count in 1u32..100, steps in prop::collection::vec(1u32..50, 1..10), seed in prop::collection::vec(any::<u32>(), 1..10)) {
+        let store = MemStore::default();
+        let (mut mmr, positions) =
+            MemMMR::<_, MergeNumberHash>::build(&store, (0..count).map(NumberHash::from)).unwrap();
+        let pos_list: Vec<u64> = seed.iter().map(|i| positions[*i as usize % positions.len()]).collect();
+        let mut proof = mmr.gen_proof(pos_list.clone()).unwrap();
+        let mut next_leaf = count;
+        for step in steps {
+            let old_mmr_size = mmr.mmr_size();
+            for _ in 0..step {
+                mmr.push(NumberHash::from(next_leaf)).unwrap();
+                next_leaf += 1;
+            }
+            proof = mmr.extend_proof(&proof, pos_list.clone(), old_mmr_size).unwrap();
+            let expected = mmr.gen_proof(pos_list.clone()).unwrap();
+            prop_assert_eq!(proof.mmr_size(), expected.mmr_size());
+            prop_assert_eq!(proof.proof_items(), expected.proof_items());
+        }
+    }
+}
+
+#[test]
+fn test_failed_push_is_unchanged() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..3).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    // the 4th leaf merges with the 3rd one
+    let leaf = (&store).get_elem(positions[2]).unwrap().unwrap();
+    (&store).delete(positions[2]).unwrap();
+
+    assert_eq!(mmr.push(NumberHash::from(3)), Err(Error::InconsistentStore));
+    assert_eq!(
+        mmr.push_batch(vec![NumberHash::from(3), NumberHash::from(4)]),
+        Err(Error::InconsistentStore)
+    );
+    assert_eq!(
+        mmr.push_info(NumberHash::from(3)).map(|_| ()),
+        Err(Error::InconsistentStore)
+    );
+    assert_eq!(mmr.mmr_size(), 4);
+    assert_eq!(mmr.batch().first_staged_pos(), None);
+    assert_eq!((&store).get_elem(4), Ok(None));
+
+    // retry once the store is repaired
+    (&store).update(positions[2], leaf).unwrap();
+    mmr.push(NumberHash::from(3)).unwrap();
+    let reference_store = MemStore::default();
+    let (reference, _) =
+        MemMMR::<_, MergeNumberHash>::build(&reference_store, (0u32..4).map(NumberHash::from))
+            .unwrap();
+    assert_eq!(mmr.get_root(), reference.get_root());
+}
+
+#[test]
+fn test_commit_count() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    for i in 0u32..11 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    assert_eq!(mmr.commit(), Ok(19));
+    // nothing is staged
+    assert_eq!(mmr.commit(), Ok(0));
+
+    // the 12th leaf merges twice
+    mmr.push(NumberHash::from(11)).unwrap();
+    assert_eq!(mmr.commit(), Ok(3));
+    let old_mmr_size = mmr.mmr_size();
+    mmr.push_batch((12u32..20).map(NumberHash::from).collect())
+        .unwrap();
+    assert_eq!(mmr.commit(), Ok(mmr.mmr_size() - old_mmr_size));
+}
+
+#[test]
+fn test_speculative_root() {
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    // the staged leaves are included
+    mmr.push(NumberHash::from(11)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let mmr_size = mmr.mmr_size();
+
+    let extra = [NumberHash::from(12), NumberHash::from(13)];
+    let speculative = mmr.speculative_root(&extra).unwrap();
+    assert_eq!(mmr.mmr_size(), mmr_size);
+    assert_eq!(mmr.get_root(), Ok(root.clone()));
+    assert_eq!((&store).len(), Ok(19));
+
+    let copy = store.clone();
+    let mut pushed = MemMMR::<_, MergeNumberHash>::new(19, &copy);
+    for leaf in (11u32..14).map(NumberHash::from) {
+        pushed.push(leaf).unwrap();
+    }
+    assert_eq!(pushed.get_root(), Ok(speculative));
+    assert_eq!(mmr.speculative_root(&[]), Ok(root));
+
+    for count in 1u32..40 {
+        let leaves: Vec<_> = (100..100 + count).map(NumberHash::from).collect();
+        let speculative = mmr.speculative_root(&leaves).unwrap();
+        let copy = store.clone();
+        let mut pushed = MemMMR::<_, MergeNumberHash>::new(19, &copy);
+        pushed.push(NumberHash::from(11)).unwrap();
+        pushed.push_batch(leaves).unwrap();
+        assert_eq!(pushed.get_root(), Ok(speculative), "count {}", count);
+    }
+
+    let empty_store = MemStore::default();
+    let empty = MemMMR::<NumberHash, MergeNumberHash>::new(0, &empty_store);
+    assert_eq!(empty.speculative_root(&[]), Err(Error::GetRootOnEmpty));
+    assert_eq!(
+        empty.speculative_root(&[NumberHash::from(0)]),
+        Ok(NumberHash::from(0))
+    );
+    let capped = MemMMR::<NumberHash, MergeNumberHash>::with_capacity(0, &empty_store, 1);
+    assert_eq!(
+        capped.speculative_root(&extra),
+        Err(Error::CapacityExceeded)
+    );
+}
+
+#[test]
+fn test_verify_conflicting_leaf_values() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[5]]).unwrap();
+
+    // a repeated entry with the same value is still accepted
+    let leaf = (positions[5], NumberHash::from(5));
+    assert_eq!(
+        proof.verify(root.clone(), vec![leaf.clone(), leaf.clone()]),
+        Ok(true)
+    );
+    // either order of the conflicting values fails
+    let forged = (positions[5], NumberHash::from(50));
+    assert_eq!(
+        proof.verify(root.clone(), vec![leaf.clone(), forged.clone()]),
+        Err(Error::ConflictingLeafValues(positions[5]))
+    );
+    assert_eq!(
+        proof.verify(root, vec![forged, leaf]),
+        Err(Error::ConflictingLeafValues(positions[5]))
+    );
+}
+
+#[test]
+fn test_gen_proof_from_leaves() {
+    let leaf_count = 23u32;
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0..leaf_count).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof_sets = vec![
+        vec![positions[0]],
+        vec![positions[5], positions[9]],
+        vec![positions[15], positions[16], positions[22]],
+        vec![positions[3], positions[4], positions[18]],
+    ];
+    let expected: Vec<_> = proof_sets
+        .iter()
+        .map(|pos_list| mmr.gen_proof(pos_list.clone()).unwrap())
+        .collect();
+
+    // keep only the peaks in the store
+    mmr.prune_below(leaf_count as u64).unwrap();
+    let stored: Vec<u64> = (0..mmr.mmr_size())
+        .filter(|pos| (&store).get_elem(*pos).unwrap().is_some())
+        .collect();
+    assert_eq!(stored, get_peaks(mmr.mmr_size()));
+    let leaf_provider = |pos: u64| {
+        Ok(crate::helper::pos_to_leaf_index(pos)
+            .filter(|index| *index < leaf_count as u64)
+            .map(|index| NumberHash::from(index as u32)))
+    };
+    for (pos_list, expected) in proof_sets.into_iter().zip(expected) {
+        let proof = mmr
+            .gen_proof_from_leaves(pos_list.clone(), leaf_provider)
+            .unwrap();
+        assert_eq!(proof.proof_items(), expected.proof_items());
+        assert_eq!(proof.mmr_size(), expected.mmr_size());
+        let leaves = pos_list
+            .into_iter()
+            .map(|pos| (pos, leaf_provider(pos).unwrap().unwrap()))
+            .collect();
+        assert_eq!(proof.verify(root.clone(), leaves), Ok(true));
+    }
+
+    // a missing leaf
+    assert_eq!(
+        mmr.gen_proof_from_leaves(vec![positions[0]], |_| Ok(None))
+            .map(|_| ()),
+        Err(Error::InconsistentStore)
+    );
+}
+
+#[test]
+fn test_pop() {
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..20).map(NumberHash::from)).unwrap();
+    // committed and staged leaves mixed
+    mmr.commit().unwrap();
+    for i in 20u32..25 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    for i in (0u32..25).rev() {
+        assert_eq!(mmr.pop(), Ok(Some(NumberHash::from(i))));
+        assert_eq!(mmr.leaf_count(), i as u64);
+        if i == 0 {
+            assert_eq!(mmr.mmr_size(), 0);
+            break;
+        }
+        assert_eq!(mmr.mmr_size(), leaf_index_to_mmr_size(i as u64 - 1));
+        let expected_store = MemStore::default();
+        let (expected, _) =
+            MemMMR::<_, MergeNumberHash>::build(&expected_store, (0u32..i).map(NumberHash::from))
+                .unwrap();
+        assert_eq!(mmr.get_root(), expected.get_root(), "leaves {}", i);
+        if i % 5 == 0 {
+            mmr.commit().unwrap();
+        }
+    }
+    assert_eq!(mmr.pop(), Ok(None));
+    mmr.commit().unwrap();
+    assert_eq!((&store).get_elem(0).unwrap(), None);
+
+    // pops after pushes
+    mmr.push(NumberHash::from(7)).unwrap();
+    mmr.push(NumberHash::from(8)).unwrap();
+    assert_eq!(mmr.pop(), Ok(Some(NumberHash::from(8))));
+    mmr.push(NumberHash::from(9)).unwrap();
+    let expected_store = MemStore::default();
+    let (expected, _) = MemMMR::<_, MergeNumberHash>::build(
+        &expected_store,
+        vec![NumberHash::from(7), NumberHash::from(9)],
+    )
+    .unwrap();
+    assert_eq!(mmr.get_root(), expected.get_root());
+}
+
+#[test]
+fn test_internal_node_proof() {
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let node = |pos: u64| (pos, mmr.batch().get_elem(pos).unwrap().unwrap());
+
+    // 6 is at height 2, only the siblings above it are proof items
+    let proof = mmr.gen_proof(vec![6]).unwrap();
+    assert_eq!(proof.proof_items().len(), 2);
+    assert!(proof.verify(root.clone(), vec![node(6)]).unwrap());
+    assert!(!proof.verify(root.clone(), vec![(6, node(5).1)]).unwrap());
+
+    // 2 is at height 1
+    let proof = mmr.gen_proof(vec![2]).unwrap();
+    assert_eq!(proof.proof_items().len(), 3);
+    assert!(proof.verify(root.clone(), vec![node(2)]).unwrap());
+
+    // mixed with leaves of other subtrees
+    let proof = mmr.gen_proof(vec![2, 5, 13, 18]).unwrap();
+    assert!(proof
+        .verify(root.clone(), vec![node(2), node(5), node(13), node(18)])
+        .unwrap());
+    // the same items as proving every leaf under the nodes
+    let leaves_proof = mmr.gen_proof(vec![0, 1, 3, 4, 7, 8, 10, 11, 18]).unwrap();
+    assert_eq!(proof.proof_items(), leaves_proof.proof_items());
+
+    // a node can't be proven along with its descendants
+    assert_eq!(
+        mmr.gen_proof(vec![2, 6]).map(|_| ()),
+        Err(Error::NodeProofsNotSupported)
+    );
+    assert_eq!(
+        mmr.gen_proof(vec![0, 14]).map(|_| ()),
+        Err(Error::NodeProofsNotSupported)
+    );
+    let proof = mmr.gen_proof(vec![6]).unwrap();
+    assert_eq!(
+        proof.verify(root, vec![node(6), node(5)]),
+        Err(Error::CorruptedProof)
+    );
+}
+
+#[test]
+fn test_range_proof() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..1000).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+
+    // an unaligned and an aligned range of 64 leaves
+    for start in [100u32, 128] {
+        let end = start + 63;
+        let leaves: Vec<NumberHash> = (start..=end).map(NumberHash::from).collect();
+        let proof = mmr.gen_range_proof(start as u64, end as u64).unwrap();
+        let naive = mmr
+            .gen_proof(positions[start as usize..=end as usize].to_vec())
+            .unwrap();
+        assert_eq!(proof.proof_items().len(), naive.proof_items().len());
+        assert!(proof
+            .verify_range(root.clone(), start as u64, end as u64, leaves.clone())
+            .unwrap());
+        assert!(naive
+            .verify_range(root.clone(), start as u64, end as u64, leaves.clone())
+            .unwrap());
+
+        let mut tampered = leaves.clone();
+        tampered[10] = NumberHash::from(1000);
+        assert!(!proof
+            .verify_range(root.clone(), start as u64, end as u64, tampered)
+            .unwrap());
+        // a shifted or shortened range
+        assert!(!proof
+            .verify_range(
+                root.clone(),
+                start as u64 + 1,
+                end as u64 + 1,
+                leaves.clone()
+            )
+            .unwrap_or(false));
+        assert!(!proof
+            .verify_range(root.clone(), start as u64, end as u64, leaves[1..].to_vec())
+            .unwrap());
+    }
+
+    assert!(mmr
+        .gen_range_proof(0, 999)
+        .unwrap()
+        .proof_items()
+        .is_empty());
+    assert_eq!(
+        mmr.gen_range_proof(5, 4).map(|_| ()),
+        Err(Error::GenProofForInvalidLeaves)
+    );
+    assert_eq!(
+        mmr.gen_range_proof(990, 1000).map(|_| ()),
+        Err(Error::GenProofForInvalidLeaves)
+    );
+}
+
+#[test]
+fn test_merge_proofs() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let cases: Vec<(Vec<usize>, Vec<usize>)> = vec![
+        (vec![0], vec![5, 10]),
+        (vec![0], vec![4]),
+        (vec![0], vec![1]),
+        (vec![2, 3], vec![3, 9]),
+        (vec![10], vec![8]),
+        (vec![7], vec![7]),
+    ];
+    for (own, other) in cases {
+        let own: Vec<u64> = own.into_iter().map(|i| positions[i]).collect();
+        let other: Vec<u64> = other.into_iter().map(|i| positions[i]).collect();
+        let mut union = own.clone();
+        union.extend(other.iter().copied());
+        let expected = mmr.gen_proof(union.clone()).unwrap();
+
+        let merged = mmr
+            .gen_proof(own.clone())
+            .unwrap()
+            .merge_with(mmr.gen_proof(other.clone()).unwrap(), own, other)
+            .unwrap();
+        assert_eq!(merged.proof_items(), expected.proof_items());
+        let leaves = union
+            .into_iter()
+            .map(|pos| (pos, mmr.batch().get_elem(pos).unwrap().unwrap()))
+            .collect();
+        assert!(merged.verify(root.clone(), leaves).unwrap());
+    }
+
+    // proofs of different mmr sizes
+    let smaller_store = MemStore::default();
+    let (smaller, _) =
+        MemMMR::<_, MergeNumberHash>::build(&smaller_store, (0u32..10).map(NumberHash::from))
+            .unwrap();
+    let proof = || mmr.gen_proof(vec![positions[0]]).unwrap();
+    assert_eq!(
+        proof()
+            .merge_with(
+                smaller.gen_proof(vec![positions[1]]).unwrap(),
+                vec![positions[0]],
+                vec![positions[1]]
+            )
+            .map(|_| ()),
+        Err(Error::CorruptedProof)
+    );
+    // positions which the proof isn't generated for
+    assert_eq!(
+        proof()
+            .merge_with(
+                proof(),
+                vec![positions[0]],
+                vec![positions[0], positions[1]]
+            )
+            .map(|_| ()),
+        Err(Error::CorruptedProof)
+    );
+}
+
+#[test]
+fn test_get_peak_values() {
+    use crate::Merge;
+
+    for count in [1u32, 2, 3, 7, 8, 11, 25, 64] {
+        let store = MemStore::default();
+        let (mmr, _) =
+            MemMMR::<_, MergeNumberHash>::build(&store, (0u32..count).map(NumberHash::from))
+                .unwrap();
+        let peaks = mmr.get_peak_values().unwrap();
+        assert_eq!(
+            peaks.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(),
+            get_peaks(mmr.mmr_size())
+        );
+        // bag from right to left
+        let mut values = peaks.into_iter().rev().map(|(_, peak)| peak);
+        let right_peak = values.next().unwrap();
+        let root = values.fold(right_peak, |root, left_peak| {
+            MergeNumberHash::merge_peaks(&root, &left_peak).unwrap()
+        });
+        assert_eq!(root, mmr.get_root().unwrap());
+    }
+    let store = MemStore::<NumberHash>::default();
+    let mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    assert_eq!(mmr.get_peak_values(), Ok(Vec::new()));
+}
+
+#[test]
+fn test_from_peaks() {
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..25).map(NumberHash::from)).unwrap();
+    let peaks = mmr.get_peak_values().unwrap();
+
+    let resumed_store = MemStore::default();
+    let mut resumed =
+        MemMMR::<_, MergeNumberHash>::from_peaks(mmr.mmr_size(), peaks.clone(), &resumed_store)
+            .unwrap();
+    assert_eq!(resumed.get_root(), mmr.get_root());
+    for i in 25u32..30 {
+        resumed.push(NumberHash::from(i)).unwrap();
+    }
+    let expected_store = MemStore::default();
+    let (expected, _) =
+        MemMMR::<_, MergeNumberHash>::build(&expected_store, (0u32..30).map(NumberHash::from))
+            .unwrap();
+    assert_eq!(resumed.mmr_size(), expected.mmr_size());
+    assert_eq!(resumed.get_root(), expected.get_root());
+
+    // the positions must be exactly the peaks
+    let from_peaks = |mmr_size, peaks| {
+        MemMMR::<_, MergeNumberHash>::from_peaks(mmr_size, peaks, &resumed_store).map(|_| ())
+    };
+    assert_eq!(
+        from_peaks(mmr.mmr_size() + 1, peaks.clone()),
+        Err(Error::InvalidMMRSize)
+    );
+    assert_eq!(
+        from_peaks(mmr.mmr_size(), peaks[1..].to_vec()),
+        Err(Error::InvalidMMRSize)
+    );
+    let mut shuffled = peaks;
+    shuffled.swap(0, 1);
+    assert_eq!(
+        from_peaks(mmr.mmr_size(), shuffled),
+        Err(Error::InvalidMMRSize)
+    );
+    assert_eq!(from_peaks(0, Vec::new()), Ok(()));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_proof_serde() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[2], positions[7]]).unwrap();
+
+    let bytes = bincode::serialize(&proof).unwrap();
+    let decoded: MerkleProof<NumberHash, MergeNumberHash> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(decoded.mmr_size(), proof.mmr_size());
+    assert_eq!(decoded.proof_items(), proof.proof_items());
+    assert!(decoded
+        .verify(
+            root,
+            vec![
+                (positions[2], NumberHash::from(2)),
+                (positions[7], NumberHash::from(7))
+            ]
+        )
+        .unwrap());
+
+    // positions of a proof built from items are kept
+    let items = proof
+        .annotated_items(&[positions[2], positions[7]])
+        .unwrap()
+        .into_iter()
+        .map(|info| (info.pos, info.value.clone()));
+    let proof = MerkleProof::<_, MergeNumberHash>::from_items(mmr.mmr_size(), items).unwrap();
+    let bytes = bincode::serialize(&proof).unwrap();
+    let decoded: MerkleProof<NumberHash, MergeNumberHash> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(decoded.item_positions(), proof.item_positions());
+}
+
+#[test]
+fn test_consistency_proof() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    // (mmr_size, root) after each push
+    let mut history = Vec::new();
+    for i in 0u32..40 {
+        mmr.push(NumberHash::from(i)).unwrap();
+        history.push((mmr.mmr_size(), mmr.get_root().unwrap()));
+    }
+    mmr.commit().unwrap();
+    for (new_index, (new_mmr_size, new_root)) in history.iter().enumerate() {
+        let new_mmr = MemMMR::<_, MergeNumberHash>::new(*new_mmr_size, &store);
+        for (old_mmr_size, old_root) in &history[..=new_index] {
+            let proof = new_mmr.gen_consistency_proof(*old_mmr_size).unwrap();
+            assert_eq!(proof.old_mmr_size(), *old_mmr_size);
+            assert_eq!(proof.new_mmr_size(), *new_mmr_size);
+            assert!(proof.verify(old_root.clone(), new_root.clone()).unwrap());
+        }
+    }
+
+    // old size 7 has a single peak, merged twice more up to size 15
+    let proof = MemMMR::<_, MergeNumberHash>::new(15, &store)
+        .gen_consistency_proof(7)
+        .unwrap();
+    assert_eq!(proof.old_peaks().len(), 1);
+    assert_eq!(proof.proof().proof_items().len(), 1);
+    let root_of = |mmr_size| {
+        history
+            .iter()
+            .find(|(size, _)| *size == mmr_size)
+            .unwrap()
+            .1
+            .clone()
+    };
+    assert!(proof.verify(root_of(7), root_of(15)).unwrap());
+    assert!(!proof.verify(root_of(8), root_of(15)).unwrap());
+    assert!(!proof.verify(root_of(7), root_of(16)).unwrap());
+
+    // an extension of another history
+    let fork_store = MemStore::default();
+    let (fork, _) =
+        MemMMR::<_, MergeNumberHash>::build(&fork_store, (1u32..9).map(NumberHash::from)).unwrap();
+    let proof = fork.gen_consistency_proof(7).unwrap();
+    assert!(!proof.verify(root_of(7), fork.get_root().unwrap()).unwrap());
+
+    let mmr = MemMMR::<_, MergeNumberHash>::new(15, &store);
+    for old_mmr_size in [0, 9, 16] {
+        assert_eq!(
+            mmr.gen_consistency_proof(old_mmr_size).map(|_| ()),
+            Err(Error::InvalidMMRSize)
+        );
+    }
+}
+
+// fails to merge a sentinel item, as a backend which may time out would
+struct FallibleMerge;
+
+impl FallibleMerge {
+    fn sentinel() -> NumberHash {
+        NumberHash::from(u32::MAX)
+    }
+}
+
+impl crate::Merge for FallibleMerge {
+    type Item = NumberHash;
+
+    fn merge(left: &Self::Item, right: &Self::Item) -> crate::Result<Self::Item> {
+        if *left == Self::sentinel() || *right == Self::sentinel() {
+            return Err(Error::MergeError("sentinel".into()));
+        }
+        MergeNumberHash::merge(left, right)
+    }
+}
+
+#[test]
+fn test_merge_error_propagation() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, FallibleMerge>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    // the 12th leaf merges with the 11th
+    assert_eq!(
+        mmr.push(FallibleMerge::sentinel()),
+        Err(Error::MergeError("sentinel".into()))
+    );
+    // nothing is staged by the failed push
+    assert_eq!(mmr.mmr_size(), 19);
+    assert_eq!(mmr.get_root(), Ok(root.clone()));
+
+    let proof = mmr.gen_proof(vec![positions[3]]).unwrap();
+    assert!(proof
+        .verify(root.clone(), vec![(positions[3], NumberHash::from(3))])
+        .unwrap());
+    assert_eq!(
+        proof.verify(root, vec![(positions[3], FallibleMerge::sentinel())]),
+        Err(Error::MergeError("sentinel".into()))
+    );
+}
+
+// counts single reads and multi-get reads of the inner store
+struct CountingStore<'a> {
+    store: &'a MemStore<NumberHash>,
+    get_elem_calls: std::cell::Cell<usize>,
+    get_elems_calls: std::cell::Cell<usize>,
+}
+
+impl MMRStoreReadOps<NumberHash> for CountingStore<'_> {
+    fn get_elem(&self, pos: u64) -> crate::Result<Option<NumberHash>> {
+        self.get_elem_calls.set(self.get_elem_calls.get() + 1);
+        self.store.get_elem(pos)
+    }
+
+    fn get_elems(&self, positions: &[u64]) -> crate::Result<Vec<Option<NumberHash>>> {
+        self.get_elems_calls.set(self.get_elems_calls.get() + 1);
+        positions
+            .iter()
+            .map(|pos| self.store.get_elem(*pos))
+            .collect()
+    }
+}
+
+impl MMRStoreWriteOps<NumberHash> for CountingStore<'_> {
+    fn append(&mut self, pos: u64, elems: Vec<NumberHash>) -> crate::Result<()> {
+        let mut store = self.store;
+        store.append(pos, elems)
+    }
+
+    fn delete(&mut self, pos: u64) -> crate::Result<()> {
+        let mut store = self.store;
+        store.delete(pos)
+    }
+
+    fn update(&mut self, pos: u64, elem: NumberHash) -> crate::Result<()> {
+        let mut store = self.store;
+        store.update(pos, elem)
+    }
+}
+
+#[test]
+fn test_batched_store_reads() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let pos_list = vec![positions[0], positions[5]];
+    let expected = mmr.gen_proof(pos_list.clone()).unwrap();
+    mmr.commit().unwrap();
+
+    let mmr = MMR::<_, MergeNumberHash, _>::new(
+        mmr.mmr_size(),
+        CountingStore {
+            store: &store,
+            get_elem_calls: Default::default(),
+            get_elems_calls: Default::default(),
+        },
+    );
+    // 4 siblings in the first peak and the bagged rhs peaks
+    let proof = mmr.gen_proof(pos_list).unwrap();
+    assert_eq!(proof.proof_items(), expected.proof_items());
+    assert_eq!(proof.proof_items().len(), 5);
+    assert_eq!(mmr.store().get_elem_calls.get(), 0);
+    assert_eq!(mmr.store().get_elems_calls.get(), 1);
+    assert_eq!(mmr.get_root(), Ok(root));
+    assert_eq!(mmr.store().get_elem_calls.get(), 0);
+    assert_eq!(mmr.store().get_elems_calls.get(), 2);
+}
+
+#[test]
+fn test_resume() {
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..13).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+
+    let resumed = MemMMR::<_, MergeNumberHash>::resume(&store).unwrap();
+    assert_eq!(resumed.mmr_size(), mmr.mmr_size());
+    assert_eq!(resumed.get_root(), mmr.get_root());
+    // staged elements are not resumed
+    mmr.push(NumberHash::from(13)).unwrap();
+    let resumed = MemMMR::<_, MergeNumberHash>::resume(&store).unwrap();
+    assert_eq!(resumed.mmr_size(), 23);
+
+    let empty_store = MemStore::<NumberHash>::default();
+    let resumed = MemMMR::<_, MergeNumberHash>::resume(&empty_store).unwrap();
+    assert!(resumed.is_empty());
+
+    // the parent of the 14th leaf is missing
+    let partial = MemStore::default();
+    let elems = (0u64..24)
+        .map(|pos| mmr.batch().get_elem(pos).unwrap().unwrap())
+        .collect();
+    crate::MMRStoreWriteOps::append(&mut &partial, 0, elems).unwrap();
+    assert_eq!(
+        MemMMR::<_, MergeNumberHash>::resume(&partial).map(|_| ()),
+        Err(Error::InvalidMMRSize)
+    );
 }
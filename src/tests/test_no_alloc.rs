@@ -0,0 +1,166 @@
+use super::{MergeNumberHash, NumberHash};
+use crate::{
+    no_alloc::{verify_leaves_no_alloc, verify_no_alloc},
+    util::{MemMMR, MemStore},
+    Error,
+};
+
+#[test]
+fn test_verify_no_alloc() {
+    for count in [1u32, 2, 3, 7, 11, 32, 33] {
+        let store = MemStore::default();
+        let (mmr, positions) =
+            MemMMR::<_, MergeNumberHash>::build(&store, (0..count).map(NumberHash::from)).unwrap();
+        let root = mmr.get_root().unwrap();
+        for (i, pos) in positions.iter().enumerate() {
+            let proof = mmr.gen_proof(vec![*pos]).unwrap();
+            let verify = |leaf| {
+                verify_no_alloc::<_, MergeNumberHash>(
+                    proof.proof_items(),
+                    &root,
+                    mmr.mmr_size(),
+                    *pos,
+                    leaf,
+                )
+            };
+            assert_eq!(
+                verify(NumberHash::from(i as u32)),
+                Ok(true),
+                "count {} leaf {}",
+                count,
+                i
+            );
+            assert_eq!(verify(NumberHash::from(count)), Ok(false));
+        }
+    }
+}
+
+#[test]
+fn test_verify_no_alloc_corrupted() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let proof = mmr.gen_proof(vec![positions[5]]).unwrap();
+    let items = proof.proof_items();
+    let verify = |items: &[NumberHash], pos| {
+        verify_no_alloc::<_, MergeNumberHash>(
+            items,
+            &root,
+            mmr.mmr_size(),
+            pos,
+            NumberHash::from(5),
+        )
+    };
+    assert_eq!(verify(items, positions[5]), Ok(true));
+    assert_eq!(
+        verify(&items[..items.len() - 1], positions[5]),
+        Err(Error::ProofItemsExhausted)
+    );
+    let mut longer = items.to_vec();
+    longer.push(NumberHash::from(0));
+    assert_eq!(
+        verify(&longer, positions[5]),
+        Err(Error::ProofItemsLeftover)
+    );
+    assert_eq!(
+        verify(items, mmr.mmr_size()),
+        Err(Error::PositionOutOfRange)
+    );
+    assert_eq!(verify(items, 2), Err(Error::NodeProofsNotSupported));
+}
+
+#[test]
+fn test_verify_leaves_no_alloc() {
+    for count in [1u32, 2, 3, 7, 11, 32, 33] {
+        let store = MemStore::default();
+        let (mmr, positions) =
+            MemMMR::<_, MergeNumberHash>::build(&store, (0..count).map(NumberHash::from)).unwrap();
+        let root = mmr.get_root().unwrap();
+        let subsets: Vec<Vec<usize>> = vec![
+            (0..positions.len()).collect(),
+            (0..positions.len()).step_by(3).collect(),
+            vec![0, positions.len() - 1],
+            vec![positions.len() / 2],
+        ];
+        for mut indices in subsets {
+            indices.dedup();
+            let leaves: Vec<_> = indices
+                .iter()
+                .map(|i| (positions[*i], NumberHash::from(*i as u32)))
+                .collect();
+            let proof = mmr
+                .gen_proof(leaves.iter().map(|(pos, _)| *pos).collect())
+                .unwrap();
+            let verify = |leaves: &[(u64, NumberHash)]| {
+                verify_leaves_no_alloc::<40, _, MergeNumberHash>(
+                    proof.proof_items(),
+                    &root,
+                    mmr.mmr_size(),
+                    leaves,
+                )
+            };
+            assert_eq!(
+                verify(&leaves),
+                Ok(true),
+                "count {} leaves {:?}",
+                count,
+                indices
+            );
+            let mut wrong_leaves = leaves.clone();
+            wrong_leaves[0].1 = NumberHash::from(count);
+            assert_eq!(verify(&wrong_leaves), Ok(false));
+        }
+    }
+}
+
+#[test]
+fn test_verify_leaves_no_alloc_errors() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let leaves: Vec<_> = [1usize, 4, 9]
+        .iter()
+        .map(|i| (positions[*i], NumberHash::from(*i as u32)))
+        .collect();
+    let proof = mmr
+        .gen_proof(leaves.iter().map(|(pos, _)| *pos).collect())
+        .unwrap();
+    let items = proof.proof_items();
+    let mmr_size = mmr.mmr_size();
+
+    // 2 leaves under the first peak, and 3 peaks
+    assert_eq!(
+        verify_leaves_no_alloc::<3, _, MergeNumberHash>(items, &root, mmr_size, &leaves),
+        Ok(true)
+    );
+    assert_eq!(
+        verify_leaves_no_alloc::<2, _, MergeNumberHash>(items, &root, mmr_size, &leaves),
+        Err(Error::CorruptedStack)
+    );
+    let verify = |items: &[NumberHash], leaves: &[(u64, NumberHash)]| {
+        verify_leaves_no_alloc::<16, _, MergeNumberHash>(items, &root, mmr_size, leaves)
+    };
+    let unsorted = vec![leaves[1].clone(), leaves[0].clone()];
+    assert_eq!(
+        verify(items, &unsorted),
+        Err(Error::UnsortedLeaves(leaves[0].0))
+    );
+    assert_eq!(
+        verify(&items[..1], &leaves),
+        Err(Error::ProofItemsExhausted)
+    );
+    // the first extra item is taken as the bagged rhs peaks
+    let mut longer = items.to_vec();
+    longer.extend(vec![NumberHash::from(0), NumberHash::from(1)]);
+    assert_eq!(verify(&longer, &leaves), Err(Error::ProofItemsLeftover));
+    assert_eq!(
+        verify(items, &[(mmr_size, NumberHash::from(0))]),
+        Err(Error::PositionOutOfRange)
+    );
+    assert_eq!(
+        verify(items, &[(2, NumberHash::from(0))]),
+        Err(Error::NodeProofsNotSupported)
+    );
+}
@@ -96,3 +96,17 @@ proptest! {
         test_sequence_sub_func(count, leaves);
     }
 }
+
+#[test]
+fn test_proof_debug() {
+    let store = MemStore::default();
+    let mut mmr = MMR::<_, MergeNumberRange, _>::new(0, &store);
+    for i in 0u32..3 {
+        mmr.push(NumberRange::from(i)).expect("push");
+    }
+    let proof = mmr.gen_proof(vec![0]).expect("gen proof");
+    assert_eq!(
+        format!("{:?}", proof),
+        "MerkleProof { mmr_size: 4, items: [1: NumberRange(1, 1), 3: NumberRange(2, 2)] }"
+    );
+}
@@ -0,0 +1,46 @@
+use super::{MergeNumberHash, NumberHash};
+use crate::{
+    leaf_index_to_pos,
+    util::{SyncMemMMR, SyncMemStore},
+};
+
+#[test]
+fn test_mmr_is_send() {
+    fn assert_send<T: Send>(_: &T) {}
+    let store = SyncMemStore::default();
+    let mut mmr = SyncMemMMR::<_, MergeNumberHash>::new(0, &store);
+    mmr.push(NumberHash::from(0)).unwrap();
+    assert_send(&mmr);
+    // a MMR can be moved to the thread using it
+    let root = std::thread::scope(|s| s.spawn(move || mmr.get_root()).join().unwrap());
+    assert_eq!(root, Ok(NumberHash::from(0)));
+}
+
+#[test]
+fn test_concurrent_gen_proof() {
+    let store = SyncMemStore::default();
+    let mut mmr = SyncMemMMR::<_, MergeNumberHash>::new(0, &store);
+    for i in 0u32..100 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    mmr.commit().unwrap();
+    let mmr_size = mmr.mmr_size();
+    let root = mmr.get_root().unwrap();
+
+    std::thread::scope(|s| {
+        for t in 0u32..8 {
+            let store = &store;
+            let root = root.clone();
+            s.spawn(move || {
+                let mmr = SyncMemMMR::<_, MergeNumberHash>::new(mmr_size, store);
+                for i in (t..100).step_by(8) {
+                    let pos = leaf_index_to_pos(i.into());
+                    let proof = mmr.gen_proof(vec![pos]).unwrap();
+                    assert!(proof
+                        .verify(root.clone(), vec![(pos, NumberHash::from(i))])
+                        .unwrap());
+                }
+            });
+        }
+    });
+}
@@ -0,0 +1,46 @@
+use super::{MergeNumberHash, NumberHash};
+use crate::{
+    typed::{LeafIndex, MmrSize, Pos},
+    util::{MemMMR, MemStore},
+};
+
+#[test]
+fn test_typed_conversions() {
+    assert_eq!(LeafIndex(0).to_pos(), Pos(0));
+    assert_eq!(LeafIndex(2).to_pos(), Pos(3));
+    assert_eq!(LeafIndex(10).to_mmr_size(), MmrSize(19));
+    assert!(Pos(7).is_leaf());
+    assert_eq!(Pos(14).height(), 3);
+    assert_eq!(Pos(7).to_leaf_index(), Some(LeafIndex(4)));
+    assert_eq!(Pos(14).to_leaf_index(), None);
+    assert_eq!(MmrSize(19).leaf_count(), 11);
+    assert_eq!(MmrSize(0).leaf_count(), 0);
+    // the conversions are the inverse of each other
+    for index in (0..100).map(LeafIndex) {
+        assert_eq!(index.to_pos().to_leaf_index(), Some(index));
+        assert_eq!(index.to_mmr_size().leaf_count(), index.0 + 1);
+    }
+}
+
+#[test]
+fn test_typed_gen_proof_and_verify() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(0, &store);
+    for i in 0u32..11 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    assert_eq!(mmr.typed_mmr_size(), LeafIndex(10).to_mmr_size());
+    let root = mmr.get_root().unwrap();
+    let pos = LeafIndex(5).to_pos();
+    let proof = mmr.gen_proof_typed(vec![pos]).unwrap();
+    assert_eq!(proof.typed_mmr_size(), mmr.typed_mmr_size());
+    assert!(proof
+        .verify_typed(root, vec![(pos, NumberHash::from(5))])
+        .unwrap());
+}
+
+#[test]
+fn test_typed_compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/leaf_index_as_pos.rs");
+}
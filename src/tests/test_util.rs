@@ -0,0 +1,552 @@
+use super::{new_blake2b, MergeNumberHash, NumberHash};
+use crate::{
+    helper::mmr_position_to_k_index,
+    leaf_index_to_pos,
+    util::{
+        all_valid, gen_nested_proof, multi_proof::verify_multi, verify_batch_detailed, verify_many,
+        ArrayMMR, ArrayStore, CachingStore, LeafMMR, MemMMR, MemStore, Transcript, TranscriptMerge,
+    },
+    Error, MMRStoreReadOps, MMRStoreWriteOps, Merge, MerkleProof, MMR,
+};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+#[test]
+fn test_transcript_merge() {
+    let store = MemStore::default();
+    let (mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let pos = leaf_index_to_pos(5);
+    let proof = mmr.gen_proof(vec![pos]).unwrap();
+
+    let proof = MerkleProof::<_, TranscriptMerge<MergeNumberHash>>::new(
+        proof.mmr_size(),
+        proof.proof_items().to_vec(),
+    );
+    let verify = || {
+        proof
+            .verify(root.clone(), vec![(pos, NumberHash::from(5))])
+            .unwrap()
+    };
+    let transcript = Transcript::new();
+    assert!(TranscriptMerge::<MergeNumberHash>::record(
+        &transcript,
+        verify
+    ));
+    let merges = transcript.take();
+    // 3 merges up to the peak and 1 to bag the peaks
+    assert_eq!(merges.len(), 4);
+    for (lhs, rhs, out) in &merges {
+        assert_eq!(&MergeNumberHash::merge(lhs, rhs).unwrap(), out);
+    }
+    assert_eq!(merges.last().map(|(_, _, out)| out), Some(&root));
+    assert!(transcript.take().is_empty());
+
+    // merges outside of `record` aren't recorded
+    assert!(verify());
+    assert!(transcript.take().is_empty());
+
+    // a nested transcript of the same merge takes the merges until it returns
+    let outer = Transcript::new();
+    let inner = Transcript::new();
+    TranscriptMerge::<MergeNumberHash>::record(&outer, || {
+        assert!(verify());
+        TranscriptMerge::<MergeNumberHash>::record(&inner, || assert!(verify()));
+        assert!(verify());
+    });
+    assert_eq!(inner.take(), merges);
+    assert_eq!(outer.take(), [merges.clone(), merges].concat());
+}
+
+#[cfg(feature = "debug-checks")]
+#[test]
+fn test_mem_store_rejects_non_contiguous_append() {
+    use crate::Error;
+
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..3).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    let mmr_size = mmr.mmr_size();
+    assert_eq!(mmr_size, 4);
+
+    // a MMR opened with a wrong size stages a segment after a gap
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(7, &store);
+    mmr.push(NumberHash::from(3)).unwrap();
+    assert_eq!(mmr.commit(), Err(Error::NonContiguousAppend(7)));
+
+    // the right size appends as usual
+    let mut mmr = MemMMR::<_, MergeNumberHash>::new(mmr_size, &store);
+    mmr.push(NumberHash::from(3)).unwrap();
+    assert_eq!(mmr.commit(), Ok(3));
+}
+
+#[test]
+fn test_nested_proof() {
+    let outer_store = MemStore::default();
+    let mut outer = MemMMR::<_, MergeNumberHash>::new(0, &outer_store);
+    // epochs of 5 leaves each
+    let epoch_stores: Vec<MemStore<NumberHash>> = (0..4).map(|_| MemStore::default()).collect();
+    let mut epochs = Vec::new();
+    for (epoch, store) in epoch_stores.iter().enumerate() {
+        let leaves = (0u32..5).map(|i| NumberHash::from(epoch as u32 * 5 + i));
+        let (inner, positions) = MemMMR::<_, MergeNumberHash>::build(store, leaves).unwrap();
+        let outer_pos = outer.push(inner.get_root().unwrap()).unwrap();
+        epochs.push((inner, positions, outer_pos));
+    }
+    let top_root = outer.get_root().unwrap();
+
+    let (inner, positions, outer_pos) = &epochs[2];
+    let proof = gen_nested_proof(inner, positions[3], &outer, *outer_pos).unwrap();
+    assert!(proof
+        .verify(top_root.clone(), NumberHash::from(13))
+        .unwrap());
+    // a leaf of another epoch
+    assert!(!proof.verify(top_root.clone(), NumberHash::from(8)).unwrap());
+
+    // the inner root must be the outer leaf
+    let (other_inner, other_positions, _) = &epochs[1];
+    let proof = gen_nested_proof(other_inner, other_positions[3], &outer, *outer_pos).unwrap();
+    assert!(!proof.verify(top_root, NumberHash::from(8)).unwrap());
+}
+
+#[test]
+fn test_verify_batch_detailed() {
+    use crate::Error;
+
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let item = |i: usize, value: u32| {
+        (
+            mmr.gen_proof(vec![positions[i]]).unwrap(),
+            vec![(positions[i], NumberHash::from(value))],
+        )
+    };
+    let valid = vec![item(0, 0), item(5, 5), item(10, 10)];
+    let results = verify_batch_detailed(root.clone(), valid);
+    assert_eq!(results, vec![Ok(true), Ok(true), Ok(true)]);
+    assert!(all_valid(&results));
+
+    // a truncated proof
+    let (proof, leaves) = item(3, 3);
+    let malformed = (
+        MerkleProof::new(proof.mmr_size(), proof.proof_items()[1..2].to_vec()),
+        leaves,
+    );
+    let results =
+        verify_batch_detailed(root, vec![item(0, 0), item(5, 6), malformed, item(10, 10)]);
+    assert_eq!(
+        results,
+        vec![
+            Ok(true),
+            Ok(false),
+            Err(Error::ProofItemsExhausted),
+            Ok(true)
+        ]
+    );
+    assert!(!all_valid(&results));
+    assert!(all_valid(&[]));
+}
+
+#[test]
+fn test_verify_many() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let mmr_size = mmr.mmr_size();
+    let item = |indices: &[usize], offset: u32| {
+        let pos_list: Vec<u64> = indices.iter().map(|i| positions[*i]).collect();
+        let leaves = indices
+            .iter()
+            .map(|i| (positions[*i], NumberHash::from(*i as u32 + offset)))
+            .collect();
+        (mmr.gen_proof(pos_list).unwrap(), leaves)
+    };
+    let mut items = vec![item(&[0], 0), item(&[1, 5], 0), item(&[3, 9, 10], 0)];
+    assert!(verify_many(root.clone(), mmr_size, &items).unwrap());
+    assert!(verify_many::<_, MergeNumberHash>(root.clone(), mmr_size, &[]).unwrap());
+    // proofs of another mmr_size never verify
+    assert!(!verify_many(root.clone(), mmr_size + 1, &items).unwrap());
+
+    items.insert(2, item(&[4, 6], 1));
+    assert!(!verify_many(root, mmr_size, &items).unwrap());
+}
+
+#[test]
+fn test_leaf_mmr() {
+    let store = MemStore::default();
+    let mut mmr = LeafMMR::new(MemMMR::<_, MergeNumberHash>::new(0, &store));
+    for i in 0u32..11 {
+        assert_eq!(mmr.append(NumberHash::from(i)).unwrap(), i as u64);
+    }
+    assert_eq!(mmr.leaf_count(), 11);
+    let root = mmr.get_root().unwrap();
+
+    let (proof, leaves) = mmr.prove(vec![2, 5, 10]).unwrap();
+    assert_eq!(
+        leaves,
+        vec![
+            (2, NumberHash::from(2)),
+            (5, NumberHash::from(5)),
+            (10, NumberHash::from(10))
+        ]
+    );
+    assert!(proof.verify_by_index(root.clone(), leaves).unwrap());
+    assert!(!proof
+        .verify_by_index(
+            root.clone(),
+            vec![
+                (2, NumberHash::from(2)),
+                (5, NumberHash::from(6)),
+                (10, NumberHash::from(10))
+            ]
+        )
+        .unwrap());
+
+    // proofs work the same once committed
+    mmr.commit().unwrap();
+    let (proof, leaves) = mmr.prove(vec![0]).unwrap();
+    assert!(proof.verify_by_index(root, leaves).unwrap());
+    assert_eq!(
+        mmr.prove(vec![11]).map(|_| ()),
+        Err(crate::Error::GenProofForInvalidLeaves)
+    );
+}
+
+// panics when a position is read twice
+struct ReadOnceStore<'a> {
+    store: &'a MemStore<NumberHash>,
+    reads: RefCell<BTreeSet<u64>>,
+}
+
+impl MMRStoreReadOps<NumberHash> for ReadOnceStore<'_> {
+    fn get_elem(&self, pos: u64) -> crate::Result<Option<NumberHash>> {
+        assert!(self.reads.borrow_mut().insert(pos), "{} is read twice", pos);
+        self.store.get_elem(pos)
+    }
+}
+
+#[test]
+fn test_caching_store() {
+    let store = MemStore::default();
+    let (mut mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..100).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    mmr.commit().unwrap();
+
+    let caching_store = CachingStore::new(
+        ReadOnceStore {
+            store: &store,
+            reads: Default::default(),
+        },
+        1024,
+    );
+    let mmr = MMR::<_, MergeNumberHash, _>::new(mmr.mmr_size(), caching_store);
+    // overlapping proofs read the shared siblings from the cache
+    for _ in 0..2 {
+        for (i, pos) in positions.iter().take(10).enumerate() {
+            let proof = mmr.gen_proof(vec![*pos]).unwrap();
+            assert!(proof
+                .verify(root.clone(), vec![(*pos, NumberHash::from(i as u32))])
+                .unwrap());
+        }
+        assert_eq!(mmr.get_root(), Ok(root.clone()));
+    }
+    assert_eq!(
+        mmr.store().cached_len(),
+        mmr.store().inner().reads.borrow().len()
+    );
+}
+
+#[test]
+fn test_caching_store_invalidation() {
+    let store = MemStore::default();
+    let mut mmr = MMR::<_, MergeNumberHash, _>::new(0, CachingStore::new(&store, 2));
+    for i in 0u32..4 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    mmr.commit().unwrap();
+    mmr.get_root().unwrap();
+    assert_eq!(mmr.store().cached_len(), 1);
+    // only 2 elements are kept
+    mmr.gen_proof(vec![0]).unwrap();
+    assert_eq!(mmr.store().cached_len(), 2);
+
+    // rewrite the cached positions with other leaves
+    mmr.rollback(0).unwrap();
+    assert_eq!(mmr.store().cached_len(), 0);
+    for i in 10u32..14 {
+        mmr.push(NumberHash::from(i)).unwrap();
+    }
+    mmr.commit().unwrap();
+    let expected_store = MemStore::default();
+    let (expected, _) =
+        MemMMR::<_, MergeNumberHash>::build(&expected_store, (10u32..14).map(NumberHash::from))
+            .unwrap();
+    assert_eq!(mmr.get_root(), expected.get_root());
+    assert_eq!(
+        mmr.gen_proof(vec![0]).unwrap().proof_items(),
+        expected.gen_proof(vec![0]).unwrap().proof_items()
+    );
+}
+
+// same hash as `MergeNumberHash` over fixed size items
+struct MergeArrayHash;
+
+impl Merge for MergeArrayHash {
+    type Item = [u8; 32];
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> crate::Result<Self::Item> {
+        let mut hasher = new_blake2b();
+        let mut hash = [0u8; 32];
+        hasher.update(lhs);
+        hasher.update(rhs);
+        hasher.finalize(&mut hash);
+        Ok(hash)
+    }
+}
+
+fn to_array(hash: &NumberHash) -> [u8; 32] {
+    let mut array = [0u8; 32];
+    array.copy_from_slice(&hash.0);
+    array
+}
+
+#[test]
+fn test_array_store() {
+    let leaves: Vec<NumberHash> = (0u32..1000).map(NumberHash::from).collect();
+    let store = MemStore::default();
+    let (mut mmr, positions) = MemMMR::<_, MergeNumberHash>::build(&store, leaves.clone()).unwrap();
+    mmr.commit().unwrap();
+    let array_store = ArrayStore::<32>::default();
+    let (mut array_mmr, array_positions) =
+        ArrayMMR::<MergeArrayHash, 32>::build(&array_store, leaves.iter().map(to_array)).unwrap();
+    array_mmr.commit().unwrap();
+    assert_eq!(positions, array_positions);
+    let root = array_mmr.get_root().unwrap();
+    assert_eq!(root, to_array(&mmr.get_root().unwrap()));
+    assert_eq!((&array_store).len(), Ok(mmr.mmr_size()));
+
+    let pos_list = vec![positions[3], positions[500], positions[999]];
+    let proof = array_mmr.gen_proof(pos_list.clone()).unwrap();
+    let proven: Vec<_> = pos_list
+        .iter()
+        .zip([3, 500, 999])
+        .map(|(pos, i)| (*pos, to_array(&leaves[i])))
+        .collect();
+    assert!(proof.verify(root, proven).unwrap());
+
+    // append-only, but the last positions can be rolled back
+    assert_eq!(array_mmr.pop(), Ok(Some(to_array(&leaves[999]))));
+    array_mmr.commit().unwrap();
+    assert_eq!(mmr.pop(), Ok(Some(leaves[999].clone())));
+    assert_eq!(
+        array_mmr.get_root().unwrap(),
+        to_array(&mmr.get_root().unwrap())
+    );
+    assert_eq!((&array_store).len(), Ok(array_mmr.mmr_size()));
+    let mut array_store_ref = &array_store;
+    assert_eq!(
+        array_store_ref.append(0, vec![[0u8; 32]]),
+        Err(crate::Error::NonContiguousAppend(0))
+    );
+    assert!(array_store_ref.delete(0).is_err());
+}
+
+// tags every parent with its height, so the heights of the layers are checked
+struct HeightMerge;
+
+impl Merge for HeightMerge {
+    type Item = NumberHash;
+
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> crate::Result<Self::Item> {
+        Self::merge_with_height(lhs, rhs, 0)
+    }
+
+    fn merge_with_height(
+        lhs: &Self::Item,
+        rhs: &Self::Item,
+        height: u8,
+    ) -> crate::Result<Self::Item> {
+        let mut data = vec![height];
+        data.extend_from_slice(&lhs.0);
+        data.extend_from_slice(&rhs.0);
+        Ok(NumberHash::from(data))
+    }
+}
+
+// the leaves of `pos_list` along with their k-index
+fn leaves_with_k_index<M: Merge<Item = NumberHash>>(
+    mmr: &MemMMR<NumberHash, M>,
+    pos_list: Vec<u64>,
+) -> Vec<(u64, usize, NumberHash)> {
+    mmr_position_to_k_index(pos_list, mmr.mmr_size())
+        .into_iter()
+        .map(|(pos, k_index)| (pos, k_index, mmr.batch().get_elem(pos).unwrap().unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_verify_multi() {
+    check_verify_multi::<MergeNumberHash>();
+    check_verify_multi::<HeightMerge>();
+}
+
+fn check_verify_multi<M: Merge<Item = NumberHash>>() {
+    for count in [1u32, 2, 3, 7, 11, 32, 33, 100] {
+        let store = MemStore::default();
+        let (mmr, positions) =
+            MemMMR::<_, M>::build(&store, (0..count).map(NumberHash::from)).unwrap();
+        let root = mmr.get_root().unwrap();
+        let subsets: Vec<Vec<u64>> = vec![
+            positions.clone(),
+            positions.iter().step_by(3).cloned().collect(),
+            positions.iter().rev().step_by(5).cloned().collect(),
+            vec![positions[0], positions[positions.len() - 1]],
+            vec![positions[positions.len() / 2]],
+        ];
+        for pos_list in subsets {
+            let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+            let leaves = leaves_with_k_index(&mmr, pos_list.clone());
+            assert_eq!(
+                verify_multi::<_, M>(
+                    root.clone(),
+                    mmr.mmr_size(),
+                    leaves.clone(),
+                    proof.proof_items()
+                ),
+                Ok(true),
+                "count {} positions {:?}",
+                count,
+                pos_list
+            );
+            let mut wrong_leaves = leaves;
+            wrong_leaves[0].2 = NumberHash::from(count);
+            assert_eq!(
+                verify_multi::<_, M>(
+                    root.clone(),
+                    mmr.mmr_size(),
+                    wrong_leaves,
+                    proof.proof_items()
+                ),
+                Ok(false)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_verify_multi_errors() {
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    let root = mmr.get_root().unwrap();
+    let pos_list = vec![positions[1], positions[4]];
+    let proof = mmr.gen_proof(pos_list.clone()).unwrap();
+    let leaves = leaves_with_k_index(&mmr, pos_list);
+    let verify = |leaves, proof_items: &[NumberHash]| {
+        verify_multi::<_, MergeNumberHash>(root.clone(), mmr.mmr_size(), leaves, proof_items)
+    };
+
+    let items = proof.proof_items();
+    assert_eq!(
+        verify(leaves.clone(), &items[..1]),
+        Err(Error::ProofItemsExhausted)
+    );
+    assert_eq!(verify(vec![], items), Err(Error::CorruptedProof));
+    assert_eq!(
+        verify(vec![(19, 0, NumberHash::from(0))], items),
+        Err(Error::PositionOutOfRange)
+    );
+    assert_eq!(
+        verify(vec![(2, 0, NumberHash::from(0))], items),
+        Err(Error::NodeProofsNotSupported)
+    );
+    // a k-index beyond the peak
+    let mut wrong_k_index = leaves.clone();
+    wrong_k_index[1].1 = 9;
+    assert_eq!(
+        verify(wrong_k_index, items),
+        Err(Error::KIndexMismatch(positions[4]))
+    );
+    // the leaf at 4 claimed at 3 under the same peak, with the k-index of either position
+    let (_, k_index, leaf) = leaves[1].clone();
+    let moved = |pos, k_index| vec![leaves[0].clone(), (pos, k_index, leaf.clone())];
+    assert_eq!(
+        verify(moved(positions[3], k_index), items),
+        Err(Error::KIndexMismatch(positions[3]))
+    );
+    let moved_k_index = mmr_position_to_k_index(vec![positions[3]], mmr.mmr_size())[0].1;
+    assert_eq!(verify(moved(positions[3], moved_k_index), items), Ok(false));
+}
+
+#[test]
+fn test_mem_store_delete() {
+    let store = MemStore::default();
+    let (mut mmr, _) =
+        MemMMR::<_, MergeNumberHash>::build(&store, (0u32..11).map(NumberHash::from)).unwrap();
+    mmr.commit().unwrap();
+    let mut store_ref = &store;
+    store_ref.delete(3).unwrap();
+    assert_eq!(store_ref.get_elem(3), Ok(None));
+    assert!(store_ref.get_elem(4).unwrap().is_some());
+    // deleting a missing position is fine
+    store_ref.delete(3).unwrap();
+
+    store_ref.delete_range(5..9).unwrap();
+    for pos in 5..9 {
+        assert_eq!(store_ref.get_elem(pos), Ok(None), "pos {}", pos);
+    }
+    assert!(store_ref.get_elem(9).unwrap().is_some());
+    assert_eq!(store_ref.len(), Ok(19));
+    store_ref.delete_range(15..19).unwrap();
+    assert_eq!(store_ref.len(), Ok(15));
+    // an empty range deletes nothing
+    let (start, end) = (12, 10);
+    store_ref.delete_range(start..end).unwrap();
+    assert!(store_ref.get_elem(11).unwrap().is_some());
+
+    // the cache of a caching store is kept coherent
+    let caching = CachingStore::new(&store, 16);
+    assert!(caching.get_elem(9).unwrap().is_some());
+    let mut caching = caching;
+    caching.delete_range(9..11).unwrap();
+    assert_eq!(caching.get_elem(9), Ok(None));
+    assert_eq!(store_ref.get_elem(10), Ok(None));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn test_structural_merges() {
+    use crate::util::{ConcatMerge, Tree, TreeMerge};
+
+    // 7 leaves have the peaks 6, 9 and 10
+    let store = MemStore::default();
+    let (mmr, positions) =
+        MemMMR::<_, TreeMerge<u32>>::build(&store, (0u32..7).map(Tree::Leaf)).unwrap();
+    let leaf = Tree::Leaf;
+    let peak_6 = Tree::node(Tree::node(leaf(0), leaf(1)), Tree::node(leaf(2), leaf(3)));
+    let peak_9 = Tree::node(leaf(4), leaf(5));
+    assert_eq!(mmr.batch().get_elem(6), Ok(Some(peak_6.clone())));
+    // bagging from right to left
+    let root = mmr.get_root().unwrap();
+    assert_eq!(root, Tree::node(Tree::node(leaf(6), peak_9), peak_6));
+
+    let proof = mmr.gen_proof(vec![positions[2]]).unwrap();
+    assert_eq!(
+        proof.proof_items(),
+        &[
+            leaf(3),
+            Tree::node(leaf(0), leaf(1)),
+            Tree::node(leaf(6), Tree::node(leaf(4), leaf(5)))
+        ]
+    );
+    assert_eq!(proof.verify(root, vec![(positions[2], leaf(2))]), Ok(true));
+
+    let store = MemStore::default();
+    let (mmr, _) = MemMMR::<_, ConcatMerge>::build(&store, (0u8..7).map(|i| vec![i])).unwrap();
+    assert_eq!(mmr.get_root(), Ok(vec![6, 4, 5, 0, 1, 2, 3]));
+}
@@ -0,0 +1,107 @@
+//! Newtypes for the different meanings of `u64` across the API.
+//!
+//! A leaf index counts leaves only, a position addresses any node of the MMR, and a mmr size
+//! is the count of all nodes. Mixing them up is a common bug, the types below make it a
+//! compile error instead.
+
+use crate::helper::{
+    leaf_index_to_mmr_size, leaf_index_to_pos, mmr_size_to_leaf_count, pos_height_in_tree,
+    pos_to_leaf_index,
+};
+use crate::{vec::Vec, MMRStoreReadOps, Merge, MerkleProof, Result, MMR};
+
+/// Index of a leaf, starts with 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LeafIndex(pub u64);
+
+/// Position of a node (leaf or internal node) in the MMR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pos(pub u64);
+
+/// Count of all nodes in the MMR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MmrSize(pub u64);
+
+impl LeafIndex {
+    /// Position of the leaf, see `helper::leaf_index_to_pos`.
+    pub fn to_pos(self) -> Pos {
+        Pos(leaf_index_to_pos(self.0))
+    }
+
+    /// Size of the MMR once the leaf is pushed, see `helper::leaf_index_to_mmr_size`.
+    pub fn to_mmr_size(self) -> MmrSize {
+        MmrSize(leaf_index_to_mmr_size(self.0))
+    }
+}
+
+impl Pos {
+    /// Height of the node, leaves are at height 0.
+    pub fn height(self) -> u8 {
+        pos_height_in_tree(self.0)
+    }
+
+    pub fn is_leaf(self) -> bool {
+        self.height() == 0
+    }
+
+    /// Index of the leaf at the position, `None` if it's an internal node, see
+    /// `helper::pos_to_leaf_index`.
+    pub fn to_leaf_index(self) -> Option<LeafIndex> {
+        pos_to_leaf_index(self.0).map(LeafIndex)
+    }
+}
+
+impl MmrSize {
+    /// Count of leaves in the MMR, see `helper::mmr_size_to_leaf_count`.
+    pub fn leaf_count(self) -> u64 {
+        mmr_size_to_leaf_count(self.0)
+    }
+}
+
+impl From<LeafIndex> for u64 {
+    fn from(index: LeafIndex) -> u64 {
+        index.0
+    }
+}
+
+impl From<Pos> for u64 {
+    fn from(pos: Pos) -> u64 {
+        pos.0
+    }
+}
+
+impl From<MmrSize> for u64 {
+    fn from(mmr_size: MmrSize) -> u64 {
+        mmr_size.0
+    }
+}
+
+impl<T, M, S> MMR<T, M, S> {
+    pub fn typed_mmr_size(&self) -> MmrSize {
+        MmrSize(self.mmr_size())
+    }
+}
+
+impl<T: Clone + PartialEq, M: Merge<Item = T>, S: MMRStoreReadOps<T>> MMR<T, M, S> {
+    /// Same as `gen_proof`, but only accepts node positions.
+    pub fn gen_proof_typed(&self, pos_list: Vec<Pos>) -> Result<MerkleProof<T, M>> {
+        self.gen_proof(pos_list.into_iter().map(u64::from).collect())
+    }
+}
+
+impl<T: Clone + PartialEq, M: Merge<Item = T>> MerkleProof<T, M> {
+    pub fn typed_mmr_size(&self) -> MmrSize {
+        MmrSize(self.mmr_size())
+    }
+
+    /// Same as `verify`, but only accepts node positions.
+    pub fn verify_typed(&self, root: T, leaves: Vec<(Pos, T)>) -> Result<bool> {
+        self.verify(
+            root,
+            leaves
+                .into_iter()
+                .map(|(pos, leaf)| (pos.into(), leaf))
+                .collect(),
+        )
+    }
+}
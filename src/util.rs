@@ -1,6 +1,14 @@
 use crate::collections::BTreeMap;
-use crate::{vec::Vec, MMRStoreReadOps, MMRStoreWriteOps, Result, MMR};
+use crate::{
+    leaf_index_to_pos, vec, vec::Vec, Error, MMRStoreReadOps, MMRStoreWriteOps, Merge, MerkleProof,
+    Result, MMR,
+};
 use core::cell::RefCell;
+use core::ops::Range;
+
+pub mod multi_proof;
+
+pub use crate::encoding::{LengthPrefixed, LengthWidth, Packable};
 
 #[derive(Clone)]
 pub struct MemStore<T>(RefCell<BTreeMap<u64, T>>);
@@ -21,16 +29,666 @@ impl<T: Clone> MMRStoreReadOps<T> for &MemStore<T> {
     fn get_elem(&self, pos: u64) -> Result<Option<T>> {
         Ok(self.0.borrow().get(&pos).cloned())
     }
+
+    fn len(&self) -> Result<u64> {
+        // compaction only deletes positions below the peaks, the last position is kept
+        Ok(self.0.borrow().keys().next_back().map_or(0, |pos| pos + 1))
+    }
 }
 
 impl<T> MMRStoreWriteOps<T> for &MemStore<T> {
     fn append(&mut self, pos: u64, elems: Vec<T>) -> Result<()> {
         let mut store = self.0.borrow_mut();
+        #[cfg(feature = "debug-checks")]
+        {
+            // compaction only deletes positions below the peaks, the last position is kept
+            let len = store.keys().next_back().map_or(0, |last_pos| last_pos + 1);
+            if pos != len {
+                return Err(crate::Error::NonContiguousAppend(pos));
+            }
+        }
         for (i, elem) in elems.into_iter().enumerate() {
             store.insert(pos + i as u64, elem);
         }
         Ok(())
     }
+
+    fn delete(&mut self, pos: u64) -> Result<()> {
+        self.0.borrow_mut().remove(&pos);
+        Ok(())
+    }
+
+    fn delete_range(&mut self, range: Range<u64>) -> Result<()> {
+        let mut store = self.0.borrow_mut();
+        let mut deleted = store.split_off(&range.start);
+        let mut rest = deleted.split_off(&range.end.max(range.start));
+        store.append(&mut rest);
+        Ok(())
+    }
+
+    fn update(&mut self, pos: u64, elem: T) -> Result<()> {
+        self.0.borrow_mut().insert(pos, elem);
+        Ok(())
+    }
 }
 
 pub type MemMMR<'a, T, M> = MMR<T, M, &'a MemStore<T>>;
+
+/// A store of fixed size `[u8; N]` items, e.g. 32 bytes hashes, kept in one contiguous buffer
+/// so no node is allocated on its own.
+///
+/// It's append-only: appends must start at the end of the store, otherwise they fail with
+/// `Error::NonContiguousAppend`, and only the last position can be deleted, which is enough
+/// for `MMR::rollback` and `MMR::pop` but not for `MMR::compact_below`.
+#[derive(Clone)]
+pub struct ArrayStore<const N: usize>(RefCell<Vec<[u8; N]>>);
+
+impl<const N: usize> Default for ArrayStore<N> {
+    fn default() -> Self {
+        ArrayStore(RefCell::new(Vec::new()))
+    }
+}
+
+impl<const N: usize> MMRStoreReadOps<[u8; N]> for &ArrayStore<N> {
+    fn get_elem(&self, pos: u64) -> Result<Option<[u8; N]>> {
+        Ok(self.0.borrow().get(pos as usize).copied())
+    }
+
+    fn len(&self) -> Result<u64> {
+        Ok(self.0.borrow().len() as u64)
+    }
+}
+
+impl<const N: usize> MMRStoreWriteOps<[u8; N]> for &ArrayStore<N> {
+    fn append(&mut self, pos: u64, elems: Vec<[u8; N]>) -> Result<()> {
+        let mut store = self.0.borrow_mut();
+        if pos != store.len() as u64 {
+            return Err(Error::NonContiguousAppend(pos));
+        }
+        store.extend(elems);
+        Ok(())
+    }
+
+    fn delete(&mut self, pos: u64) -> Result<()> {
+        let mut store = self.0.borrow_mut();
+        match (store.len() as u64).checked_sub(1) {
+            Some(last_pos) if last_pos == pos => {
+                store.pop();
+                Ok(())
+            }
+            // already deleted
+            _ if pos >= store.len() as u64 => Ok(()),
+            _ => Err(Error::StoreError(
+                "only the last position can be deleted".into(),
+            )),
+        }
+    }
+
+    fn update(&mut self, pos: u64, elem: [u8; N]) -> Result<()> {
+        let mut store = self.0.borrow_mut();
+        let stored = store
+            .get_mut(pos as usize)
+            .ok_or(Error::InconsistentStore)?;
+        *stored = elem;
+        Ok(())
+    }
+}
+
+pub type ArrayMMR<'a, M, const N: usize> = MMR<[u8; N], M, &'a ArrayStore<N>>;
+
+/// Same as `MemStore`, but implements the async store traits, enabled by `async`.
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct AsyncMemStore<T>(RefCell<BTreeMap<u64, T>>);
+
+#[cfg(feature = "async")]
+impl<T> Default for AsyncMemStore<T> {
+    fn default() -> Self {
+        AsyncMemStore(RefCell::new(Default::default()))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: Clone> crate::MMRStoreReadOpsAsync<T> for &AsyncMemStore<T> {
+    async fn get_elem(&self, pos: u64) -> Result<Option<T>> {
+        Ok(self.0.borrow().get(&pos).cloned())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> crate::MMRStoreWriteOpsAsync<T> for &AsyncMemStore<T> {
+    async fn append(&mut self, pos: u64, elems: Vec<T>) -> Result<()> {
+        let mut store = self.0.borrow_mut();
+        for (i, elem) in elems.into_iter().enumerate() {
+            store.insert(pos + i as u64, elem);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+pub type AsyncMemMMR<'a, T, M> = crate::async_mmr::AsyncMMR<T, M, &'a AsyncMemStore<T>>;
+
+/// Read-through cache of a store, keeps up to `capacity` elements by position.
+///
+/// Reads are served from the cache if possible and populate it otherwise, appends and deletes
+/// invalidate the affected positions. Once full, the lowest position is evicted, which is
+/// usually the oldest node and the least likely to be in new proofs.
+pub struct CachingStore<T, S> {
+    store: S,
+    cache: RefCell<BTreeMap<u64, T>>,
+    capacity: usize,
+}
+
+impl<T, S> CachingStore<T, S> {
+    pub fn new(store: S, capacity: usize) -> Self {
+        CachingStore {
+            store,
+            cache: RefCell::new(BTreeMap::new()),
+            capacity,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.store
+    }
+
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    /// Count of cached elements
+    pub fn cached_len(&self) -> usize {
+        self.cache.borrow().len()
+    }
+
+    fn insert_cache(&self, pos: u64, elem: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut cache = self.cache.borrow_mut();
+        if cache.len() >= self.capacity && !cache.contains_key(&pos) {
+            let lowest_pos = *cache.keys().next().expect("full");
+            cache.remove(&lowest_pos);
+        }
+        cache.insert(pos, elem);
+    }
+}
+
+impl<T: Clone, S: MMRStoreReadOps<T>> MMRStoreReadOps<T> for CachingStore<T, S> {
+    fn get_elem(&self, pos: u64) -> Result<Option<T>> {
+        if let Some(elem) = self.cache.borrow().get(&pos) {
+            return Ok(Some(elem.clone()));
+        }
+        let elem = self.store.get_elem(pos)?;
+        if let Some(elem) = &elem {
+            self.insert_cache(pos, elem.clone());
+        }
+        Ok(elem)
+    }
+
+    fn get_elems(&self, positions: &[u64]) -> Result<Vec<Option<T>>> {
+        let mut elems: Vec<Option<T>> = {
+            let cache = self.cache.borrow();
+            positions
+                .iter()
+                .map(|pos| cache.get(pos).cloned())
+                .collect()
+        };
+        let missing: Vec<u64> = positions
+            .iter()
+            .zip(&elems)
+            .filter(|(_, elem)| elem.is_none())
+            .map(|(pos, _)| *pos)
+            .collect();
+        if missing.is_empty() {
+            return Ok(elems);
+        }
+        let mut stored = self.store.get_elems(&missing)?.into_iter();
+        for (pos, elem) in positions.iter().zip(elems.iter_mut()) {
+            if elem.is_none() {
+                *elem = stored.next().flatten();
+                if let Some(elem) = elem {
+                    self.insert_cache(*pos, elem.clone());
+                }
+            }
+        }
+        Ok(elems)
+    }
+
+    fn len(&self) -> Result<u64> {
+        self.store.len()
+    }
+}
+
+impl<T, S: MMRStoreWriteOps<T>> MMRStoreWriteOps<T> for CachingStore<T, S> {
+    fn append(&mut self, pos: u64, elems: Vec<T>) -> Result<()> {
+        let end_pos = pos + elems.len() as u64;
+        let mut cache = self.cache.borrow_mut();
+        let stale: Vec<u64> = cache.range(pos..end_pos).map(|(pos, _)| *pos).collect();
+        for pos in stale {
+            cache.remove(&pos);
+        }
+        self.store.append(pos, elems)
+    }
+
+    fn delete(&mut self, pos: u64) -> Result<()> {
+        self.cache.borrow_mut().remove(&pos);
+        self.store.delete(pos)
+    }
+
+    fn delete_range(&mut self, range: Range<u64>) -> Result<()> {
+        self.cache
+            .borrow_mut()
+            .retain(|pos, _| !range.contains(pos));
+        self.store.delete_range(range)
+    }
+
+    fn update(&mut self, pos: u64, elem: T) -> Result<()> {
+        self.cache.borrow_mut().remove(&pos);
+        self.store.update(pos, elem)
+    }
+}
+
+/// A proof and the `(leaf_index, leaf)` it proves
+pub type IndexedProof<T, M> = (MerkleProof<T, M>, Vec<(u64, T)>);
+
+/// A MMR addressed by leaf index only, positions never leave the wrapper.
+/// Proofs are verified with `MerkleProof::verify_by_index`.
+pub struct LeafMMR<T, M, S> {
+    mmr: MMR<T, M, S>,
+}
+
+impl<T, M, S> LeafMMR<T, M, S> {
+    pub fn new(mmr: MMR<T, M, S>) -> Self {
+        LeafMMR { mmr }
+    }
+
+    pub fn mmr(&self) -> &MMR<T, M, S> {
+        &self.mmr
+    }
+
+    pub fn into_inner(self) -> MMR<T, M, S> {
+        self.mmr
+    }
+
+    pub fn leaf_count(&self) -> u64 {
+        self.mmr.leaf_count()
+    }
+}
+
+impl<T: Clone + PartialEq, M: Merge<Item = T>, S: MMRStoreReadOps<T>> LeafMMR<T, M, S> {
+    /// Append a leaf, returns its leaf index.
+    pub fn append(&mut self, value: T) -> Result<u64> {
+        let index = self.leaf_count();
+        self.mmr.push(value)?;
+        Ok(index)
+    }
+
+    pub fn get_root(&self) -> Result<T> {
+        self.mmr.get_root()
+    }
+
+    /// Generate a proof of the leaves, returns it with the `(leaf_index, leaf)` to verify.
+    pub fn prove(&self, indices: Vec<u64>) -> Result<IndexedProof<T, M>> {
+        let leaf_count = self.leaf_count();
+        let leaves = indices
+            .into_iter()
+            .map(|index| {
+                if index >= leaf_count {
+                    return Err(Error::GenProofForInvalidLeaves);
+                }
+                let leaf = self
+                    .mmr
+                    .batch()
+                    .get_elem(leaf_index_to_pos(index))?
+                    .ok_or(Error::InconsistentStore)?;
+                Ok((index, leaf))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let proof = self.mmr.gen_proof(
+            leaves
+                .iter()
+                .map(|(index, _)| leaf_index_to_pos(*index))
+                .collect(),
+        )?;
+        Ok((proof, leaves))
+    }
+}
+
+impl<T, M, S: MMRStoreWriteOps<T>> LeafMMR<T, M, S> {
+    pub fn commit(&mut self) -> Result<u64> {
+        self.mmr.commit()
+    }
+}
+
+/// Proof of a leaf in a two-level accumulator, an outer MMR whose leaves are roots of inner MMRs.
+pub struct NestedProof<T, M> {
+    inner_pos: u64,
+    inner_proof: MerkleProof<T, M>,
+    outer_pos: u64,
+    outer_proof: MerkleProof<T, M>,
+}
+
+impl<T: core::fmt::Debug, M> core::fmt::Debug for NestedProof<T, M> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("NestedProof")
+            .field("inner_pos", &self.inner_pos)
+            .field("inner_proof", &self.inner_proof)
+            .field("outer_pos", &self.outer_pos)
+            .field("outer_proof", &self.outer_proof)
+            .finish()
+    }
+}
+
+/// Generate a proof of the leaf at `inner_pos` in `inner`,
+/// the root of `inner` must be the leaf at `outer_pos` in `outer`.
+pub fn gen_nested_proof<T, M, S1, S2>(
+    inner: &MMR<T, M, S1>,
+    inner_pos: u64,
+    outer: &MMR<T, M, S2>,
+    outer_pos: u64,
+) -> Result<NestedProof<T, M>>
+where
+    T: Clone + PartialEq,
+    M: Merge<Item = T>,
+    S1: MMRStoreReadOps<T>,
+    S2: MMRStoreReadOps<T>,
+{
+    Ok(NestedProof {
+        inner_pos,
+        inner_proof: inner.gen_proof(vec![inner_pos])?,
+        outer_pos,
+        outer_proof: outer.gen_proof(vec![outer_pos])?,
+    })
+}
+
+impl<T: Clone + PartialEq, M: Merge<Item = T>> NestedProof<T, M> {
+    pub fn inner_proof(&self) -> &MerkleProof<T, M> {
+        &self.inner_proof
+    }
+
+    pub fn outer_proof(&self) -> &MerkleProof<T, M> {
+        &self.outer_proof
+    }
+
+    /// Verifies the leaf up to the root of the outer MMR,
+    /// the calculated inner root is verified as a leaf of the outer proof.
+    pub fn verify(&self, top_root: T, leaf: T) -> Result<bool> {
+        let inner_root = self
+            .inner_proof
+            .calculate_root(vec![(self.inner_pos, leaf)])?;
+        self.outer_proof
+            .verify(top_root, vec![(self.outer_pos, inner_root)])
+    }
+}
+
+/// A proof and the leaves it proves
+pub type BatchItem<T, M> = (MerkleProof<T, M>, Vec<(u64, T)>);
+
+/// Verifies every `(proof, leaves)` against the root, returns one result per item in order so
+/// all failures can be reported at once. Items are verified in parallel with the `rayon` feature.
+#[cfg(not(feature = "rayon"))]
+pub fn verify_batch_detailed<T, M>(root: T, items: Vec<BatchItem<T, M>>) -> Vec<Result<bool>>
+where
+    T: Clone + PartialEq,
+    M: Merge<Item = T>,
+{
+    items
+        .into_iter()
+        .map(|(proof, leaves)| proof.verify(root.clone(), leaves))
+        .collect()
+}
+
+/// Verifies every `(proof, leaves)` against the root, returns one result per item in order so
+/// all failures can be reported at once. Items are verified in parallel with the `rayon` feature.
+#[cfg(feature = "rayon")]
+pub fn verify_batch_detailed<T, M>(root: T, items: Vec<BatchItem<T, M>>) -> Vec<Result<bool>>
+where
+    T: Clone + PartialEq + Send + Sync,
+    M: Merge<Item = T> + Send,
+{
+    use rayon::prelude::*;
+
+    items
+        .into_par_iter()
+        .map(|(proof, leaves)| proof.verify(root.clone(), leaves))
+        .collect()
+}
+
+/// Verifies every `(proof, leaves)` of a MMR with `mmr_size` against the root, returns `false`
+/// at the first proof of another size or which fails, see `verify_batch_detailed` to get
+/// every failure.
+pub fn verify_many<T, M>(root: T, mmr_size: u64, proofs: &[BatchItem<T, M>]) -> Result<bool>
+where
+    T: Clone + PartialEq,
+    M: Merge<Item = T>,
+{
+    for (proof, leaves) in proofs {
+        if proof.mmr_size() != mmr_size || !proof.verify(root.clone(), leaves.clone())? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Returns whether all results of `verify_batch_detailed` are `Ok(true)`.
+pub fn all_valid(results: &[Result<bool>]) -> bool {
+    results.iter().all(|result| result == &Ok(true))
+}
+
+/// A `Merge` which concatenates the bytes of the children without hashing, enabled by
+/// `testing`. With distinct single byte leaves the root lists the leaves in merge order.
+#[cfg(feature = "testing")]
+pub struct ConcatMerge;
+
+#[cfg(feature = "testing")]
+impl Merge for ConcatMerge {
+    type Item = Vec<u8>;
+
+    fn merge(left: &Self::Item, right: &Self::Item) -> Result<Self::Item> {
+        let mut merged = Vec::with_capacity(left.len() + right.len());
+        merged.extend_from_slice(left);
+        merged.extend_from_slice(right);
+        Ok(merged)
+    }
+}
+
+/// A node of the explicit binary tree built by `TreeMerge`, enabled by `testing`.
+#[cfg(feature = "testing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tree<T> {
+    Leaf(T),
+    Node(crate::boxed::Box<Tree<T>>, crate::boxed::Box<Tree<T>>),
+}
+
+#[cfg(feature = "testing")]
+impl<T> Tree<T> {
+    /// Merge two trees into a node.
+    pub fn node(left: Tree<T>, right: Tree<T>) -> Self {
+        Tree::Node(crate::boxed::Box::new(left), crate::boxed::Box::new(right))
+    }
+}
+
+/// A `Merge` which keeps the children instead of hashing them, enabled by `testing`, so the
+/// root shows exactly which nodes are merged and in which order, peaks included.
+#[cfg(feature = "testing")]
+pub struct TreeMerge<T>(core::marker::PhantomData<T>);
+
+#[cfg(feature = "testing")]
+impl<T: Clone> Merge for TreeMerge<T> {
+    type Item = Tree<T>;
+
+    fn merge(left: &Self::Item, right: &Self::Item) -> Result<Self::Item> {
+        Ok(Tree::node(left.clone(), right.clone()))
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "std")] {
+        use std::any::{Any, TypeId};
+        use std::marker::PhantomData;
+        use std::rc::Rc;
+        use std::sync::RwLock;
+
+        /// A `Sync` variant of `MemStore`, which can be shared by threads to generate proofs
+        /// concurrently. `MMR` caches its root and isn't `Sync`, each thread opens its own MMR
+        /// over the shared store.
+        #[derive(Default)]
+        pub struct SyncMemStore<T>(RwLock<BTreeMap<u64, T>>);
+
+        impl<T: Clone> MMRStoreReadOps<T> for &SyncMemStore<T> {
+            fn get_elem(&self, pos: u64) -> Result<Option<T>> {
+                let store = self.0.read().map_err(|e| Error::StoreError(e.to_string()))?;
+                Ok(store.get(&pos).cloned())
+            }
+
+            fn len(&self) -> Result<u64> {
+                let store = self.0.read().map_err(|e| Error::StoreError(e.to_string()))?;
+                Ok(store.keys().next_back().map_or(0, |pos| pos + 1))
+            }
+        }
+
+        impl<T> MMRStoreWriteOps<T> for &SyncMemStore<T> {
+            fn append(&mut self, pos: u64, elems: Vec<T>) -> Result<()> {
+                let mut store = self.0.write().map_err(|e| Error::StoreError(e.to_string()))?;
+                for (i, elem) in elems.into_iter().enumerate() {
+                    store.insert(pos + i as u64, elem);
+                }
+                Ok(())
+            }
+
+            fn delete(&mut self, pos: u64) -> Result<()> {
+                let mut store = self.0.write().map_err(|e| Error::StoreError(e.to_string()))?;
+                store.remove(&pos);
+                Ok(())
+            }
+
+            fn update(&mut self, pos: u64, elem: T) -> Result<()> {
+                let mut store = self.0.write().map_err(|e| Error::StoreError(e.to_string()))?;
+                store.insert(pos, elem);
+                Ok(())
+            }
+        }
+
+        pub type SyncMemMMR<'a, T, M> = MMR<T, M, &'a SyncMemStore<T>>;
+
+        std::thread_local! {
+            // transcripts being recorded by `TranscriptMerge::record`, the innermost last, each
+            // with the type of its wrapped merge
+            static TRANSCRIPTS: RefCell<Vec<(TypeId, Box<dyn Any>)>> = RefCell::new(Vec::new());
+        }
+
+        /// The merges recorded by `TranscriptMerge::record`, a handle which can be cloned to
+        /// share the same transcript.
+        pub struct Transcript<T>(Rc<RefCell<Vec<(T, T, T)>>>);
+
+        impl<T> Transcript<T> {
+            pub fn new() -> Self {
+                Transcript(Rc::new(RefCell::new(Vec::new())))
+            }
+
+            /// Returns the recorded merges in order and clears the transcript.
+            pub fn take(&self) -> Vec<(T, T, T)> {
+                self.0.take()
+            }
+        }
+
+        impl<T> Default for Transcript<T> {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl<T> Clone for Transcript<T> {
+            fn clone(&self) -> Self {
+                Transcript(Rc::clone(&self.0))
+            }
+        }
+
+        /// A `Merge` wrapper which records every `(lhs, rhs, out)` passed through it, for example
+        /// to collect the witness of a verification for circuit generation.
+        ///
+        /// `Merge` has no receiver, so the merges are recorded into the transcript passed to
+        /// `record` while its closure runs on the same thread. Merges outside of `record` aren't
+        /// recorded, and a nested `record` of the same `M` takes the merges until it returns.
+        pub struct TranscriptMerge<M>(PhantomData<M>);
+
+        impl<M: Merge + 'static> TranscriptMerge<M>
+        where
+            M::Item: Clone + 'static,
+        {
+            /// Runs `f` and records the merges of `TranscriptMerge<M>` it calls into `transcript`.
+            pub fn record<R, F: FnOnce() -> R>(transcript: &Transcript<M::Item>, f: F) -> R {
+                // pops the transcript even if `f` panics
+                struct Recording;
+                impl Drop for Recording {
+                    fn drop(&mut self) {
+                        TRANSCRIPTS.with(|transcripts| transcripts.borrow_mut().pop());
+                    }
+                }
+                TRANSCRIPTS.with(|transcripts| {
+                    transcripts
+                        .borrow_mut()
+                        .push((TypeId::of::<M>(), Box::new(transcript.clone())))
+                });
+                let _recording = Recording;
+                f()
+            }
+
+            fn log(lhs: &M::Item, rhs: &M::Item, out: &M::Item) {
+                TRANSCRIPTS.with(|transcripts| {
+                    let transcripts = transcripts.borrow();
+                    let transcript = transcripts
+                        .iter()
+                        .rev()
+                        .find(|(merge, _)| *merge == TypeId::of::<M>())
+                        .and_then(|(_, transcript)| transcript.downcast_ref::<Transcript<M::Item>>());
+                    if let Some(transcript) = transcript {
+                        transcript
+                            .0
+                            .borrow_mut()
+                            .push((lhs.clone(), rhs.clone(), out.clone()));
+                    }
+                })
+            }
+        }
+
+        impl<M: Merge + 'static> Merge for TranscriptMerge<M>
+        where
+            M::Item: Clone + 'static,
+        {
+            type Item = M::Item;
+
+            const ID: u32 = M::ID;
+
+            fn merge(left: &Self::Item, right: &Self::Item) -> Result<Self::Item> {
+                let out = M::merge(left, right)?;
+                Self::log(left, right, &out);
+                Ok(out)
+            }
+
+            fn merge_with_height(
+                left: &Self::Item,
+                right: &Self::Item,
+                height: u8,
+            ) -> Result<Self::Item> {
+                let out = M::merge_with_height(left, right, height)?;
+                Self::log(left, right, &out);
+                Ok(out)
+            }
+
+            fn merge_peaks(peak1: &Self::Item, peak2: &Self::Item) -> Result<Self::Item> {
+                let out = M::merge_peaks(peak1, peak2)?;
+                Self::log(peak1, peak2, &out);
+                Ok(out)
+            }
+
+            fn bag(accumulated: &Self::Item, next: &Self::Item) -> Result<Self::Item> {
+                let out = M::bag(accumulated, next)?;
+                Self::log(accumulated, next, &out);
+                Ok(out)
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Layered verification of proofs for multiple leaves, a reference for verifiers which locate
+//! nodes by k-index rather than by position, e.g. in Solidity.
+//!
+//! The proof is the same as `MMR::gen_proof`, the nodes under a peak are merged layer by
+//! layer from left to right, so the proof items are consumed in the same order.
+
+use crate::helper::{peaks, pos_height_in_tree, position_to_peak_and_k_index};
+use crate::mmr::bagging_peaks_hashes;
+use crate::vec::Vec;
+use crate::{Error, Merge, Result};
+
+/// Verifies the `(position, k-index, leaf)` of leaves against the root, the k-index of each
+/// position is given by `helper::mmr_position_to_k_index`.
+///
+/// The hashing only follows the k-indexes, the positions are checked against them so a leaf
+/// can't be claimed at another position under the same peak.
+///
+/// Returns `Error::NodeProofsNotSupported` if a position isn't a leaf, `Error::PositionOutOfRange`
+/// if it's beyond the mmr, `Error::KIndexMismatch` if a k-index isn't the one of its position,
+/// or the proof item errors of `MerkleProof::verify`.
+pub fn verify_multi<T: Clone + PartialEq, M: Merge<Item = T>>(
+    root: T,
+    mmr_size: u64,
+    mut leaves: Vec<(u64, usize, T)>,
+    proof_items: &[T],
+) -> Result<bool> {
+    if leaves
+        .iter()
+        .any(|(pos, _, _)| pos_height_in_tree(*pos) > 0)
+    {
+        return Err(Error::NodeProofsNotSupported);
+    }
+    leaves.sort_by_key(|(pos, _, _)| *pos);
+    leaves.dedup_by(|a, b| a.0 == b.0);
+    if leaves.is_empty() {
+        return Err(Error::CorruptedProof);
+    }
+    for (pos, k_index, _) in &leaves {
+        match position_to_peak_and_k_index(*pos, mmr_size) {
+            None => return Err(Error::PositionOutOfRange),
+            Some((_, expected, _)) if expected != *k_index => {
+                return Err(Error::KIndexMismatch(*pos))
+            }
+            _ => {}
+        }
+    }
+
+    let mut proof_iter = proof_items.iter();
+    let mut leaves = leaves.into_iter().peekable();
+    let mut peaks_hashes = Vec::new();
+    for (peak_pos, peak_height) in peaks(mmr_size) {
+        let mut layer = Vec::new();
+        while let Some((pos, _, _)) = leaves.peek() {
+            if *pos > peak_pos {
+                break;
+            }
+            let (_, k_index, leaf) = leaves.next().expect("peeked");
+            layer.push((k_index, leaf));
+        }
+        let peak_root = if layer.is_empty() {
+            // a lhs peak, or the bagged rhs peaks after the last peak with leaves
+            match proof_iter.next() {
+                Some(peak_root) => peak_root.clone(),
+                None => break,
+            }
+        } else {
+            calculate_layers::<_, M, _>(layer, peak_height, &mut proof_iter)?
+        };
+        peaks_hashes.push(peak_root);
+    }
+    // ensure nothing left in proof_iter
+    if proof_iter.next().is_some() {
+        return Err(Error::ProofItemsLeftover);
+    }
+    let calculated_root = bagging_peaks_hashes::<T, M>(&peaks_hashes, &mut { usize::MAX })?;
+    Ok(calculated_root == root)
+}
+
+// merge the `(k-index, node)` of a layer into their parents until the peak at `peak_height`
+fn calculate_layers<'a, T: 'a, M: Merge<Item = T>, I: Iterator<Item = &'a T>>(
+    mut layer: Vec<(usize, T)>,
+    peak_height: u8,
+    proof_iter: &mut I,
+) -> Result<T> {
+    for height in 0..peak_height {
+        let mut parents = Vec::with_capacity(layer.len());
+        let mut nodes = layer.into_iter().peekable();
+        while let Some((k_index, node)) = nodes.next() {
+            let parent = if k_index % 2 == 0 {
+                // the right sibling is either the next node of the layer or a proof item
+                match nodes.peek() {
+                    Some((next_k_index, _)) if *next_k_index == k_index + 1 => {
+                        let (_, right) = nodes.next().expect("peeked");
+                        M::merge_with_height(&node, &right, height + 1)?
+                    }
+                    _ => {
+                        let right = proof_iter.next().ok_or(Error::ProofItemsExhausted)?;
+                        M::merge_with_height(&node, right, height + 1)?
+                    }
+                }
+            } else {
+                let sibling = proof_iter.next().ok_or(Error::ProofItemsExhausted)?;
+                M::merge_with_height(sibling, &node, height + 1)?
+            };
+            parents.push((k_index / 2, parent));
+        }
+        layer = parents;
+    }
+    match (layer.pop(), layer.is_empty()) {
+        (Some((0, peak_root)), true) => Ok(peak_root),
+        _ => Err(Error::CorruptedProof),
+    }
+}
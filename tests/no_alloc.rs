@@ -0,0 +1,73 @@
+//! Verifies proofs by the verifiers of `no_alloc` without allocating, built with
+//! `--no-default-features` by `make test-no-std`.
+
+use ckb_merkle_mountain_range::{
+    no_alloc::{verify_leaves_no_alloc, verify_no_alloc},
+    Error, Merge, Result,
+};
+
+type Hash = [u8; 32];
+
+// not a secure hash, only mixes the bytes so the order of the children matters
+struct MixMerge;
+
+impl Merge for MixMerge {
+    type Item = Hash;
+
+    fn merge(left: &Hash, right: &Hash) -> Result<Hash> {
+        let mut out = [0u8; 32];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = left[i].wrapping_mul(3) ^ right[i].rotate_left(1) ^ i as u8;
+        }
+        Ok(out)
+    }
+}
+
+fn merge(left: &Hash, right: &Hash) -> Hash {
+    MixMerge::merge(left, right).unwrap()
+}
+
+//       6
+//     /   \
+//    2     5
+//   / \   / \
+//  0   1 3   4  7
+const MMR_SIZE: u64 = 8;
+
+fn leaf(i: u8) -> Hash {
+    [i; 32]
+}
+
+fn root() -> Hash {
+    let peak = merge(&merge(&leaf(0), &leaf(1)), &merge(&leaf(2), &leaf(3)));
+    // bagging from right to left
+    merge(&leaf(4), &peak)
+}
+
+#[test]
+fn test_verify_no_alloc_of_fixed_proofs() {
+    let peak = merge(&merge(&leaf(0), &leaf(1)), &merge(&leaf(2), &leaf(3)));
+    // the last leaf is a peak, proven by the lhs peak
+    assert_eq!(
+        verify_no_alloc::<_, MixMerge>(&[peak], &root(), MMR_SIZE, 7, leaf(4)),
+        Ok(true)
+    );
+
+    // the leaves at 0 and 4, the siblings of each layer then the rhs peak
+    let items = [leaf(1), leaf(2), leaf(4)];
+    let leaves = [(0, leaf(0)), (4, leaf(3))];
+    assert_eq!(
+        verify_leaves_no_alloc::<3, _, MixMerge>(&items, &root(), MMR_SIZE, &leaves),
+        Ok(true)
+    );
+    let leaves = [(0, leaf(0)), (4, leaf(2))];
+    assert_eq!(
+        verify_leaves_no_alloc::<3, _, MixMerge>(&items, &root(), MMR_SIZE, &leaves),
+        Ok(false)
+    );
+    // the stack can't hold both leaves under the peak
+    assert_eq!(
+        verify_leaves_no_alloc::<1, _, MixMerge>(&items, &root(), MMR_SIZE, &leaves),
+        Err(Error::CorruptedStack)
+    );
+}
@@ -0,0 +1,21 @@
+use ckb_merkle_mountain_range::{
+    typed::LeafIndex,
+    util::{MemMMR, MemStore},
+    Merge, Result,
+};
+
+struct MergeU64;
+
+impl Merge for MergeU64 {
+    type Item = u64;
+    fn merge(lhs: &Self::Item, rhs: &Self::Item) -> Result<Self::Item> {
+        Ok(lhs + rhs)
+    }
+}
+
+fn main() {
+    let store = MemStore::default();
+    let mut mmr = MemMMR::<u64, MergeU64>::new(0, &store);
+    mmr.push(1).unwrap();
+    let _ = mmr.gen_proof_typed(vec![LeafIndex(0)]);
+}